@@ -1,5 +1,5 @@
 import json
-from PyQt5.QtWidgets import QDialog, QVBoxLayout, QDialogButtonBox, QLabel, QDoubleSpinBox, QSlider, QHBoxLayout, QFileDialog
+from PyQt5.QtWidgets import QDialog, QVBoxLayout, QDialogButtonBox, QLabel, QDoubleSpinBox, QSlider, QHBoxLayout, QFileDialog, QComboBox
 from PyQt5.QtCore import Qt
 from PyQt5.QtGui import QIcon
 
@@ -36,6 +36,24 @@ def smooth_request(world_name):
     response = requests.post(BACKEND_ADDRESS + "smooth", data=json_data, headers=headers)
     print(f'Smooth Response status code: {response.status_code}')
 
+def undo_request(world_name):
+    headers = {'Content-Type': 'application/json'}
+    req_data = {
+        "world_name": world_name
+    }
+    json_data = json.dumps(req_data)
+    response = requests.post(BACKEND_ADDRESS + "undo", data=json_data, headers=headers)
+    print(f'Undo Response status code: {response.status_code}')
+
+def redo_request(world_name):
+    headers = {'Content-Type': 'application/json'}
+    req_data = {
+        "world_name": world_name
+    }
+    json_data = json.dumps(req_data)
+    response = requests.post(BACKEND_ADDRESS + "redo", data=json_data, headers=headers)
+    print(f'Redo Response status code: {response.status_code}')
+
 def add_noise_request(world_name):
     headers = {'Content-Type': 'application/json'}
     req_data = {
@@ -152,3 +170,46 @@ class ClimatePopup(OperationDialog):
         self.layout.addWidget(self.buttonBox)
         self.route = "calculate_climate"
 
+
+class BrushPopup(OperationDialog):
+    def __init__(self, main_window):
+        super().__init__(main_window)
+        self.layout.addWidget(QLabel("Operation"))
+        self.operation = QComboBox()
+        self.operation.addItems(["raise", "lower", "flatten", "smooth", "noise"])
+        self.layout.addWidget(self.operation)
+        self.add_float_option("Latitude", -90, 90, 0)
+        self.add_float_option("Longitude", -180, 180, 0)
+        self.add_float_option("Radius", 0, 90, 10)
+        self.add_float_option("Falloff", 0, 90, 3)
+        self.add_float_option("Amount", 0, 5000, 500)
+        self.layout.addWidget(self.buttonBox)
+        self.route = "brush"
+
+    def send_request(self):
+        headers = {'Content-Type': 'application/json'}
+        operation = {"type": self.operation.currentText()}
+        amount = self.options["Amount"].value()
+        if operation["type"] in ["raise", "lower"]:
+            operation["amount"] = int(amount)
+        elif operation["type"] == "noise":
+            operation["frequency"] = 30.0
+            operation["intensity"] = amount
+        req_data = {
+            "world_name": self.main_window.selected_world(),
+            "request_priority": 1,
+            "params": {
+                "area": {
+                    "type": "circle",
+                    "center": [self.options["Latitude"].value(), self.options["Longitude"].value()],
+                    "radius": self.options["Radius"].value()
+                },
+                "operation": operation,
+                "falloff": self.options["Falloff"].value()
+            }
+        }
+        json_data = json.dumps(req_data)
+        response = requests.post(self.main_window.backend_address + self.route, data=json_data, headers=headers)
+        print(f'Brush Response status code: {response.status_code}')
+
+        self.accept()
@@ -34,6 +34,9 @@ def natural_sort(file_paths):
     return sorted(file_paths, key=natural_keys)
 
 
+PREVIEW_WIDTH = 500
+
+
 class GenerationMenu(QDialog):
     image_loaded = pyqtSignal(QPixmap)
     def __init__(self, main_window):
@@ -49,7 +52,7 @@ class GenerationMenu(QDialog):
         self.menu_layout.addRow("Seed", self.seed_input)
 
         self.shape_combobox = QComboBox()
-        self.shape_combobox.addItems(["Globe", "Cylinder", "Flat"])
+        self.shape_combobox.addItems(["Globe", "Cylinder", "Flat", "CubeSphere", "Torus", "Disc"])
         self.menu_layout.addRow("Shape", self.shape_combobox)
         self.options = {}
 
@@ -59,6 +62,8 @@ class GenerationMenu(QDialog):
 
         self.add_resolution_option()
 
+        self.add_sketch_option()
+
         self.erosion_input = QSpinBox()
         self.erosion_input.setValue(4)
         self.menu_layout.addRow("Erosion Iterations", self.erosion_input)
@@ -74,8 +79,13 @@ class GenerationMenu(QDialog):
         self.menu_layout.addWidget(self.hotspots_checkbox)
         self.generation_button = QPushButton("Generate Map", self)
         self.generation_button.setCursor(QCursor(Qt.PointingHandCursor))
-        self.generation_button.clicked.connect(self.send_request)
+        self.generation_button.clicked.connect(lambda : self.send_request())
         self.menu_layout.addWidget(self.generation_button)
+        # previews show the same world as the full resolution, with less detail
+        self.preview_button = QPushButton("Generate Preview", self)
+        self.preview_button.setCursor(QCursor(Qt.PointingHandCursor))
+        self.preview_button.clicked.connect(lambda : self.send_request(preview=True))
+        self.menu_layout.addWidget(self.preview_button)
 
         self.setLayout(self.menu_layout)
 
@@ -164,7 +174,22 @@ class GenerationMenu(QDialog):
         layout_h.addWidget(self.height_input)
         self.menu_layout.addRow("Resolution", self.resolution_widget)
 
-    def prepare_config(self):
+    def add_sketch_option(self):
+        self.sketch_file = None
+        self.sketch_button = QPushButton("None", self)
+        self.sketch_button.clicked.connect(self.select_sketch)
+        self.menu_layout.addRow("Sketch", self.sketch_button)
+
+    def select_sketch(self):
+        file_dialog = QFileDialog(self)
+        file_dialog.setNameFilter("PNG (*.png)");
+        if file_dialog.exec():
+            self.sketch_file = file_dialog.selectedFiles()[0]
+        else:
+            self.sketch_file = None
+        self.sketch_button.setText(self.sketch_file if self.sketch_file else "None")
+
+    def prepare_config(self, preview=False):
         config = {
             "shape": "Globe",
             "seed": 1,
@@ -187,6 +212,12 @@ class GenerationMenu(QDialog):
         config["make_climate"] = self.climate_checkbox.isChecked()
         config["erosion_iterations"] = self.erosion_input.value()
         config["hotspots"] = 50 if self.hotspots_checkbox.isChecked() else 0
+        if self.sketch_file:
+            config["sketch"] = self.sketch_file
+        if preview:
+            scale = PREVIEW_WIDTH / config["width_pixels"]
+            config["width_pixels"] = PREVIEW_WIDTH
+            config["height_pixels"] = max(1, round(config["height_pixels"] * scale))
 
         req_data = {
             "world_name": self.seed_input.text(),
@@ -195,8 +226,8 @@ class GenerationMenu(QDialog):
         json_data = json.dumps(req_data)
         return json_data
 
-    def send_request_thread(self):
-        json_data = self.prepare_config()
+    def send_request_thread(self, preview):
+        json_data = self.prepare_config(preview)
         headers = {'Content-Type': 'application/json'}
         try:
             response = requests.post(self.main_window.backend_address + "generate", data=json_data, headers=headers)
@@ -235,11 +266,11 @@ class GenerationMenu(QDialog):
             count += 1
         print("count:", count)
     
-    def send_request(self):
+    def send_request(self, preview=False):
         self.main_window.new_tab(self.seed_input.text())
         self.done = False
         self.image_loaded.connect(lambda : self.main_window.tabs.currentWidget().display_image())
-        t1 = Thread(target=self.send_request_thread, args=[])
+        t1 = Thread(target=self.send_request_thread, args=[preview])
         t2 = Thread(target=self.display_pipeline_images_thread, args=[])
         t2.start()
         t1.start()
@@ -1,6 +1,6 @@
 from PyQt5.QtWidgets import QAction, QMenuBar, QFileDialog
 from PIL import Image
-from edit_requests import ClimatePopup, ResizePopup, WaterLevelPopup, add_noise_request, erosion_request, smooth_request, translation_noise_request, custom_layer_request
+from edit_requests import BrushPopup, ClimatePopup, ResizePopup, WaterLevelPopup, add_noise_request, erosion_request, smooth_request, translation_noise_request, custom_layer_request, undo_request, redo_request
 from generation_menu import GenerationMenu
 from new_view_menu import NewViewMenu
 
@@ -33,6 +33,14 @@ class TopMenuBar(QMenuBar):
         add_erosion_action.triggered.connect(lambda : erosion_request(self.main_window.selected_world()))
         self.edit_menu.addAction(add_erosion_action)
 
+        undo_action = QAction("Undo", self.main_window)
+        undo_action.triggered.connect(lambda : undo_request(self.main_window.selected_world()))
+        self.edit_menu.addAction(undo_action)
+
+        redo_action = QAction("Redo", self.main_window)
+        redo_action.triggered.connect(lambda : redo_request(self.main_window.selected_world()))
+        self.edit_menu.addAction(redo_action)
+
         add_noise_action = QAction("Add Noise", self.main_window)
         add_noise_action.triggered.connect(lambda : add_noise_request(self.main_window.selected_world()))
         self.edit_menu.addAction(add_noise_action)
@@ -49,6 +57,10 @@ class TopMenuBar(QMenuBar):
         smooth_action.triggered.connect(lambda : smooth_request(self.main_window.selected_world()))
         self.edit_menu.addAction(smooth_action)
 
+        brush_action = QAction("Brush", self.main_window)
+        brush_action.triggered.connect(lambda : BrushPopup(self.main_window).exec())
+        self.edit_menu.addAction(brush_action)
+
         resize_action = QAction("Resize", self.main_window)
         resize_action.triggered.connect(lambda : ResizePopup(self.main_window).exec())
         self.edit_menu.addAction(resize_action)
@@ -0,0 +1,159 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::de::DeserializeOwned;
+
+use crate::{complete_map::CompleteMap, error::MapResult, seed::mix, shapes::map_shape::MapShape};
+
+// Maps left by pipelines after their steps, named by the hash of the map size, the shape
+// and the keys of all the steps up to that point. Step keys only describe the parameters
+// of the steps, so the checkpoints must be removed when the code of a step changes.
+pub struct CheckpointCache {
+    dir: PathBuf,
+    // steps faster than this, in seconds, are run again rather than stored
+    pub min_step_duration: f32,
+    // the least recently used checkpoints are removed beyond this number
+    pub max_checkpoints: usize,
+}
+
+impl CheckpointCache {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            min_step_duration: 1.0,
+            max_checkpoints: 8,
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        return self.dir.join(format!("{:016x}.bin", key));
+    }
+
+    pub fn load<S: MapShape + DeserializeOwned>(&self, key: u64) -> Option<CompleteMap<S>> {
+        let path = self.path(key);
+        let file = File::open(&path).ok()?;
+        let cmap = bincode::deserialize_from(DeflateDecoder::new(BufReader::new(file))).ok()?;
+        // keeps the checkpoint from being pruned as the least recently used
+        let _ = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(std::time::SystemTime::now()));
+        return Some(cmap);
+    }
+
+    pub fn save<S: MapShape>(&self, key: u64, cmap: &CompleteMap<S>) -> MapResult<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let temporary = path.with_extension("tmp");
+        let mut encoder = DeflateEncoder::new(
+            BufWriter::new(File::create(&temporary)?),
+            Compression::fast(),
+        );
+        bincode::serialize_into(&mut encoder, cmap)?;
+        encoder.finish()?.flush()?;
+        fs::rename(&temporary, &path)?;
+        self.prune()
+    }
+
+    fn prune(&self) -> MapResult<()> {
+        let mut checkpoints = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.path().extension().and_then(|e| e.to_str()) == Some("bin") {
+                checkpoints.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        if checkpoints.len() <= self.max_checkpoints {
+            return Ok(());
+        }
+        checkpoints.sort();
+        for (_, path) in &checkpoints[..checkpoints.len() - self.max_checkpoints] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+// FNV-1a, mixed, so that the keys are the same on every platform and release of Rust
+fn hash_bytes(state: u64, bytes: &[u8]) -> u64 {
+    let mut hash = state ^ 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    return mix(hash);
+}
+
+// Key of the map after each step, for the steps up to the first one without a key.
+pub fn prefix_keys<S: MapShape>(
+    circunference: usize,
+    height: usize,
+    step_keys: &[Option<String>],
+) -> Vec<u64> {
+    let base = format!(
+        "{} {} {}x{}",
+        env!("CARGO_PKG_VERSION"),
+        std::any::type_name::<S>(),
+        circunference,
+        height
+    );
+    let mut state = hash_bytes(0, base.as_bytes());
+    let mut keys = vec![];
+    for key in step_keys {
+        let Some(key) = key else {
+            break;
+        };
+        state = hash_bytes(state, key.as_bytes());
+        keys.push(state);
+    }
+    return keys;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::{flat::Flat, globe::Globe};
+
+    fn keys(steps: &[Option<&str>]) -> Vec<u64> {
+        let steps: Vec<Option<String>> = steps.iter().map(|key| key.map(String::from)).collect();
+        return prefix_keys::<Globe>(100, 50, &steps);
+    }
+
+    #[test]
+    fn keys_depend_on_every_step_before() {
+        let ab = keys(&[Some("a"), Some("b")]);
+        assert_eq!(ab.len(), 2);
+        assert_eq!(keys(&[Some("a"), Some("b")]), ab);
+        assert_eq!(keys(&[Some("a"), Some("c")])[0], ab[0]);
+        assert_ne!(keys(&[Some("a"), Some("c")])[1], ab[1]);
+        assert_ne!(keys(&[Some("b"), Some("b")])[1], ab[1]);
+        // steps without a key end the steps that can be cached
+        assert_eq!(keys(&[Some("a"), None, Some("b")]), vec![ab[0]]);
+        // and so do the shape and the size of the map
+        let a = [Some("a".to_string())];
+        assert_ne!(prefix_keys::<Flat>(100, 50, &a)[0], ab[0]);
+        assert_ne!(prefix_keys::<Globe>(100, 51, &a)[0], ab[0]);
+    }
+
+    #[test]
+    fn least_recently_used_checkpoints_are_removed() {
+        let dir = std::env::temp_dir().join(format!("checkpoints_{}", std::process::id()));
+        let mut cache = CheckpointCache::new(dir.to_str().unwrap());
+        cache.max_checkpoints = 2;
+        let mut cmap: CompleteMap<Flat> = CompleteMap::new(4, 2);
+        cmap.height.values[1][3] = 42;
+        for key in 0..3 {
+            cache.save(key, &cmap).unwrap();
+            // modification times must differ for the order to be known
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert!(cache.load::<Flat>(0).is_none());
+        let loaded = cache.load::<Flat>(2).unwrap();
+        assert_eq!(loaded.height.values, cmap.height.values);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
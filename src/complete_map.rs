@@ -1,18 +1,19 @@
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::{Read, Write},
-};
+use std::collections::HashMap;
 
-use bincode::Error;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
 use crate::{
+    configuration::ShapeEnum,
+    error::{MapError, MapResult},
+    map_file::layers::{copy_layer, swap_layer, LayerId},
     map_view::color_scheme::CategoryColorScheme,
     partial_map::PartialMap,
     pipeline_steps::{climate::Climate, rivers::River},
-    shapes::map_shape::MapShape,
+    shapes::{
+        cube_sphere::CubeSphere, cylinder::Cylinder, disc::Disc, flat::Flat, globe::Globe,
+        map_shape::MapShape, torus::Torus,
+    },
 };
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -75,20 +76,103 @@ impl<S: MapShape> CompleteMap<S> {
         }
     }
 
-    pub fn save(&self, filename: &str) -> Result<(), Error> {
-        let serialized = bincode::serialize(&self).unwrap();
-        let mut file = File::create(filename)?;
-        file.write_all(&serialized)?;
+    pub fn require_climate(&self) -> MapResult<()> {
+        self.require_temperature()?;
+        self.require_precipitation()
+    }
+
+    pub fn require_temperature(&self) -> MapResult<()> {
+        if self.temperature.is_empty() {
+            return Err(MapError::MissingLayer("temperature".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn require_precipitation(&self) -> MapResult<()> {
+        if self.precipitation.is_empty() {
+            return Err(MapError::MissingLayer("precipitation".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn require_annual_precipitation(&self) -> MapResult<()> {
+        if self.annual_precipitation.values.is_empty() {
+            return Err(MapError::MissingLayer("annual precipitation".to_string()));
+        }
         Ok(())
     }
 }
 
-pub fn load<S: MapShape + for<'a> Deserialize<'a>>(filename: &str) -> CompleteMap<S> {
-    let mut file = File::open(filename).unwrap();
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).unwrap();
+#[derive(Clone)]
+pub enum CompleteMapEnum {
+    Cylinder(CompleteMap<Cylinder>),
+    CubeSphere(CompleteMap<CubeSphere>),
+    Globe(CompleteMap<Globe>),
+    Flat(CompleteMap<Flat>),
+    Torus(CompleteMap<Torus>),
+    Disc(CompleteMap<Disc>),
+}
 
-    let deserialized: CompleteMap<S> = bincode::deserialize(&buffer).unwrap();
+impl CompleteMapEnum {
+    pub fn shape(&self) -> ShapeEnum {
+        match self {
+            CompleteMapEnum::Cylinder(_) => ShapeEnum::Cylinder,
+            CompleteMapEnum::CubeSphere(_) => ShapeEnum::CubeSphere,
+            CompleteMapEnum::Globe(_) => ShapeEnum::Globe,
+            CompleteMapEnum::Flat(_) => ShapeEnum::Flat,
+            CompleteMapEnum::Torus(_) => ShapeEnum::Torus,
+            CompleteMapEnum::Disc(_) => ShapeEnum::Disc,
+        }
+    }
+
+    pub fn dimensions(&self) -> [usize; 2] {
+        match self {
+            CompleteMapEnum::Cylinder(cmap) => [cmap.height.circunference, cmap.height.height],
+            CompleteMapEnum::CubeSphere(cmap) => [cmap.height.circunference, cmap.height.height],
+            CompleteMapEnum::Globe(cmap) => [cmap.height.circunference, cmap.height.height],
+            CompleteMapEnum::Flat(cmap) => [cmap.height.circunference, cmap.height.height],
+            CompleteMapEnum::Torus(cmap) => [cmap.height.circunference, cmap.height.height],
+            CompleteMapEnum::Disc(cmap) => [cmap.height.circunference, cmap.height.height],
+        }
+    }
+
+    // A map of the same shape with only the given layers of this one
+    #[allow(dead_code)]
+    pub fn copy_layers(&self, layers: &[LayerId]) -> CompleteMapEnum {
+        fn copy<S: MapShape>(from: &CompleteMap<S>, layers: &[LayerId]) -> CompleteMap<S> {
+            let mut copy = CompleteMap::new(0, 0);
+            for layer in layers {
+                copy_layer(from, &mut copy, *layer);
+            }
+            return copy;
+        }
+        match self {
+            CompleteMapEnum::Cylinder(cmap) => CompleteMapEnum::Cylinder(copy(cmap, layers)),
+            CompleteMapEnum::CubeSphere(cmap) => CompleteMapEnum::CubeSphere(copy(cmap, layers)),
+            CompleteMapEnum::Globe(cmap) => CompleteMapEnum::Globe(copy(cmap, layers)),
+            CompleteMapEnum::Flat(cmap) => CompleteMapEnum::Flat(copy(cmap, layers)),
+            CompleteMapEnum::Torus(cmap) => CompleteMapEnum::Torus(copy(cmap, layers)),
+            CompleteMapEnum::Disc(cmap) => CompleteMapEnum::Disc(copy(cmap, layers)),
+        }
+    }
 
-    return deserialized;
+    // Exchanges the given layers with those of a map of the same shape, e.g. one made by
+    // copy_layers. Maps of different shapes are left as they are.
+    #[allow(dead_code)]
+    pub fn swap_layers(&mut self, other: &mut CompleteMapEnum, layers: &[LayerId]) {
+        fn swap<S: MapShape>(a: &mut CompleteMap<S>, b: &mut CompleteMap<S>, layers: &[LayerId]) {
+            for layer in layers {
+                swap_layer(a, b, *layer);
+            }
+        }
+        match (self, other) {
+            (CompleteMapEnum::Cylinder(a), CompleteMapEnum::Cylinder(b)) => swap(a, b, layers),
+            (CompleteMapEnum::CubeSphere(a), CompleteMapEnum::CubeSphere(b)) => swap(a, b, layers),
+            (CompleteMapEnum::Globe(a), CompleteMapEnum::Globe(b)) => swap(a, b, layers),
+            (CompleteMapEnum::Flat(a), CompleteMapEnum::Flat(b)) => swap(a, b, layers),
+            (CompleteMapEnum::Torus(a), CompleteMapEnum::Torus(b)) => swap(a, b, layers),
+            (CompleteMapEnum::Disc(a), CompleteMapEnum::Disc(b)) => swap(a, b, layers),
+            _ => {}
+        }
+    }
 }
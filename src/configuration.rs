@@ -1,13 +1,23 @@
+use std::fs;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::error::{MapError, MapResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ShapeEnum {
     Globe,
     Cylinder,
     Flat,
+    // cube-sphere grid, with cells of nearly the same area all over the sphere
+    CubeSphere,
+    // flat map wrapping around in both directions
+    Torus,
+    // round flat world surrounded by ocean
+    Disc,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct Configuration {
     pub shape: ShapeEnum,
@@ -25,10 +35,73 @@ pub struct Configuration {
     pub erosion_iterations: u32,
     pub supercontinent: bool,
     pub islands: f32,
+    #[serde(default)]
+    pub recipe: Option<String>,
+    // painted image of where land, sea, mountains and lowlands go (see pipeline_steps::sketch),
+    // followed by the standard recipe, which then builds on the sketch, unless a recipe is given
+    #[serde(default)]
+    pub sketch: Option<String>,
+    // directory where the maps after slow steps are kept, so that generating again
+    // with changes only in the last steps does not run the first ones
+    #[serde(default)]
+    pub checkpoint_dir: Option<String>,
+    #[serde(default)]
+    pub log_step_timings: bool,
+    // directory for an image of the height after every step
+    #[serde(default)]
+    pub snapshot_dir: Option<String>,
+    // JSON file with the duration of every step and statistics of the map after it
+    #[serde(default)]
+    pub stats_file: Option<String>,
     // pub height_source_img: String
 }
 
-#[derive(Debug, Deserialize)]
+impl Configuration {
+    pub fn from_file(filename: &str) -> MapResult<Self> {
+        let data = fs::read_to_string(filename)
+            .map_err(|e| MapError::Config(format!("could not read {}: {}", filename, e)))?;
+        let config: Configuration = serde_json::from_str(&data)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> MapResult<()> {
+        if self.width_pixels == 0 || self.height_pixels == 0 {
+            return Err(MapError::Config(format!(
+                "map size must be positive, got {}x{}",
+                self.width_pixels, self.height_pixels
+            )));
+        }
+        if self.number_of_plates == 0 {
+            return Err(MapError::Config(
+                "number_of_plates must be positive".to_string(),
+            ));
+        }
+        if !(0.0..=100.0).contains(&self.water_percentage) {
+            return Err(MapError::Config(format!(
+                "water_percentage must be between 0 and 100, got {}",
+                self.water_percentage
+            )));
+        }
+        for (name, percentiles) in [
+            ("land_height_percentiles", &self.land_height_percentiles),
+            ("ocean_depth_percentiles", &self.ocean_depth_percentiles),
+        ] {
+            if percentiles.is_empty() {
+                return Err(MapError::Config(format!("{} must not be empty", name)));
+            }
+            if let Some((p, _)) = percentiles.iter().find(|(p, _)| !(0.0..=100.0).contains(p)) {
+                return Err(MapError::Config(format!(
+                    "{} contains percentile {} outside of 0..100",
+                    name, p
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct ClimateConfiguration {
     pub pole_temperature: f32,
@@ -0,0 +1,70 @@
+use std::{error::Error, fmt, io};
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Image(image::ImageError),
+    Serialization(String),
+    Config(String),
+    Recipe(String),
+    InvalidData(String),
+    MissingLayer(String),
+    Step { index: usize, source: Box<MapError> },
+    Cancelled,
+}
+
+pub type MapResult<T> = Result<T, MapError>;
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "io error: {}", e),
+            MapError::Image(e) => write!(f, "image error: {}", e),
+            MapError::Serialization(message) => write!(f, "serialization error: {}", message),
+            MapError::Config(message) => write!(f, "invalid configuration: {}", message),
+            MapError::Recipe(message) => write!(f, "invalid recipe: {}", message),
+            MapError::InvalidData(message) => write!(f, "invalid data: {}", message),
+            MapError::MissingLayer(layer) => write!(f, "missing layer: {}", layer),
+            MapError::Step { index, source } => write!(f, "pipeline step {}: {}", index, source),
+            MapError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl Error for MapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MapError::Io(e) => Some(e),
+            MapError::Image(e) => Some(e),
+            MapError::Step { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MapError {
+    fn from(e: io::Error) -> Self {
+        MapError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for MapError {
+    fn from(e: image::ImageError) -> Self {
+        MapError::Image(e)
+    }
+}
+
+impl From<bincode::Error> for MapError {
+    fn from(e: bincode::Error) -> Self {
+        match *e {
+            bincode::ErrorKind::Io(e) => MapError::Io(e),
+            e => MapError::Serialization(e.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(e: serde_json::Error) -> Self {
+        MapError::Serialization(e.to_string())
+    }
+}
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, fs};
+
+use serde_json::{json, Value};
+
+use crate::{
+    complete_map::CompleteMap, error::MapResult, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+// Corner of a pixel on the circunference x height grid, as [row, column].
+pub type Corner = [usize; 2];
+
+fn round_degrees(value: f32) -> f64 {
+    return (value as f64 * 1e5).round() / 1e5;
+}
+
+// GeoJSON positions are [longitude, latitude].
+fn position(latitude: f32, longitude: f32) -> Value {
+    return json!([round_degrees(longitude), round_degrees(latitude)]);
+}
+
+fn feature(geometry: Value, properties: Value) -> Value {
+    return json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": properties,
+    });
+}
+
+// Lines between pixels of `cells` with different values, grouped by the pair of values
+// they separate. `cells` holds `width` x `height` values row by row, starting from latitude -90.
+// Lines are traced along pixel edges, except the edges on the ±180 meridian.
+pub fn trace_boundaries<T: Copy + Eq + Ord + std::hash::Hash>(
+    width: usize,
+    height: usize,
+    cells: &[T],
+) -> Vec<((T, T), Vec<Corner>)> {
+    let mut segments: HashMap<(T, T), Vec<[Corner; 2]>> = HashMap::new();
+    for row in 0..height {
+        for column in 0..width {
+            let value = cells[row * width + column];
+            if column + 1 < width {
+                let right = cells[row * width + column + 1];
+                if value != right {
+                    segments
+                        .entry((value.min(right), value.max(right)))
+                        .or_default()
+                        .push([[row, column + 1], [row + 1, column + 1]]);
+                }
+            }
+            if row + 1 < height {
+                let up = cells[(row + 1) * width + column];
+                if value != up {
+                    segments
+                        .entry((value.min(up), value.max(up)))
+                        .or_default()
+                        .push([[row + 1, column], [row + 1, column + 1]]);
+                }
+            }
+        }
+    }
+    let mut keys: Vec<(T, T)> = segments.keys().copied().collect();
+    keys.sort();
+    let mut lines = vec![];
+    for key in keys {
+        for line in chain_segments(&segments[&key]) {
+            lines.push((key, line));
+        }
+    }
+    return lines;
+}
+
+// Joins segments sharing their ends into polylines.
+fn chain_segments(segments: &[[Corner; 2]]) -> Vec<Vec<Corner>> {
+    let mut by_corner: HashMap<Corner, Vec<usize>> = HashMap::new();
+    for (i, [a, b]) in segments.iter().enumerate() {
+        by_corner.entry(*a).or_default().push(i);
+        by_corner.entry(*b).or_default().push(i);
+    }
+    let mut used = vec![false; segments.len()];
+    let next_segment = |corner: Corner, used: &mut Vec<bool>| -> Option<Corner> {
+        let i = *by_corner.get(&corner)?.iter().find(|i| !used[**i])?;
+        used[i] = true;
+        let [a, b] = segments[i];
+        return Some(if a == corner { b } else { a });
+    };
+
+    let mut lines = vec![];
+    for i in 0..segments.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        let [a, b] = segments[i];
+        let mut forward = vec![a, b];
+        while let Some(corner) = next_segment(*forward.last().unwrap(), &mut used) {
+            forward.push(corner);
+        }
+        let mut backward = vec![];
+        while let Some(corner) = next_segment(*backward.last().unwrap_or(&a), &mut used) {
+            backward.push(corner);
+        }
+        backward.reverse();
+        backward.extend(forward);
+        lines.push(backward);
+    }
+    return lines;
+}
+
+fn corner_position(corner: Corner, width: usize, height: usize) -> Value {
+    let latitude = corner[0] as f32 * 180.0 / height as f32 - 90.0;
+    let longitude = corner[1] as f32 * 360.0 / width as f32 - 180.0;
+    return position(latitude, longitude);
+}
+
+fn line_geometry(line: &[Corner], width: usize, height: usize) -> Value {
+    let coordinates: Vec<Value> = line
+        .iter()
+        .map(|corner| corner_position(*corner, width, height))
+        .collect();
+    return json!({ "type": "LineString", "coordinates": coordinates });
+}
+
+fn river_features<S: MapShape>(cmap: &CompleteMap<S>) -> Vec<Value> {
+    let mut features = vec![];
+    for (i, river) in cmap.rivers.iter().enumerate() {
+        if river.len() < 2 {
+            continue;
+        }
+        let mut coordinates = vec![];
+        let mut volumes = vec![];
+        for point in river {
+            let [x, y] = point.position;
+            let [latitude, longitude] = cmap.height.cell_center(x, y);
+            coordinates.push(position(latitude, longitude));
+            volumes.push(point.volume);
+        }
+        features.push(feature(
+            json!({ "type": "LineString", "coordinates": coordinates }),
+            json!({
+                "kind": "river",
+                "id": i,
+                "volume": volumes.iter().max(),
+                "volumes": volumes,
+            }),
+        ));
+    }
+    return features;
+}
+
+fn point_features(points: &[[f32; 2]], kind: &str) -> Vec<Value> {
+    return points
+        .iter()
+        .map(|[latitude, longitude]| {
+            feature(
+                json!({ "type": "Point", "coordinates": position(*latitude, *longitude) }),
+                json!({ "kind": kind }),
+            )
+        })
+        .collect();
+}
+
+// Rivers as lines with their volume, coastlines and plate boundaries traced along
+// pixel edges, and hotspots and mountain chains as points, in WGS84 lat/long.
+pub fn map_to_geojson<S: MapShape>(cmap: &CompleteMap<S>) -> Value {
+    let width = cmap.height.circunference;
+    let height = cmap.height.values.len();
+    let mut features = river_features(cmap);
+
+    let land: Vec<bool> = cmap.height.to_grid().iter().map(|h| *h > 0.0).collect();
+    for (_, line) in trace_boundaries(width, height, &land) {
+        let closed = line.first() == line.last();
+        features.push(feature(
+            line_geometry(&line, width, height),
+            json!({ "kind": "coastline", "closed": closed }),
+        ));
+    }
+
+    let plates = plate_grid(cmap);
+    if !plates.is_empty() {
+        for ((plate1, plate2), line) in trace_boundaries(width, height, &plates) {
+            features.push(feature(
+                line_geometry(&line, width, height),
+                json!({ "kind": "plate_boundary", "plates": [plate1, plate2] }),
+            ));
+        }
+    }
+
+    features.extend(point_features(&cmap.hotspots, "hotspot"));
+    features.extend(point_features(&cmap.mountain_chains, "mountain_chain"));
+    features.extend(point_features(&cmap.andean_chains, "andean_chain"));
+    features.extend(point_features(&cmap.hymalayan_chains, "himalayan_chain"));
+    features.extend(point_features(&cmap.trenches, "trench"));
+
+    return json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+}
+
+// Plates on the same grid as the height, empty when plates were not generated.
+fn plate_grid<S: MapShape>(cmap: &CompleteMap<S>) -> Vec<usize> {
+    let plates = &cmap.tectonic_plates;
+    if plates.values.len() != cmap.height.values.len() || cmap.tectonic_plates_directions.is_empty()
+    {
+        return vec![];
+    }
+    return layer_grid(plates, cmap.height.circunference);
+}
+
+// Values of a layer on a grid `width` pixels wide, row by row. Rows shorter than
+// `width`, as in globes, are stretched.
+pub fn layer_grid<S: MapShape, T: Copy>(pmap: &PartialMap<S, T>, width: usize) -> Vec<T> {
+    let mut grid = Vec::with_capacity(width * pmap.values.len());
+    for row in &pmap.values {
+        for column in 0..width {
+            let y = (column * row.len() / width).min(row.len() - 1);
+            grid.push(row[y]);
+        }
+    }
+    return grid;
+}
+
+pub fn save_geojson<S: MapShape>(cmap: &CompleteMap<S>, filename: &str) -> MapResult<()> {
+    fs::write(filename, serde_json::to_vec(&map_to_geojson(cmap))?)?;
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use image::{ImageBuffer, Luma};
+
+use crate::{
+    complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    geotiff::write_geotiff,
+    partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+// Layers of CompleteMap holding one number per pixel, which can be exported as heightmaps.
+pub const EXPORTABLE_LAYERS: [&str; 5] = [
+    "height",
+    "annual_precipitation",
+    "continentality",
+    "fresh_water",
+    "vegetation_density",
+];
+
+pub trait ElevationValue: Copy {
+    fn to_f32(self) -> f32;
+}
+
+impl ElevationValue for i32 {
+    fn to_f32(self) -> f32 {
+        return self as f32;
+    }
+}
+
+impl ElevationValue for f32 {
+    fn to_f32(self) -> f32 {
+        return self;
+    }
+}
+
+// Every format is written north up, the first row at latitude 90
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeightmapFormat {
+    // 16-bit grayscale png, scaled between the minimum and maximum values
+    Png16,
+    // little endian int16 values, clamped to the i16 range
+    R16,
+    // little endian float32 values
+    Raw,
+    // float32 values with an equirectangular WGS84 georeference
+    GeoTiff,
+}
+
+impl HeightmapFormat {
+    pub fn from_path(filename: &str) -> MapResult<Self> {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(HeightmapFormat::Png16),
+            Some("r16") => Ok(HeightmapFormat::R16),
+            Some("raw") | Some("f32") => Ok(HeightmapFormat::Raw),
+            Some("tif") | Some("tiff") => Ok(HeightmapFormat::GeoTiff),
+            _ => Err(MapError::InvalidData(format!(
+                "unknown heightmap format for {}, use .png, .r16, .raw or .tif",
+                filename
+            ))),
+        }
+    }
+}
+
+impl<S: MapShape, T: Clone + ElevationValue> PartialMap<S, T> {
+    // Values on a circunference x height grid, row by row in the same order as save_as_img.
+    // Rows shorter than the circunference, as in globes, are stretched. Shapes whose rows
+    // are not bands of latitude are sampled at the center of every pixel of the grid.
+    pub fn to_grid(&self) -> Vec<f32> {
+        let mut grid = Vec::with_capacity(self.circunference * self.height);
+        if !S::LAT_LON_GRID {
+            for img_x in 0..self.height {
+                for img_y in 0..self.circunference {
+                    let latitude = (img_x as f32 + 0.5) * 180.0 / self.height as f32 - 90.0;
+                    let longitude =
+                        (img_y as f32 + 0.5) * 360.0 / self.circunference as f32 - 180.0;
+                    grid.push(self.get(latitude, longitude).to_f32());
+                }
+            }
+            return grid;
+        }
+        for row in &self.values {
+            for img_y in 0..self.circunference {
+                let y = (img_y * row.len() / self.circunference).min(row.len() - 1);
+                grid.push(row[y].to_f32());
+            }
+        }
+        return grid;
+    }
+
+    // The rows of to_grid in reverse, to get the north up orientation image viewers, game
+    // engines and GIS tools expect
+    pub fn north_up_grid(&self) -> Vec<f32> {
+        return self
+            .to_grid()
+            .chunks(self.circunference.max(1))
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+    }
+
+    pub fn value_range(&self) -> [f32; 2] {
+        let mut range = [f32::MAX, f32::MIN];
+        for value in self.values.iter().flatten() {
+            range[0] = range[0].min(value.to_f32());
+            range[1] = range[1].max(value.to_f32());
+        }
+        return range;
+    }
+
+    pub fn save_as_png16(&self, filename: &str, min_value: f32, max_value: f32) -> MapResult<()> {
+        let scale = u16::MAX as f32 / (max_value - min_value).max(f32::EPSILON);
+        let pixels = self
+            .north_up_grid()
+            .iter()
+            .map(|value| {
+                ((value - min_value) * scale)
+                    .round()
+                    .clamp(0.0, u16::MAX as f32) as u16
+            })
+            .collect();
+        let imgbuffer: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_raw(self.circunference as u32, self.height as u32, pixels).ok_or(
+                MapError::InvalidData(format!("layer does not fit in {}", filename)),
+            )?;
+        imgbuffer.save(filename)?;
+        Ok(())
+    }
+
+    pub fn save_as_r16(&self, filename: &str) -> MapResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        for value in self.north_up_grid() {
+            let value = value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn save_as_raw(&self, filename: &str) -> MapResult<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        for value in self.north_up_grid() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn save_as_geotiff(&self, filename: &str) -> MapResult<()> {
+        write_geotiff(
+            filename,
+            self.circunference,
+            self.height,
+            &self.north_up_grid(),
+            [-180.0, -90.0, 180.0, 90.0],
+        )
+    }
+
+    // Picks the format from the file extension. png files are scaled to the range of the layer.
+    pub fn export_heightmap(&self, filename: &str) -> MapResult<HeightmapFormat> {
+        let format = HeightmapFormat::from_path(filename)?;
+        match format {
+            HeightmapFormat::Png16 => {
+                let [min_value, max_value] = self.value_range();
+                self.save_as_png16(filename, min_value, max_value)?
+            }
+            HeightmapFormat::R16 => self.save_as_r16(filename)?,
+            HeightmapFormat::Raw => self.save_as_raw(filename)?,
+            HeightmapFormat::GeoTiff => self.save_as_geotiff(filename)?,
+        }
+        Ok(format)
+    }
+}
+
+pub fn export_layer<S: MapShape>(
+    cmap: &CompleteMap<S>,
+    layer: &str,
+    filename: &str,
+) -> MapResult<HeightmapFormat> {
+    match layer {
+        "height" => cmap.height.export_heightmap(filename),
+        "annual_precipitation" => cmap.annual_precipitation.export_heightmap(filename),
+        "continentality" => cmap.continentality.export_heightmap(filename),
+        "fresh_water" => cmap.fresh_water.export_heightmap(filename),
+        "vegetation_density" => cmap.vegetation_density.export_heightmap(filename),
+        _ => Err(MapError::InvalidData(format!(
+            "layer {} cannot be exported, expected one of {}",
+            layer,
+            EXPORTABLE_LAYERS.join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{import::heightmap::load_heightmap, shapes::flat::Flat};
+
+    // Heights increasing from south to north and from west to east
+    fn test_layer() -> PartialMap<Flat, i32> {
+        let mut layer = PartialMap::new(8, 4);
+        for (x, row) in layer.values.iter_mut().enumerate() {
+            for (y, value) in row.iter_mut().enumerate() {
+                *value = 100 * x as i32 + y as i32 - 150;
+            }
+        }
+        return layer;
+    }
+
+    #[test]
+    fn grids_are_north_up() {
+        let layer = test_layer();
+        let grid = layer.north_up_grid();
+        assert_eq!(grid[0], layer.values[3][0] as f32);
+        assert_eq!(grid[grid.len() - 1], layer.values[0][7] as f32);
+    }
+
+    #[test]
+    fn exported_heightmaps_are_read_back() {
+        let layer = test_layer();
+        let dir = std::env::temp_dir().join(format!("heightmap_export_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for extension in ["r16", "raw", "tif"] {
+            let path = dir.join(format!("height.{}", extension));
+            let path = path.to_str().unwrap();
+            layer.export_heightmap(path).unwrap();
+            let read: PartialMap<Flat, i32> = load_heightmap(path, None, Some(8)).unwrap();
+            assert_eq!(read.values, layer.values, "{}", extension);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+};
+
+use flate2::read::ZlibDecoder;
+
+use crate::error::{MapError, MapResult};
+
+// Baseline TIFF tags
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PREDICTOR: u16 = 317;
+const TILE_WIDTH: u16 = 322;
+const SAMPLE_FORMAT: u16 = 339;
+// GeoTIFF tags
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+
+const TYPE_BYTE: u16 = 1;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_DOUBLE: u16 = 12;
+
+const COMPRESSION_NONE: u64 = 1;
+const COMPRESSION_DEFLATE: u64 = 8;
+const COMPRESSION_DEFLATE_OLD: u64 = 32946;
+
+// GeoKeys: geographic model, pixels cover an area, WGS84 lat/long
+const GEO_KEYS: [u16; 16] = [
+    1, 1, 0, 3, // directory version, key revision, minor revision, number of keys
+    1024, 0, 1, 2, // GTModelTypeGeoKey = ModelTypeGeographic
+    1025, 0, 1, 1, // GTRasterTypeGeoKey = RasterPixelIsArea
+    2048, 0, 1, 4326, // GeographicTypeGeoKey = GCS_WGS_84
+];
+
+// Writes a single band float32 GeoTIFF. `values` holds `width * height` samples,
+// row by row starting from the north, covering [west, south, east, north] in degrees.
+pub fn write_geotiff(
+    filename: &str,
+    width: usize,
+    height: usize,
+    values: &[f32],
+    bounds: [f64; 4],
+) -> MapResult<()> {
+    let data_length = width as u64 * height as u64 * 4;
+    if values.len() != width * height {
+        return Err(MapError::InvalidData(format!(
+            "{} values for a {}x{} image",
+            values.len(),
+            width,
+            height
+        )));
+    }
+    let [west, south, east, north] = bounds;
+    let pixel_scale = [
+        (east - west) / width as f64,
+        (north - south) / height as f64,
+        0.0,
+    ];
+    let tiepoint = [0.0, 0.0, 0.0, west, north, 0.0];
+
+    let n_entries = 14;
+    let ifd_offset: u32 = 8;
+    let ifd_length = 2 + n_entries * 12 + 4;
+    let pixel_scale_offset = ifd_offset + ifd_length;
+    let tiepoint_offset = pixel_scale_offset + 3 * 8;
+    let geo_keys_offset = tiepoint_offset + 6 * 8;
+    let data_offset = geo_keys_offset + GEO_KEYS.len() as u32 * 2;
+    // classic TIFF files use 32 bit offsets
+    if data_offset as u64 + data_length > u32::MAX as u64 {
+        return Err(MapError::InvalidData(format!(
+            "{}x{} is too large for a GeoTIFF file",
+            width, height
+        )));
+    }
+
+    let mut header = vec![];
+    header.extend_from_slice(b"II");
+    header.extend_from_slice(&42u16.to_le_bytes());
+    header.extend_from_slice(&ifd_offset.to_le_bytes());
+
+    // entries must be sorted by tag
+    header.extend_from_slice(&(n_entries as u16).to_le_bytes());
+    let entries: [(u16, u16, u32, u32); 14] = [
+        (IMAGE_WIDTH, TYPE_LONG, 1, width as u32),
+        (IMAGE_LENGTH, TYPE_LONG, 1, height as u32),
+        (BITS_PER_SAMPLE, TYPE_SHORT, 1, 32),
+        (COMPRESSION, TYPE_SHORT, 1, 1),
+        (PHOTOMETRIC_INTERPRETATION, TYPE_SHORT, 1, 1),
+        (STRIP_OFFSETS, TYPE_LONG, 1, data_offset),
+        (SAMPLES_PER_PIXEL, TYPE_SHORT, 1, 1),
+        (ROWS_PER_STRIP, TYPE_LONG, 1, height as u32),
+        (STRIP_BYTE_COUNTS, TYPE_LONG, 1, data_length as u32),
+        (PLANAR_CONFIGURATION, TYPE_SHORT, 1, 1),
+        // IEEE floating point
+        (SAMPLE_FORMAT, TYPE_SHORT, 1, 3),
+        (MODEL_PIXEL_SCALE, TYPE_DOUBLE, 3, pixel_scale_offset),
+        (MODEL_TIEPOINT, TYPE_DOUBLE, 6, tiepoint_offset),
+        (
+            GEO_KEY_DIRECTORY,
+            TYPE_SHORT,
+            GEO_KEYS.len() as u32,
+            geo_keys_offset,
+        ),
+    ];
+    for (tag, field_type, count, value) in entries {
+        header.extend_from_slice(&tag.to_le_bytes());
+        header.extend_from_slice(&field_type.to_le_bytes());
+        header.extend_from_slice(&count.to_le_bytes());
+        if field_type == TYPE_SHORT && count == 1 {
+            // values shorter than 4 bytes are left justified
+            header.extend_from_slice(&(value as u16).to_le_bytes());
+            header.extend_from_slice(&[0, 0]);
+        } else {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    header.extend_from_slice(&0u32.to_le_bytes());
+
+    for value in pixel_scale.iter().chain(tiepoint.iter()) {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    for key in GEO_KEYS {
+        header.extend_from_slice(&key.to_le_bytes());
+    }
+
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writer.write_all(&header)?;
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// A single band raster read from a TIFF file, rows starting from the top of the image.
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f32>,
+}
+
+struct TiffReader {
+    data: Vec<u8>,
+    little_endian: bool,
+}
+
+impl TiffReader {
+    fn bytes(&self, offset: usize, length: usize) -> MapResult<&[u8]> {
+        self.data
+            .get(offset..offset + length)
+            .ok_or_else(|| MapError::InvalidData("tiff file is truncated".to_string()))
+    }
+
+    fn uint(&self, offset: usize, size: usize) -> MapResult<u64> {
+        let bytes = self.bytes(offset, size)?;
+        let mut value = 0;
+        for i in 0..size {
+            let byte = if self.little_endian {
+                bytes[size - 1 - i]
+            } else {
+                bytes[i]
+            };
+            value = (value << 8) | byte as u64;
+        }
+        return Ok(value);
+    }
+
+    // Integer values of an IFD entry, stored in the entry itself when they fit in 4 bytes.
+    fn entry_values(&self, entry_offset: usize) -> MapResult<Vec<u64>> {
+        let field_type = self.uint(entry_offset + 2, 2)? as u16;
+        let count = self.uint(entry_offset + 4, 4)? as usize;
+        let size = match field_type {
+            TYPE_BYTE => 1,
+            TYPE_SHORT => 2,
+            TYPE_LONG => 4,
+            _ => return Ok(vec![]),
+        };
+        let mut offset = entry_offset + 8;
+        if size * count > 4 {
+            offset = self.uint(offset, 4)? as usize;
+        }
+        (0..count)
+            .map(|i| self.uint(offset + i * size, size))
+            .collect()
+    }
+}
+
+// Reads the first band of the first image of a TIFF or GeoTIFF file. Supports
+// uncompressed and deflate compressed strips of 8, 16 or 32 bit integers and
+// 32 or 64 bit floats. The georeference is ignored, the raster covers the whole map.
+pub fn read_geotiff(filename: &str) -> MapResult<Raster> {
+    let data = fs::read(filename)?;
+    let little_endian = match data.get(0..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => {
+            return Err(MapError::InvalidData(format!(
+                "{} is not a tiff file",
+                filename
+            )))
+        }
+    };
+    let tiff = TiffReader {
+        data,
+        little_endian,
+    };
+    if tiff.uint(2, 2)? != 42 {
+        return Err(MapError::InvalidData(format!(
+            "{} is not a classic tiff file",
+            filename
+        )));
+    }
+
+    let ifd_offset = tiff.uint(4, 4)? as usize;
+    let n_entries = tiff.uint(ifd_offset, 2)? as usize;
+    let mut tags = HashMap::new();
+    for i in 0..n_entries {
+        let entry_offset = ifd_offset + 2 + i * 12;
+        let tag = tiff.uint(entry_offset, 2)? as u16;
+        tags.insert(tag, tiff.entry_values(entry_offset)?);
+    }
+    let tag = |tag: u16, default: Option<u64>| -> MapResult<u64> {
+        match tags.get(&tag).and_then(|values| values.first()) {
+            Some(value) => Ok(*value),
+            None => default.ok_or_else(|| {
+                MapError::InvalidData(format!("{} is missing tiff tag {}", filename, tag))
+            }),
+        }
+    };
+
+    let width = tag(IMAGE_WIDTH, None)? as usize;
+    let height = tag(IMAGE_LENGTH, None)? as usize;
+    let bits_per_sample = tag(BITS_PER_SAMPLE, Some(1))? as usize;
+    let samples_per_pixel = tag(SAMPLES_PER_PIXEL, Some(1))? as usize;
+    let sample_format = tag(SAMPLE_FORMAT, Some(1))?;
+    let compression = tag(COMPRESSION, Some(COMPRESSION_NONE))?;
+    let predictor = tag(PREDICTOR, Some(1))?;
+    let rows_per_strip = tag(ROWS_PER_STRIP, Some(height as u64))? as usize;
+    if tags.contains_key(&TILE_WIDTH) {
+        return Err(MapError::InvalidData(format!(
+            "{} is tiled, only tiffs stored in strips are supported",
+            filename
+        )));
+    }
+    if tag(PLANAR_CONFIGURATION, Some(1))? != 1 && samples_per_pixel > 1 {
+        return Err(MapError::InvalidData(format!(
+            "{} has planar samples, which are not supported",
+            filename
+        )));
+    }
+    if predictor != 1 && !(predictor == 2 && sample_format != 3) {
+        return Err(MapError::InvalidData(format!(
+            "{} uses unsupported predictor {}",
+            filename, predictor
+        )));
+    }
+    let bytes_per_sample = bits_per_sample / 8;
+    if !matches!(
+        (sample_format, bits_per_sample),
+        (1, 8) | (1, 16) | (1, 32) | (2, 8) | (2, 16) | (2, 32) | (3, 32) | (3, 64)
+    ) {
+        return Err(MapError::InvalidData(format!(
+            "{} has unsupported {} bit samples of format {}",
+            filename, bits_per_sample, sample_format
+        )));
+    }
+
+    let strip_offsets = tags.get(&STRIP_OFFSETS).cloned().unwrap_or_default();
+    let strip_byte_counts = tags.get(&STRIP_BYTE_COUNTS).cloned().unwrap_or_default();
+    if strip_offsets.len() != strip_byte_counts.len() {
+        return Err(MapError::InvalidData(format!(
+            "{} has inconsistent strips",
+            filename
+        )));
+    }
+    let mut pixels = Vec::with_capacity(width * height * samples_per_pixel * bytes_per_sample);
+    for (offset, length) in strip_offsets.iter().zip(strip_byte_counts.iter()) {
+        let strip = tiff.bytes(*offset as usize, *length as usize)?;
+        match compression {
+            COMPRESSION_NONE => pixels.extend_from_slice(strip),
+            COMPRESSION_DEFLATE | COMPRESSION_DEFLATE_OLD => {
+                ZlibDecoder::new(strip).read_to_end(&mut pixels)?;
+            }
+            _ => {
+                return Err(MapError::InvalidData(format!(
+                    "{} uses unsupported compression {}",
+                    filename, compression
+                )))
+            }
+        }
+    }
+    let row_length = width * samples_per_pixel * bytes_per_sample;
+    let n_strips = height.div_ceil(rows_per_strip.max(1));
+    if pixels.len() < row_length * height || strip_offsets.len() < n_strips {
+        return Err(MapError::InvalidData(format!(
+            "{} has less pixels than its {}x{} size",
+            filename, width, height
+        )));
+    }
+
+    let pixel_reader = TiffReader {
+        data: pixels,
+        little_endian,
+    };
+    let mut values = Vec::with_capacity(width * height);
+    for row in 0..height {
+        let mut previous: u64 = 0;
+        for column in 0..width {
+            let offset = row * row_length + column * samples_per_pixel * bytes_per_sample;
+            let mut raw = pixel_reader.uint(offset, bytes_per_sample)?;
+            if predictor == 2 {
+                // horizontal differencing
+                raw = raw.wrapping_add(previous) & (u64::MAX >> (64 - bits_per_sample));
+                previous = raw;
+            }
+            let value = match (sample_format, bits_per_sample) {
+                (1, _) => raw as f32,
+                (2, 8) => raw as u8 as i8 as f32,
+                (2, 16) => raw as u16 as i16 as f32,
+                (2, _) => raw as u32 as i32 as f32,
+                (3, 32) => f32::from_bits(raw as u32),
+                _ => f64::from_bits(raw) as f32,
+            };
+            values.push(value);
+        }
+    }
+    Ok(Raster {
+        width,
+        height,
+        values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn test_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("geotiff_{}_{}", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn geotiffs_are_read_back() {
+        let path = test_file("round_trip");
+        let values = [-10.5, 0.0, 1.25, 8848.0, -11034.0, 3.0e-3];
+        write_geotiff(&path, 3, 2, &values, [-180.0, -90.0, 180.0, 90.0]).unwrap();
+        let raster = read_geotiff(&path).unwrap();
+        assert_eq!([raster.width, raster.height], [3, 2]);
+        assert_eq!(raster.values, values);
+
+        assert!(write_geotiff(&path, 4, 2, &values, [-180.0, -90.0, 180.0, 90.0]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // A big endian tiff of signed 16 bit samples, one deflate compressed strip per row,
+    // with horizontal differencing, as written by other tools
+    #[test]
+    fn compressed_big_endian_tiffs_are_read() {
+        let rows: [[i16; 3]; 2] = [[-5, 10, 300], [0, -1, 32767]];
+        let strips: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|row| {
+                let mut previous = 0i16;
+                let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+                for value in row {
+                    encoder
+                        .write_all(&value.wrapping_sub(previous).to_be_bytes())
+                        .unwrap();
+                    previous = *value;
+                }
+                encoder.finish().unwrap()
+            })
+            .collect();
+
+        let n_entries = 10;
+        let arrays_offset = 8 + 2 + n_entries * 12 + 4;
+        let strips_offset = arrays_offset + 16;
+        let entries: [(u16, u16, u32, u32); 10] = [
+            (IMAGE_WIDTH, TYPE_SHORT, 1, 3),
+            (IMAGE_LENGTH, TYPE_SHORT, 1, 2),
+            (BITS_PER_SAMPLE, TYPE_SHORT, 1, 16),
+            (COMPRESSION, TYPE_SHORT, 1, COMPRESSION_DEFLATE as u32),
+            (STRIP_OFFSETS, TYPE_LONG, 2, arrays_offset),
+            (SAMPLES_PER_PIXEL, TYPE_SHORT, 1, 1),
+            (ROWS_PER_STRIP, TYPE_SHORT, 1, 1),
+            (STRIP_BYTE_COUNTS, TYPE_LONG, 2, arrays_offset + 8),
+            (PREDICTOR, TYPE_SHORT, 1, 2),
+            (SAMPLE_FORMAT, TYPE_SHORT, 1, 2),
+        ];
+        let mut tiff = b"MM".to_vec();
+        tiff.extend(42u16.to_be_bytes());
+        tiff.extend(8u32.to_be_bytes());
+        tiff.extend((n_entries as u16).to_be_bytes());
+        for (tag, field_type, count, value) in entries {
+            tiff.extend(tag.to_be_bytes());
+            tiff.extend(field_type.to_be_bytes());
+            tiff.extend(count.to_be_bytes());
+            if field_type == TYPE_SHORT {
+                tiff.extend((value as u16).to_be_bytes());
+                tiff.extend([0, 0]);
+            } else {
+                tiff.extend(value.to_be_bytes());
+            }
+        }
+        tiff.extend(0u32.to_be_bytes());
+        tiff.extend(strips_offset.to_be_bytes());
+        tiff.extend((strips_offset + strips[0].len() as u32).to_be_bytes());
+        for strip in &strips {
+            tiff.extend((strip.len() as u32).to_be_bytes());
+        }
+        for strip in &strips {
+            tiff.extend(strip);
+        }
+
+        let path = test_file("big_endian");
+        std::fs::write(&path, &tiff).unwrap();
+        let raster = read_geotiff(&path).unwrap();
+        let expected: Vec<f32> = rows.iter().flatten().map(|value| *value as f32).collect();
+        assert_eq!(raster.values, expected);
+
+        // truncated files are an error, not a panic
+        std::fs::write(&path, &tiff[..tiff.len() - 4]).unwrap();
+        assert!(read_geotiff(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use image::{DynamicImage, GenericImageView, Rgba};
+use serde::Deserialize;
+
+use crate::{
+    error::{MapError, MapResult},
+    geotiff::read_geotiff,
+    map_view::color_scheme::GradientColorScheme,
+    partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+// How the samples of a heightmap file are turned into heights in meters.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ValueMapping {
+    // samples are heights, `sea_level` is the sample at height 0. The samples of
+    // images are their 16 bit gray values.
+    Meters {
+        #[serde(default)]
+        sea_level: f32,
+    },
+    // samples scaled to 0..1 go from `black` to `white` meters. With a `sea_level`
+    // (also in 0..1) each side of it is scaled separately, so that it maps to 0.
+    Linear {
+        black: f32,
+        white: f32,
+        #[serde(default)]
+        sea_level: Option<f32>,
+    },
+    // green for land and blue for water, as written by PartialMap::save_as_img
+    GreenBlue {
+        min: i32,
+        max: i32,
+    },
+    // the height of the closest color in a gradient, for hand painted maps
+    ColorScheme(GradientColorScheme),
+}
+
+impl ValueMapping {
+    fn scale(&self, level: f32) -> f32 {
+        match self {
+            ValueMapping::Linear {
+                black,
+                white,
+                sea_level: Some(sea_level),
+            } => {
+                if level < *sea_level {
+                    return black * (sea_level - level) / sea_level.max(f32::EPSILON);
+                }
+                return white * (level - sea_level) / (1.0 - sea_level).max(f32::EPSILON);
+            }
+            ValueMapping::Linear { black, white, .. } => black + level * (white - black),
+            ValueMapping::Meters { sea_level } => level - sea_level,
+            _ => level,
+        }
+    }
+}
+
+// Samples read from a heightmap file, rows starting from latitude -90. Grid files and 16 bit
+// images are north up, as written by export::heightmap, while images read with a color
+// mapping have their first row at latitude -90, as written by PartialMap::save_as_img.
+pub enum HeightmapSource {
+    Image(DynamicImage),
+    // 16 bit and float files: r16, raw and GeoTIFF
+    Grid {
+        width: usize,
+        height: usize,
+        values: Vec<f32>,
+    },
+}
+
+impl HeightmapSource {
+    // Raw files do not store their size, they are `raw_width` samples wide, or twice as
+    // wide as high when it is not given.
+    pub fn open(filename: &str, raw_width: Option<usize>) -> MapResult<Self> {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let source = match extension.as_deref() {
+            Some("tif") | Some("tiff") => {
+                let raster = read_geotiff(filename)?;
+                HeightmapSource::Grid {
+                    width: raster.width,
+                    height: raster.height,
+                    values: reverse_rows(&raster.values, raster.width),
+                }
+            }
+            Some("r16") => {
+                let values = fs::read(filename)?
+                    .chunks_exact(2)
+                    .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32)
+                    .collect();
+                Self::raw_grid(filename, values, raw_width)?
+            }
+            Some("raw") | Some("f32") => {
+                let values = fs::read(filename)?
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect();
+                Self::raw_grid(filename, values, raw_width)?
+            }
+            _ => HeightmapSource::Image(image::open(filename)?),
+        };
+        return Ok(source);
+    }
+
+    fn raw_grid(filename: &str, values: Vec<f32>, raw_width: Option<usize>) -> MapResult<Self> {
+        let width = match raw_width {
+            Some(width) => width,
+            None => 2 * ((values.len() / 2) as f64).sqrt().round() as usize,
+        };
+        if width == 0 || values.is_empty() || !values.len().is_multiple_of(width) {
+            return Err(MapError::InvalidData(format!(
+                "{} has {} samples, which is not a multiple of the width {}",
+                filename,
+                values.len(),
+                width
+            )));
+        }
+        return Ok(HeightmapSource::Grid {
+            width,
+            height: values.len() / width,
+            values: reverse_rows(&values, width),
+        });
+    }
+
+    pub fn default_mapping(&self) -> ValueMapping {
+        match self {
+            HeightmapSource::Image(_) => ValueMapping::GreenBlue {
+                min: -5000,
+                max: 6400,
+            },
+            HeightmapSource::Grid { .. } => ValueMapping::Meters { sea_level: 0.0 },
+        }
+    }
+
+    pub fn to_heights<S: MapShape>(&self, mapping: &ValueMapping) -> MapResult<PartialMap<S, i32>> {
+        let rows = match self {
+            HeightmapSource::Image(img) => {
+                let mut rows = image_heights(img, mapping);
+                if matches!(
+                    mapping,
+                    ValueMapping::Meters { .. } | ValueMapping::Linear { .. }
+                ) {
+                    rows.reverse();
+                }
+                rows
+            }
+            HeightmapSource::Grid {
+                width,
+                height,
+                values,
+            } => grid_heights(*width, *height, values, mapping)?,
+        };
+        return Ok(PartialMap::from_rows(rows));
+    }
+}
+
+fn reverse_rows(values: &[f32], width: usize) -> Vec<f32> {
+    return values
+        .chunks(width.max(1))
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+}
+
+fn image_heights(img: &DynamicImage, mapping: &ValueMapping) -> Vec<Vec<i32>> {
+    let gray = match mapping {
+        ValueMapping::Meters { .. } | ValueMapping::Linear { .. } => Some(img.to_luma16()),
+        _ => None,
+    };
+    let gray_level = |img_x: u32, img_y: u32| -> f32 {
+        gray.as_ref()
+            .map_or(0.0, |gray| gray.get_pixel(img_x, img_y)[0] as f32)
+    };
+    let mut color_heights: HashMap<Rgba<u8>, i32> = HashMap::new();
+    let mut rows = vec![];
+    for img_y in 0..img.height() {
+        let mut line = vec![];
+        for img_x in 0..img.width() {
+            let value = match mapping {
+                ValueMapping::GreenBlue { min, max } => {
+                    let color = img.get_pixel(img_x, img_y);
+                    if color[1] > color[2] {
+                        // land
+                        max * color[1] as i32 / 254
+                    } else {
+                        // water
+                        min * (255 - color[2] as i32) / 255
+                    }
+                }
+                ValueMapping::ColorScheme(color_scheme) => {
+                    let color = img.get_pixel(img_x, img_y);
+                    *color_heights
+                        .entry(color)
+                        .or_insert_with(|| height_of_color(color_scheme, color))
+                }
+                ValueMapping::Meters { .. } => {
+                    mapping.scale(gray_level(img_x, img_y)).round() as i32
+                }
+                ValueMapping::Linear { .. } => {
+                    let level = gray_level(img_x, img_y) / u16::MAX as f32;
+                    mapping.scale(level).round() as i32
+                }
+            };
+            line.push(value);
+        }
+        rows.push(line);
+    }
+    return rows;
+}
+
+fn grid_heights(
+    width: usize,
+    height: usize,
+    values: &[f32],
+    mapping: &ValueMapping,
+) -> MapResult<Vec<Vec<i32>>> {
+    let [min_value, max_value] = values.iter().fold([f32::MAX, f32::MIN], |range, value| {
+        [range[0].min(*value), range[1].max(*value)]
+    });
+    let to_height = |value: f32| -> MapResult<i32> {
+        let height = match mapping {
+            ValueMapping::Meters { .. } => mapping.scale(value),
+            ValueMapping::Linear { .. } => {
+                let level = (value - min_value) / (max_value - min_value).max(f32::EPSILON);
+                mapping.scale(level)
+            }
+            ValueMapping::GreenBlue { .. } | ValueMapping::ColorScheme(_) => {
+                return Err(MapError::InvalidData(
+                    "color mappings need a color image".to_string(),
+                ))
+            }
+        };
+        return Ok(height.round() as i32);
+    };
+    (0..height)
+        .map(|row| {
+            values[row * width..(row + 1) * width]
+                .iter()
+                .map(|value| to_height(*value))
+                .collect()
+        })
+        .collect()
+}
+
+// Inverse of the gradient: the color is projected on every segment between two
+// consecutive points of the gradient, and the closest projection gives the height.
+fn height_of_color(color_scheme: &GradientColorScheme, color: Rgba<u8>) -> i32 {
+    let points = &color_scheme.points;
+    let Some((first_value, first_color)) = points.first() else {
+        return 0;
+    };
+    let rgb = |c: &Rgba<u8>| [c[0] as f32, c[1] as f32, c[2] as f32];
+    let target = rgb(&color);
+    let distance =
+        |a: [f32; 3], b: [f32; 3]| -> f32 { (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f32>() };
+
+    let mut best_value = *first_value as f32;
+    let mut best_distance = distance(rgb(first_color), target);
+    for segment in points.windows(2) {
+        let (value_a, color_a) = segment[0];
+        let (value_b, color_b) = segment[1];
+        let a = rgb(&color_a);
+        let b = rgb(&color_b);
+        let direction = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let length = distance(a, b);
+        let t = if length > 0.0 {
+            ((0..3)
+                .map(|i| (target[i] - a[i]) * direction[i])
+                .sum::<f32>()
+                / length)
+                .clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let projection = [
+            a[0] + t * direction[0],
+            a[1] + t * direction[1],
+            a[2] + t * direction[2],
+        ];
+        let projection_distance = distance(projection, target);
+        if projection_distance < best_distance {
+            best_distance = projection_distance;
+            best_value = value_a as f32 + t * (value_b - value_a) as f32;
+        }
+    }
+    return best_value.round() as i32;
+}
+
+pub fn load_heightmap<S: MapShape>(
+    filename: &str,
+    mapping: Option<&ValueMapping>,
+    raw_width: Option<usize>,
+) -> MapResult<PartialMap<S, i32>> {
+    let source = HeightmapSource::open(filename, raw_width)?;
+    match mapping {
+        Some(mapping) => source.to_heights(mapping),
+        None => source.to_heights(&source.default_mapping()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma, RgbaImage};
+
+    use super::*;
+    use crate::shapes::flat::Flat;
+
+    fn test_file(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("heightmap_import_{}_{}", std::process::id(), name));
+        return path.to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn mappings_scale_samples_to_meters() {
+        let meters = ValueMapping::Meters { sea_level: 100.0 };
+        assert_eq!(meters.scale(350.0), 250.0);
+        let linear = ValueMapping::Linear {
+            black: -1000.0,
+            white: 3000.0,
+            sea_level: None,
+        };
+        assert_eq!(linear.scale(0.0), -1000.0);
+        assert_eq!(linear.scale(0.5), 1000.0);
+        // each side of the sea level is scaled on its own
+        let coast = ValueMapping::Linear {
+            black: -1000.0,
+            white: 3000.0,
+            sea_level: Some(0.25),
+        };
+        assert_eq!(coast.scale(0.0), -1000.0);
+        assert_eq!(coast.scale(0.25), 0.0);
+        assert_eq!(coast.scale(1.0), 3000.0);
+    }
+
+    #[test]
+    fn grids_use_their_range_for_linear_mappings() {
+        let linear = ValueMapping::Linear {
+            black: 0.0,
+            white: 1000.0,
+            sea_level: None,
+        };
+        let rows = grid_heights(2, 2, &[10.0, 20.0, 30.0, 50.0], &linear).unwrap();
+        assert_eq!(rows, vec![vec![0, 250], vec![500, 1000]]);
+        let green_blue = ValueMapping::GreenBlue { min: -10, max: 10 };
+        assert!(grid_heights(2, 2, &[0.0; 4], &green_blue).is_err());
+    }
+
+    #[test]
+    fn colors_are_projected_on_the_gradient() {
+        let gradient = GradientColorScheme {
+            points: vec![
+                (-1000, Rgba([0, 0, 200, 255])),
+                (0, Rgba([0, 200, 0, 255])),
+                (2000, Rgba([200, 200, 200, 255])),
+            ],
+        };
+        assert_eq!(height_of_color(&gradient, Rgba([0, 0, 200, 255])), -1000);
+        assert_eq!(height_of_color(&gradient, Rgba([100, 200, 100, 255])), 1000);
+        assert_eq!(height_of_color(&gradient, Rgba([255, 255, 255, 255])), 2000);
+    }
+
+    // 16 bit images are north up, color images south up as written by save_as_img
+    #[test]
+    fn image_rows_follow_their_mapping() {
+        let gray = test_file("gray.png");
+        let img: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_fn(2, 2, |_, y| Luma([1000 * (y as u16 + 1)]));
+        img.save(&gray).unwrap();
+        let meters = ValueMapping::Meters { sea_level: 0.0 };
+        let heights: PartialMap<Flat, i32> = load_heightmap(&gray, Some(&meters), None).unwrap();
+        assert_eq!(heights.values, vec![vec![2000, 2000], vec![1000, 1000]]);
+
+        let color = test_file("color.png");
+        let img = RgbaImage::from_fn(2, 2, |_, y| {
+            if y == 0 {
+                Rgba([0, 0, 255, 255])
+            } else {
+                Rgba([0, 254, 0, 255])
+            }
+        });
+        img.save(&color).unwrap();
+        let heights: PartialMap<Flat, i32> = load_heightmap(&color, None, None).unwrap();
+        assert_eq!(heights.values, vec![vec![0, 0], vec![6400, 6400]]);
+
+        std::fs::remove_file(&gray).unwrap();
+        std::fs::remove_file(&color).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    error::MapError,
+    map_pipeline::{MapPipeline, StepProgress},
+    shapes::map_shape::MapShape,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+// A map generated in the background. The world is added to the store when it finishes.
+pub struct Job {
+    world_name: String,
+    state: JobState,
+    steps: Vec<String>,
+    progress: Vec<StepProgress>,
+    error: Option<String>,
+    started: Instant,
+    ended: Option<Instant>,
+    cancel: Arc<AtomicBool>,
+}
+
+#[derive(Serialize)]
+pub struct JobStatus {
+    pub id: u64,
+    pub world_name: String,
+    pub state: JobState,
+    // names of all the steps of the pipeline, empty until it is built
+    pub steps: Vec<String>,
+    // one entry for each finished step
+    pub progress: Vec<StepProgress>,
+    pub elapsed: f32,
+    pub error: Option<String>,
+}
+
+pub type Jobs = Arc<Mutex<HashMap<u64, Job>>>;
+
+// How long the status of a job that is not running anymore can be read
+const ENDED_JOB_LIFETIME: Duration = Duration::from_secs(600);
+
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+pub fn lock_jobs(jobs: &Jobs) -> MutexGuard<'_, HashMap<u64, Job>> {
+    jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Forgets the jobs that ended more than ENDED_JOB_LIFETIME ago
+fn expire_jobs(jobs: &mut HashMap<u64, Job>) {
+    jobs.retain(|_, job| {
+        job.ended
+            .map_or(true, |ended| ended.elapsed() < ENDED_JOB_LIFETIME)
+    });
+}
+
+pub fn start_job(jobs: &Jobs, world_name: &str) -> u64 {
+    let id = NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed);
+    let job = Job {
+        world_name: world_name.to_string(),
+        state: JobState::Running,
+        steps: vec![],
+        progress: vec![],
+        error: None,
+        started: Instant::now(),
+        ended: None,
+        cancel: Arc::new(AtomicBool::new(false)),
+    };
+    let mut locked_jobs = lock_jobs(jobs);
+    expire_jobs(&mut locked_jobs);
+    locked_jobs.insert(id, job);
+    return id;
+}
+
+// Records the steps of the pipeline and its progress in the job, and lets the job cancel it.
+pub fn track_pipeline<S: MapShape + 'static>(pipeline: &mut MapPipeline<S>, jobs: &Jobs, id: u64) {
+    let mut locked_jobs = lock_jobs(jobs);
+    let Some(job) = locked_jobs.get_mut(&id) else {
+        return;
+    };
+    job.steps = pipeline.step_names();
+    pipeline.set_cancel_flag(job.cancel.clone());
+    let jobs = jobs.clone();
+    pipeline.on_progress(move |progress| {
+        if let Some(job) = lock_jobs(&jobs).get_mut(&id) {
+            job.progress.push(progress.clone());
+        }
+    });
+}
+
+pub fn finish_job(jobs: &Jobs, id: u64, result: Result<(), MapError>) {
+    let mut locked_jobs = lock_jobs(jobs);
+    let Some(job) = locked_jobs.get_mut(&id) else {
+        return;
+    };
+    job.ended = Some(Instant::now());
+    job.state = match result {
+        Ok(()) => JobState::Finished,
+        Err(MapError::Cancelled) => JobState::Cancelled,
+        Err(e) => {
+            job.error = Some(e.to_string());
+            JobState::Failed
+        }
+    };
+}
+
+// Returns false when there is no such job or it is not running anymore. The pipeline only
+// checks the flag between steps, so a running step, e.g. the climate, is finished first.
+pub fn cancel_job(jobs: &Jobs, id: u64) -> bool {
+    match lock_jobs(jobs).get(&id) {
+        Some(job) if job.state == JobState::Running => {
+            job.cancel.store(true, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+// None for unknown jobs and for jobs that ended more than ENDED_JOB_LIFETIME ago
+pub fn job_status(jobs: &Jobs, id: u64) -> Option<JobStatus> {
+    let mut locked_jobs = lock_jobs(jobs);
+    expire_jobs(&mut locked_jobs);
+    let job = locked_jobs.get(&id)?;
+    return Some(JobStatus {
+        id,
+        world_name: job.world_name.clone(),
+        state: job.state,
+        steps: job.steps.clone(),
+        progress: job.progress.clone(),
+        elapsed: (job.ended.unwrap_or_else(Instant::now) - job.started).as_secs_f32(),
+        error: job.error.clone(),
+    });
+}
@@ -1,59 +1,240 @@
 use std::env;
-use std::{fs::File, io::Read, time::Instant};
+use std::sync::Arc;
+use std::time::Instant;
 
-use complete_map::{load, CompleteMap};
+use serde::de::DeserializeOwned;
+
+use complete_map::{CompleteMap, CompleteMapEnum};
 use draw_functions::{draw_all, draw_all_equirectangular};
 
-use shapes::{cylinder::Cylinder, flat::Flat, globe::Globe};
+use shapes::{
+    cube_sphere::CubeSphere, cylinder::Cylinder, disc::Disc, flat::Flat, globe::Globe, torus::Torus,
+};
 
 use crate::configuration::{Configuration, ShapeEnum};
-use crate::recipe::standard_recipe;
+use crate::error::MapResult;
+use crate::export::{geojson, heightmap};
+use crate::map_file::layers::LayerId;
+use crate::pipeline_steps::region::RegionConfig;
+use crate::recipe::{recipe_from_config, region_recipe};
+use crate::shapes::map_shape::MapShape;
 
+mod checkpoint;
 mod complete_map;
 mod configuration;
 mod draw_functions;
+mod error;
+mod export;
+mod geotiff;
+mod import;
+mod map_file;
 mod map_pipeline;
 mod map_view;
 mod partial_map;
+mod pipeline_observer;
 mod pipeline_steps;
 mod recipe;
+mod seed;
 mod shapes;
 
-fn generate_map(config_file: &str) {
-    let start = Instant::now();
+// Generates a map with the recipe of the configuration, printing the duration of every
+// step when the configuration asks for it
+fn run_recipe<S: MapShape + DeserializeOwned + 'static>(
+    config: &Configuration,
+) -> MapResult<CompleteMap<S>> {
+    let mut map_pipeline = recipe_from_config(config)?;
+    let cmap = map_pipeline.execute()?;
+    for progress in map_pipeline.report() {
+        if config.log_step_timings && progress.cached {
+            println!(
+                "step {}/{} cached {}",
+                progress.index + 1,
+                progress.steps,
+                progress.name
+            );
+        } else if config.log_step_timings {
+            println!(
+                "step {}/{} {:.3}s {}",
+                progress.index + 1,
+                progress.steps,
+                progress.duration,
+                progress.name
+            );
+            for sub_step in &progress.sub_steps {
+                println!("    {:.3}s {}", sub_step.duration, sub_step.name);
+            }
+        }
+        if let Some(e) = &progress.checkpoint_error {
+            println!(
+                "could not save the checkpoint of step {}: {}",
+                progress.index, e
+            );
+        }
+    }
+    return Ok(cmap);
+}
 
-    let mut file = File::open(config_file).unwrap();
-    let mut data = String::new();
-    file.read_to_string(&mut data).unwrap();
+fn generate_map(config_file: &str) -> MapResult<()> {
+    let start = Instant::now();
 
-    let config: Configuration = serde_json::from_str(&data).unwrap();
+    let config = Configuration::from_file(config_file)?;
 
-    match config.shape {
+    let cmap_enum = match config.shape {
         ShapeEnum::Cylinder => {
-            let cmap: CompleteMap<Cylinder> = standard_recipe(&config).execute();
-            let end = Instant::now();
-            let generation_time = (end - start).as_secs_f32();
-            println!("generation time: {generation_time}");
-            let _ = cmap.save("map.bin");
-            draw_all_equirectangular(&cmap);
+            let cmap: CompleteMap<Cylinder> = run_recipe(&config)?;
+            CompleteMapEnum::Cylinder(cmap)
+        }
+        ShapeEnum::CubeSphere => {
+            let cmap: CompleteMap<CubeSphere> = run_recipe(&config)?;
+            CompleteMapEnum::CubeSphere(cmap)
         }
         ShapeEnum::Globe => {
-            let cmap: CompleteMap<Globe> = standard_recipe(&config).execute();
-            let end = Instant::now();
-            let generation_time = (end - start).as_secs_f32();
-            println!("generation time: {generation_time}");
-            let _ = cmap.save("map.bin");
-            draw_all(&cmap);
+            let cmap: CompleteMap<Globe> = run_recipe(&config)?;
+            CompleteMapEnum::Globe(cmap)
         }
         ShapeEnum::Flat => {
-            let cmap: CompleteMap<Flat> = standard_recipe(&config).execute();
-            let end = Instant::now();
-            let generation_time = (end - start).as_secs_f32();
-            println!("generation time: {generation_time}");
-            let _ = cmap.save("map.bin");
-            draw_all_equirectangular(&cmap);
+            let cmap: CompleteMap<Flat> = run_recipe(&config)?;
+            CompleteMapEnum::Flat(cmap)
+        }
+        ShapeEnum::Torus => {
+            let cmap: CompleteMap<Torus> = run_recipe(&config)?;
+            CompleteMapEnum::Torus(cmap)
+        }
+        ShapeEnum::Disc => {
+            let cmap: CompleteMap<Disc> = run_recipe(&config)?;
+            CompleteMapEnum::Disc(cmap)
+        }
+    };
+    let end = Instant::now();
+    let generation_time = (end - start).as_secs_f32();
+    println!("generation time: {generation_time}");
+    map_file::save(&cmap_enum, Some(&config), "map.bin")?;
+    draw_map(&cmap_enum);
+    Ok(())
+}
+
+fn draw_map(cmap_enum: &CompleteMapEnum) {
+    match cmap_enum {
+        CompleteMapEnum::Globe(cmap) => draw_all(cmap),
+        CompleteMapEnum::Cylinder(cmap) => draw_all_equirectangular(cmap),
+        CompleteMapEnum::CubeSphere(cmap) => draw_all(cmap),
+        CompleteMapEnum::Flat(cmap) => draw_all_equirectangular(cmap),
+        CompleteMapEnum::Torus(cmap) => draw_all_equirectangular(cmap),
+        CompleteMapEnum::Disc(cmap) => draw_all_equirectangular(cmap),
+    }
+}
+
+fn load_map(filename: &str) -> MapResult<()> {
+    let start = Instant::now();
+    let map_file = map_file::load(filename, ShapeEnum::Globe)?;
+    let end = Instant::now();
+    let loading_time = (end - start).as_secs_f32();
+    println!("loading time: {loading_time}");
+    println!(
+        "format version {}, {:?} {}x{}",
+        map_file.version,
+        map_file.header.shape,
+        map_file.header.circunference,
+        map_file.header.height
+    );
+    match &map_file.map {
+        CompleteMapEnum::Globe(cmap) => cmap.height.save_as_img("height.png", -10000, 10000)?,
+        CompleteMapEnum::Cylinder(cmap) => cmap.height.save_as_img("height.png", -10000, 10000)?,
+        CompleteMapEnum::CubeSphere(cmap) => {
+            cmap.height.save_as_img("height.png", -10000, 10000)?
+        }
+        CompleteMapEnum::Flat(cmap) => cmap.height.save_as_img("height.png", -10000, 10000)?,
+        CompleteMapEnum::Torus(cmap) => cmap.height.save_as_img("height.png", -10000, 10000)?,
+        CompleteMapEnum::Disc(cmap) => cmap.height.save_as_img("height.png", -10000, 10000)?,
+    }
+    draw_map(&map_file.map);
+    Ok(())
+}
+
+fn print_header(filename: &str) -> MapResult<()> {
+    match map_file::read_header(filename)? {
+        Some(header) => println!("{}", serde_json::to_string_pretty(&header)?),
+        None => {
+            println!("{} has no header (saved by an older version)", filename);
+            return Ok(());
         }
     }
+    let reader = map_file::MapReader::open(filename, ShapeEnum::Globe)?;
+    if reader.is_lazy() {
+        println!("layers (compressed size):");
+        for (layer, size) in reader.stored_layers() {
+            println!("  {:<22}{:>12} bytes", layer, size);
+        }
+    }
+    Ok(())
+}
+
+// Only the exported layer is read from the map file.
+fn export_layer(filename: &str, layer: &str, output: &str) -> MapResult<()> {
+    let mut reader = map_file::MapReader::open(filename, ShapeEnum::Globe)?;
+    let layer_ids: Vec<LayerId> = LayerId::from_name(layer).into_iter().collect();
+    let cmap_enum = reader.read_layers(&layer_ids)?;
+    let format = match &cmap_enum {
+        CompleteMapEnum::Globe(cmap) => heightmap::export_layer(cmap, layer, output)?,
+        CompleteMapEnum::Cylinder(cmap) => heightmap::export_layer(cmap, layer, output)?,
+        CompleteMapEnum::CubeSphere(cmap) => heightmap::export_layer(cmap, layer, output)?,
+        CompleteMapEnum::Flat(cmap) => heightmap::export_layer(cmap, layer, output)?,
+        CompleteMapEnum::Torus(cmap) => heightmap::export_layer(cmap, layer, output)?,
+        CompleteMapEnum::Disc(cmap) => heightmap::export_layer(cmap, layer, output)?,
+    };
+    println!("exported {} to {} as {:?}", layer, output, format);
+    Ok(())
+}
+
+fn export_geojson(filename: &str, output: &str) -> MapResult<()> {
+    let mut reader = map_file::MapReader::open(filename, ShapeEnum::Globe)?;
+    let cmap_enum = reader.read_layers(&[
+        LayerId::Height,
+        LayerId::Rivers,
+        LayerId::TectonicPlates,
+        LayerId::Features,
+    ])?;
+    match &cmap_enum {
+        CompleteMapEnum::Globe(cmap) => geojson::save_geojson(cmap, output)?,
+        CompleteMapEnum::Cylinder(cmap) => geojson::save_geojson(cmap, output)?,
+        CompleteMapEnum::CubeSphere(cmap) => geojson::save_geojson(cmap, output)?,
+        CompleteMapEnum::Flat(cmap) => geojson::save_geojson(cmap, output)?,
+        CompleteMapEnum::Torus(cmap) => geojson::save_geojson(cmap, output)?,
+        CompleteMapEnum::Disc(cmap) => geojson::save_geojson(cmap, output)?,
+    }
+    println!("exported vectors to {}", output);
+    Ok(())
+}
+
+fn refine_region(filename: &str, region_file: &str, output: &str) -> MapResult<()> {
+    let start = Instant::now();
+    let region = RegionConfig::from_file(region_file)?;
+    let map_file = map_file::load(filename, ShapeEnum::Globe)?;
+    let seed = map_file.header.seed.unwrap_or(0);
+    let cmap: CompleteMap<Flat> = match map_file.map {
+        CompleteMapEnum::Globe(cmap) => region_recipe(Arc::new(cmap), &region, seed)?.execute()?,
+        CompleteMapEnum::Cylinder(cmap) => {
+            region_recipe(Arc::new(cmap), &region, seed)?.execute()?
+        }
+        CompleteMapEnum::CubeSphere(cmap) => {
+            region_recipe(Arc::new(cmap), &region, seed)?.execute()?
+        }
+        CompleteMapEnum::Flat(cmap) => region_recipe(Arc::new(cmap), &region, seed)?.execute()?,
+        CompleteMapEnum::Torus(cmap) => region_recipe(Arc::new(cmap), &region, seed)?.execute()?,
+        CompleteMapEnum::Disc(cmap) => region_recipe(Arc::new(cmap), &region, seed)?.execute()?,
+    };
+    let end = Instant::now();
+    let generation_time = (end - start).as_secs_f32();
+    println!("generation time: {generation_time}");
+    map_file::save(&CompleteMapEnum::Flat(cmap), None, output)?;
+    println!("saved region to {}", output);
+    Ok(())
+}
+
+fn report_error(result: MapResult<()>) {
+    if let Err(e) = result {
+        println!("Error: {}", e);
+    }
 }
 
 fn main() {
@@ -61,23 +242,46 @@ fn main() {
 
     if args.len() <= 1 {
         println!("Generating map from config.json");
-        generate_map("config.json");
+        report_error(generate_map("config.json"));
+        return;
     }
 
-    match args[1].as_str() {
-        "load" => {
+    match (args[1].as_str(), args.get(2)) {
+        ("load", Some(filename)) => {
             println!("Loading map");
-            let start = Instant::now();
-            let cmap = load::<Globe>(args[2].as_str());
-            let end = Instant::now();
-            let loading_time = (end - start).as_secs_f32();
-            println!("loading time: {loading_time}");
-            cmap.height.save_as_img("height.png", -10000, 10000);
-            draw_all(&cmap);
-        }
-        "generate" => {
-            println!("Generating map from {}", args[2].as_str());
-            generate_map(args[2].as_str());
+            report_error(load_map(filename));
+        }
+        ("generate", Some(config_file)) => {
+            println!("Generating map from {}", config_file);
+            report_error(generate_map(config_file));
+        }
+        ("info", Some(filename)) => report_error(print_header(filename)),
+        ("export", Some(filename)) => match (args.get(3), args.get(4)) {
+            (Some(layer), Some(output)) => report_error(export_layer(filename, layer, output)),
+            _ => println!(
+                "usage: export <map file> <layer> <output.png|.r16|.raw|.tif>, layers: {}",
+                heightmap::EXPORTABLE_LAYERS.join(", ")
+            ),
+        },
+        ("geojson", Some(filename)) => match args.get(3) {
+            Some(output) => report_error(export_geojson(filename, output)),
+            None => println!("usage: geojson <map file> <output.geojson>"),
+        },
+        ("region", Some(filename)) => match args.get(3) {
+            Some(region_file) => report_error(refine_region(
+                filename,
+                region_file,
+                args.get(4).map_or("region.bin", |s| s.as_str()),
+            )),
+            None => println!("usage: region <map file> <region.json> [output.bin]"),
+        },
+        ("load", None)
+        | ("generate", None)
+        | ("info", None)
+        | ("export", None)
+        | ("geojson", None)
+        | ("region", None) => {
+            println!("missing file argument for {}", args[1])
         }
         _ => println!("unrecognized option: {}", args[1]),
     }
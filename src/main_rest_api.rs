@@ -2,64 +2,367 @@
 extern crate rocket;
 use thread_priority::{set_current_thread_priority, ThreadPriority};
 
-use std::{collections::HashMap, sync::Mutex, time::Instant};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-use complete_map::{load, CompleteMap};
+use complete_map::{CompleteMap, CompleteMapEnum};
 use configuration::{ClimateConfiguration, Configuration, ShapeEnum};
 use draw_functions::{draw_precipitation, draw_temperature};
-use map_view::view_config::{draw_with_config, img_from_config, ViewConfiguration};
+use error::{MapError, MapResult};
+use export::{geojson, heightmap};
+use import::heightmap::ValueMapping;
+use jobs::{cancel_job, finish_job, job_status, start_job, track_pipeline, JobStatus, Jobs};
+use map_file::{layers::LayerId, MapReader};
+use map_view::{
+    projection::tile::MAX_ZOOM,
+    view_config::{draw_with_config, img_from_config, tile_from_config, ViewConfiguration},
+};
 use pipeline_steps::{
-    calculate_climate::CalculateClimate, define_coastlines::DefineCoastline,
-    height_noise::HeightNoise, hydraulic_erosion::HydraulicErosion,
-    load_custom_layer::LoadCustomLayer, pipeline_step::PipelineStep, resize::Resize,
-    smooth::Smooth, translation_noise::TranslationNoise, water_level::WaterLevel,
+    brush::{Brush, BrushOperation},
+    calculate_climate::CalculateClimate,
+    define_coastlines::DefineCoastline,
+    height_noise::HeightNoise,
+    hydraulic_erosion::HydraulicErosion,
+    load_custom_layer::LoadCustomLayer,
+    load_height::LoadHeight,
+    mask::{MaskSpec, Masked},
+    pipeline_step::{apply_checked, PipelineStep},
+    region::RegionConfig,
+    resize::Resize,
+    smooth::Smooth,
+    translation_noise::TranslationNoise,
+    water_level::WaterLevel,
 };
-use recipe::{recipe_from_image, standard_recipe};
+use recipe::{recipe_from_config, recipe_from_image, region_recipe};
 use rocket::{
-    http::ContentType,
-    serde::{json::Json, Deserialize, Serialize},
+    http::{ContentType, Status},
+    response::status::Custom,
+    serde::{
+        de::DeserializeOwned,
+        json::{self, Json, Value},
+        Deserialize, Serialize,
+    },
     State,
 };
-use shapes::{cylinder::Cylinder, flat::Flat, globe::Globe};
+use seed::derive_seed;
+use shapes::{
+    cube_sphere::CubeSphere, cylinder::Cylinder, disc::Disc, flat::Flat, globe::Globe,
+    map_shape::MapShape, torus::Torus,
+};
+use tile_cache::{is_valid_name, TileCache, TileKey};
 
+mod checkpoint;
 mod complete_map;
 mod configuration;
 mod draw_functions;
+mod error;
+mod export;
+mod geotiff;
+mod import;
+mod jobs;
+mod map_file;
 mod map_pipeline;
 mod map_view;
 mod partial_map;
+mod pipeline_observer;
 mod pipeline_steps;
 mod recipe;
+mod seed;
 mod shapes;
+mod tile_cache;
 
-enum CompleteMapEnum {
-    Cylinder(CompleteMap<Cylinder>),
-    Globe(CompleteMap<Globe>),
-    Flat(CompleteMap<Flat>),
+struct World {
+    // shared with the requests rendering tiles, which do not hold the store lock
+    map: Arc<CompleteMapEnum>,
+    // changes with every edit of the map, to tell apart cached tiles
+    revision: u64,
+    // configuration the map was generated from, stored in saved files
+    config: Option<Configuration>,
+    // world and region the map was refined from by /refine_region
+    region: Option<RegionSource>,
+    // file the map was loaded from, with the layers that have not been read yet
+    source: Option<(MapReader, Vec<LayerId>)>,
+    // edits applied since the map was generated or loaded, in order
+    edits: Vec<Edit>,
+    // layers changed by the last edits as they were before them, the last one for the last edit
+    undo: VecDeque<LayerSnapshot>,
+    // undone edits with the layers they changed as they were after them, the last one undone last
+    redo: Vec<(Edit, LayerSnapshot)>,
 }
 
-type MapStore = Mutex<HashMap<String, CompleteMapEnum>>;
+// Edits that can be undone. Each one keeps a copy of the layers it changed.
+const HISTORY_LENGTH: usize = 16;
 
-macro_rules! apply_operation {
-    ($pipeline_step:expr, $key:expr, $store:expr) => {{
-        let mut locked_store = $store.lock().unwrap();
-        let Some(cmap_enum): Option<CompleteMapEnum> = locked_store.remove(&$key) else {
-            return Json(Message {
-                message: format!("no map found"),
-            });
+// Some layers of a map, the other layers of `map` being empty
+struct LayerSnapshot {
+    layers: Vec<LayerId>,
+    map: CompleteMapEnum,
+}
+
+// An edit of a world, with what is needed to repeat it with perform_edit
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Edit {
+    operation: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mask: Option<MaskSpec>,
+}
+
+impl Edit {
+    fn new(operation: &str) -> Self {
+        Self {
+            operation: operation.to_string(),
+            seed: None,
+            params: Value::Null,
+            mask: None,
+        }
+    }
+
+    fn masked(self, mask: Option<MaskSpec>) -> Self {
+        Self { mask, ..self }
+    }
+
+    fn seeded(operation: &str, seed: u32) -> Self {
+        Self {
+            seed: Some(seed),
+            ..Self::new(operation)
+        }
+    }
+
+    fn with_params(operation: &str, params: &impl Serialize) -> Self {
+        Self {
+            params: json::to_value(params).unwrap_or(Value::Null),
+            ..Self::new(operation)
+        }
+    }
+
+    fn params<T: DeserializeOwned>(&self) -> MapResult<T> {
+        return json::from_value(self.params.clone()).map_err(|e| {
+            MapError::InvalidData(format!("parameters of {}: {}", self.operation, e))
+        });
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct EditSeed {
+    operation: String,
+    seed: u32,
+}
+
+// The edits of a world and the configuration it was generated from, replayed by /replay
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct EditScript {
+    world_config: Option<Configuration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    region: Option<RegionSource>,
+    edits: Vec<Edit>,
+}
+
+// A region refined from another world, which must be loaded, and unchanged, to replay it
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RegionSource {
+    parent: String,
+    config: RegionConfig,
+}
+
+impl World {
+    fn new(map: CompleteMapEnum, config: Option<Configuration>) -> Self {
+        Self {
+            map: Arc::new(map),
+            revision: next_revision(),
+            config,
+            region: None,
+            source: None,
+            edits: vec![],
+            undo: VecDeque::new(),
+            redo: vec![],
+        }
+    }
+
+    // The requested seed, or one derived from the seed of the world and the number of
+    // random edits, so that repeating the same edits on the same world gives the same map.
+    fn edit_seed(&self, requested: Option<u32>) -> u32 {
+        if let Some(seed) = requested {
+            return seed;
+        }
+        let world_seed = self.config.as_ref().map_or(0, |config| config.seed);
+        let random_edits = self.edits.iter().filter(|edit| edit.seed.is_some()).count();
+        return derive_seed(world_seed as u64, random_edits as u64) as u32;
+    }
+
+    // Records an edit applied to the map, `before` being the map before it and `layers` the
+    // layers the edit changed.
+    fn push_edit(&mut self, edit: Edit, before: &CompleteMapEnum, layers: Vec<LayerId>) {
+        self.edits.push(edit);
+        self.undo.push_back(LayerSnapshot {
+            map: before.copy_layers(&layers),
+            layers,
+        });
+        if self.undo.len() > HISTORY_LENGTH {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+        self.revision = next_revision();
+    }
+
+    fn undo(&mut self) -> Option<&Edit> {
+        let mut snapshot = self.undo.pop_back()?;
+        let edit = self.edits.pop()?;
+        Arc::make_mut(&mut self.map).swap_layers(&mut snapshot.map, &snapshot.layers);
+        self.redo.push((edit, snapshot));
+        self.revision = next_revision();
+        return self.redo.last().map(|(edit, _)| edit);
+    }
+
+    fn redo(&mut self) -> Option<&Edit> {
+        let (edit, mut snapshot) = self.redo.pop()?;
+        Arc::make_mut(&mut self.map).swap_layers(&mut snapshot.map, &snapshot.layers);
+        self.undo.push_back(snapshot);
+        self.edits.push(edit);
+        self.revision = next_revision();
+        return self.edits.last();
+    }
+
+    // Reads the layers that were not loaded yet from the file the map was loaded from.
+    fn ensure_layers(&mut self, layers: &[LayerId]) -> MapResult<()> {
+        let Some((reader, missing)) = &mut self.source else {
+            return Ok(());
         };
-        dbg!(&$pipeline_step);
-        match cmap_enum {
-            CompleteMapEnum::Globe(cmap) => {
-                locked_store.insert($key, CompleteMapEnum::Globe($pipeline_step.apply(&cmap)));
-            }
-            CompleteMapEnum::Cylinder(cmap) => {
-                locked_store.insert($key, CompleteMapEnum::Cylinder($pipeline_step.apply(&cmap)));
-            }
-            CompleteMapEnum::Flat(cmap) => {
-                locked_store.insert($key, CompleteMapEnum::Flat($pipeline_step.apply(&cmap)));
-            }
+        let to_read: Vec<LayerId> = missing
+            .iter()
+            .copied()
+            .filter(|layer| layers.contains(layer))
+            .collect();
+        if !to_read.is_empty() {
+            reader.read_layers_into(Arc::make_mut(&mut self.map), &to_read)?;
+            missing.retain(|layer| !to_read.contains(layer));
+        }
+        if missing.is_empty() {
+            self.source = None;
+        }
+        Ok(())
+    }
+}
+
+// shared with the threads of generation jobs
+type MapStore = Arc<Mutex<HashMap<String, World>>>;
+
+static LAST_REVISION: AtomicU64 = AtomicU64::new(0);
+
+// Increasing, and starting from the current time so that tiles cached by an earlier run
+// of the server are not mistaken for tiles of the maps of this one.
+fn next_revision() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_micros() as u64);
+    let last = LAST_REVISION
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some((last + 1).max(now))
+        })
+        .unwrap_or(0);
+    return (last + 1).max(now);
+}
+
+// View configurations registered with /tile_layerset, with their revision
+struct LayerSet {
+    view_config: Arc<ViewConfiguration>,
+    revision: u64,
+}
+
+type LayerSets = Mutex<HashMap<String, LayerSet>>;
+
+const TILE_CACHE_DIR: &str = "tile_cache";
+// tiles kept for each world, a few zoom levels of a world being about this many tiles
+const MAX_CACHED_TILES: usize = 4096;
+
+// a request that panicked while holding the lock must not make every later request fail
+fn lock_store(store: &MapStore) -> MutexGuard<'_, HashMap<String, World>> {
+    store
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Applies a step of an edit, only inside the mask of the edit if it has one. Returns the
+// layers the step changed.
+fn apply_edit_step<S: MapShape + 'static>(
+    step: impl PipelineStep<S> + 'static,
+    mask: Option<&MaskSpec>,
+    cmap: &mut CompleteMap<S>,
+) -> MapResult<Vec<LayerId>> {
+    let layers = step.writes();
+    match mask {
+        Some(mask) => apply_checked(&Masked::new(Box::new(step), mask.build()?), cmap)?,
+        None => apply_checked(&step, cmap)?,
+    }
+    return Ok(layers);
+}
+
+// Applies the steps to the map of a world as one edit, which can be undone. When a step
+// fails, the map is left as it was.
+macro_rules! apply_edit {
+    ($store:expr, $key:expr, $edit:expr, $($pipeline_step:expr),+ $(,)?) => {{
+        let mut locked_store = lock_store($store);
+        let Some(world): Option<&mut World> = locked_store.get_mut($key) else {
+            return Err(format!("no map found"));
         };
+        world
+            .ensure_layers(&LayerId::ALL)
+            .map_err(|e| format!("Error: {}", e))?;
+        let before = world.map.clone();
+        let cmap_enum = Arc::make_mut(&mut world.map);
+        let result = (|| -> MapResult<Vec<LayerId>> {
+            let mut layers = vec![];
+            $(
+                let step = $pipeline_step;
+                let written = match &mut *cmap_enum {
+                    CompleteMapEnum::Globe(cmap) => {
+                        apply_edit_step(step, $edit.mask.as_ref(), cmap)?
+                    }
+                    CompleteMapEnum::Cylinder(cmap) => {
+                        apply_edit_step(step, $edit.mask.as_ref(), cmap)?
+                    }
+                    CompleteMapEnum::CubeSphere(cmap) => {
+                        apply_edit_step(step, $edit.mask.as_ref(), cmap)?
+                    }
+                    CompleteMapEnum::Flat(cmap) => {
+                        apply_edit_step(step, $edit.mask.as_ref(), cmap)?
+                    }
+                    CompleteMapEnum::Torus(cmap) => {
+                        apply_edit_step(step, $edit.mask.as_ref(), cmap)?
+                    }
+                    CompleteMapEnum::Disc(cmap) => {
+                        apply_edit_step(step, $edit.mask.as_ref(), cmap)?
+                    }
+                };
+                for layer in written {
+                    if !layers.contains(&layer) {
+                        layers.push(layer);
+                    }
+                }
+            )+
+            Ok(layers)
+        })();
+        match result {
+            Ok(layers) => world.push_edit($edit, &before, layers),
+            Err(e) => {
+                world.map = before;
+                return Err(format!("Error: {}", e));
+            }
+        }
     }};
 }
 
@@ -68,56 +371,76 @@ struct RequestData<T> {
     world_name: String,
     request_priority: u8,
     params: T,
+    // limits the edit to part of the world
+    #[serde(default)]
+    mask: Option<MaskSpec>,
 }
 
-fn generate_map(req_data: &GenerationRequest, store: &State<MapStore>) {
+// With a job, the job follows the progress of the pipeline and can cancel it.
+fn generate_map(
+    req_data: &GenerationRequest,
+    store: &MapStore,
+    job: Option<(&Jobs, u64)>,
+) -> MapResult<()> {
     let start = Instant::now();
+    req_data.world_config.validate()?;
     macro_rules! generate_map_with_shape {
         ($shape:ty, $cmap_enum:ident, $config:expr) => {{
-            let cmap: CompleteMap<$shape> = standard_recipe(&req_data.world_config).execute();
+            let mut pipeline = recipe_from_config::<$shape>(&req_data.world_config)?;
+            if let Some((jobs, id)) = job {
+                track_pipeline(&mut pipeline, jobs, id);
+            }
+            let cmap: CompleteMap<$shape> = pipeline.execute()?;
             let end = Instant::now();
             let generation_time = (end - start).as_secs_f32();
             println!("generation time: {generation_time}");
-            let _ = cmap.save("map.bin");
-            store.lock().unwrap().insert(
-                req_data.world_name.clone(),
+            let world = World::new(
                 CompleteMapEnum::$cmap_enum(cmap),
+                Some(req_data.world_config.clone()),
             );
+            let _ = map_file::save(&world.map, world.config.as_ref(), "map.bin");
+            lock_store(store).insert(req_data.world_name.clone(), world);
         }};
     }
     match req_data.world_config.shape {
         ShapeEnum::Cylinder => generate_map_with_shape!(Cylinder, Cylinder, config),
+        ShapeEnum::CubeSphere => generate_map_with_shape!(CubeSphere, CubeSphere, config),
         ShapeEnum::Globe => generate_map_with_shape!(Globe, Globe, config),
         ShapeEnum::Flat => generate_map_with_shape!(Flat, Flat, config),
+        ShapeEnum::Torus => generate_map_with_shape!(Torus, Torus, config),
+        ShapeEnum::Disc => generate_map_with_shape!(Disc, Disc, config),
     }
+    Ok(())
 }
 
 fn generate_map_from_image(
     world_name: String,
-    filepath: String,
+    load_height: LoadHeight,
     shape: &ShapeEnum,
     store: &State<MapStore>,
-) {
+) -> MapResult<()> {
     let start = Instant::now();
     macro_rules! generate_map_with_shape {
         ($shape:ty, $cmap_enum:ident, $config:expr) => {{
-            let cmap: CompleteMap<$shape> = recipe_from_image(filepath).execute();
+            let cmap: CompleteMap<$shape> = recipe_from_image(load_height).execute()?;
             let end = Instant::now();
             let generation_time = (end - start).as_secs_f32();
             println!("generation time: {generation_time}");
-            let _ = cmap.save("map.bin");
+            let world = World::new(CompleteMapEnum::$cmap_enum(cmap), None);
+            let _ = map_file::save(&world.map, None, "map.bin");
             // draw_old_style(&cmap);
-            store
-                .lock()
-                .unwrap()
-                .insert(world_name, CompleteMapEnum::$cmap_enum(cmap));
+            lock_store(store).insert(world_name, world);
         }};
     }
     match shape {
         ShapeEnum::Cylinder => generate_map_with_shape!(Cylinder, Cylinder, config),
+        ShapeEnum::CubeSphere => generate_map_with_shape!(CubeSphere, CubeSphere, config),
         ShapeEnum::Globe => generate_map_with_shape!(Globe, Globe, config),
         ShapeEnum::Flat => generate_map_with_shape!(Flat, Flat, config),
+        ShapeEnum::Torus => generate_map_with_shape!(Torus, Torus, config),
+        ShapeEnum::Disc => generate_map_with_shape!(Disc, Disc, config),
     }
+    Ok(())
 }
 
 #[get("/draw", format = "json", data = "<input>")]
@@ -126,16 +449,24 @@ fn draw(input: Json<RequestData<ViewConfiguration>>, store: &State<MapStore>) ->
     let view_config: ViewConfiguration = req_params.params;
     let key: String = req_params.world_name;
 
-    let locked_store = store.lock().unwrap();
-    let Some(cmap_enum): Option<&CompleteMapEnum> = locked_store.get(&key) else {
+    let mut locked_store = lock_store(store);
+    let Some(world): Option<&mut World> = locked_store.get_mut(&key) else {
         return Json(Message {
             message: format!("no map found"),
         });
     };
-    match cmap_enum {
+    if let Err(e) = world.ensure_layers(&view_config.required_layers()) {
+        return Json(Message {
+            message: format!("Error: {}", e),
+        });
+    }
+    match world.map.as_ref() {
         CompleteMapEnum::Globe(cmap) => draw_with_config(&cmap, &view_config),
         CompleteMapEnum::Cylinder(cmap) => draw_with_config(&cmap, &view_config),
+        CompleteMapEnum::CubeSphere(cmap) => draw_with_config(&cmap, &view_config),
         CompleteMapEnum::Flat(cmap) => draw_with_config(&cmap, &view_config),
+        CompleteMapEnum::Torus(cmap) => draw_with_config(&cmap, &view_config),
+        CompleteMapEnum::Disc(cmap) => draw_with_config(&cmap, &view_config),
     }
     Json(Message {
         message: "Ok".to_string(),
@@ -151,37 +482,180 @@ fn get_image(
         1 => set_current_thread_priority(ThreadPriority::Max),
         _ => set_current_thread_priority(ThreadPriority::Min),
     };
+    // the priority only orders the drawings, which are done without it
+    if let Err(e) = result {
+        warn!("could not set the priority of the drawing thread: {:?}", e);
+    }
 
-    if result.is_ok() {
-        let input_inner = input.into_inner();
+    let input_inner = input.into_inner();
 
-        let locked_store = store.lock().unwrap();
-        let Some(cmap_enum): Option<&CompleteMapEnum> = locked_store.get(&input_inner.world_name)
-        else {
-            return None;
-        };
+    let mut locked_store = lock_store(store);
+    let world: &mut World = locked_store.get_mut(&input_inner.world_name)?;
+    world
+        .ensure_layers(&input_inner.params.required_layers())
+        .ok()?;
+    let cmap_enum: &CompleteMapEnum = &world.map;
 
-        let img = match cmap_enum {
-            CompleteMapEnum::Globe(cmap) => img_from_config(&cmap, &input_inner.params),
-            CompleteMapEnum::Cylinder(cmap) => img_from_config(&cmap, &input_inner.params),
-            CompleteMapEnum::Flat(cmap) => img_from_config(&cmap, &input_inner.params),
-        };
+    let img = match cmap_enum {
+        CompleteMapEnum::Globe(cmap) => img_from_config(&cmap, &input_inner.params),
+        CompleteMapEnum::Cylinder(cmap) => img_from_config(&cmap, &input_inner.params),
+        CompleteMapEnum::CubeSphere(cmap) => img_from_config(&cmap, &input_inner.params),
+        CompleteMapEnum::Flat(cmap) => img_from_config(&cmap, &input_inner.params),
+        CompleteMapEnum::Torus(cmap) => img_from_config(&cmap, &input_inner.params),
+        CompleteMapEnum::Disc(cmap) => img_from_config(&cmap, &input_inner.params),
+    };
 
-        let mut buffer = Vec::new();
-        if image::codecs::png::PngEncoder::new(&mut buffer)
-            .encode(&img, img.width(), img.height(), image::ColorType::Rgba8)
-            .is_ok()
-        {
-            Some((ContentType::PNG, buffer))
-        } else {
-            None
-        }
+    let mut buffer = Vec::new();
+    if image::codecs::png::PngEncoder::new(&mut buffer)
+        .encode(&img, img.width(), img.height(), image::ColorType::Rgba8)
+        .is_ok()
+    {
+        Some((ContentType::PNG, buffer))
     } else {
-        dbg!("Failed to set priority.");
         None
     }
 }
 
+// Rivers, coastlines, plate boundaries and hotspots as a GeoJSON FeatureCollection
+#[get("/get_geojson", format = "json", data = "<input>")]
+fn get_geojson(
+    input: Json<BasicRequestParams>,
+    store: &State<MapStore>,
+) -> Option<(ContentType, String)> {
+    let key = input.into_inner().world_name;
+    let mut locked_store = lock_store(store);
+    let world: &mut World = locked_store.get_mut(&key)?;
+    world
+        .ensure_layers(&[
+            LayerId::Height,
+            LayerId::Rivers,
+            LayerId::TectonicPlates,
+            LayerId::Features,
+        ])
+        .ok()?;
+    let geojson = match world.map.as_ref() {
+        CompleteMapEnum::Globe(cmap) => geojson::map_to_geojson(cmap),
+        CompleteMapEnum::Cylinder(cmap) => geojson::map_to_geojson(cmap),
+        CompleteMapEnum::CubeSphere(cmap) => geojson::map_to_geojson(cmap),
+        CompleteMapEnum::Flat(cmap) => geojson::map_to_geojson(cmap),
+        CompleteMapEnum::Torus(cmap) => geojson::map_to_geojson(cmap),
+        CompleteMapEnum::Disc(cmap) => geojson::map_to_geojson(cmap),
+    };
+    Some((ContentType::JSON, geojson.to_string()))
+}
+
+#[derive(Deserialize)]
+struct LayerSetInput {
+    name: String,
+    params: ViewConfiguration,
+}
+
+// Registers a view configuration to be used by /tiles under the given name
+#[post("/tile_layerset", format = "json", data = "<input>")]
+fn tile_layerset(input: Json<LayerSetInput>, layersets: &State<LayerSets>) -> Json<Message> {
+    let input_inner = input.into_inner();
+    if !is_valid_name(&input_inner.name) {
+        return Json(Message {
+            message: format!("Error: invalid layer set name {}", input_inner.name),
+        });
+    }
+    let layerset = LayerSet {
+        view_config: Arc::new(input_inner.params),
+        revision: next_revision(),
+    };
+    layersets
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(input_inner.name, layerset);
+    Json(Message {
+        message: "Ok".to_string(),
+    })
+}
+
+// Web Mercator tiles for Leaflet or OpenLayers. The layer set is either a name registered
+// with /tile_layerset or a comma separated list of layers, as in "satellite,rivers".
+// The store is only locked to read the missing layers, tiles are rendered without the lock.
+// Unknown worlds and tiles out of range are 404s, failing to read or render a tile a 500.
+#[get("/tiles/<world_name>/<layerset>/<zoom>/<x>/<y>")]
+#[allow(clippy::too_many_arguments)]
+fn tiles(
+    world_name: &str,
+    layerset: &str,
+    zoom: u32,
+    x: u32,
+    y: &str,
+    store: &State<MapStore>,
+    layersets: &State<LayerSets>,
+    cache: &State<TileCache>,
+) -> Result<Option<(ContentType, Vec<u8>)>, Custom<String>> {
+    let Some(y) = y.strip_suffix(".png").and_then(|y| y.parse::<u32>().ok()) else {
+        return Ok(None);
+    };
+    if zoom > MAX_ZOOM || x >= 1 << zoom || y >= 1 << zoom {
+        return Ok(None);
+    }
+    if !is_valid_name(world_name) || !is_valid_name(layerset) {
+        return Ok(None);
+    }
+
+    let (view_config, layerset_revision) = match layersets
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(layerset)
+    {
+        Some(registered) => (registered.view_config.clone(), registered.revision),
+        None => {
+            let layers = layerset.split(',').map(String::from).collect();
+            (Arc::new(ViewConfiguration::with_layers(layers)), 0)
+        }
+    };
+
+    let (map, world_revision) = {
+        let mut locked_store = lock_store(store);
+        let Some(world) = locked_store.get_mut(world_name) else {
+            return Ok(None);
+        };
+        world
+            .ensure_layers(&view_config.required_layers())
+            .map_err(internal_error)?;
+        (world.map.clone(), world.revision)
+    };
+
+    let key = TileKey {
+        world: world_name,
+        world_revision,
+        layerset,
+        layerset_revision,
+        zoom,
+        x,
+        y,
+    };
+    if let Some(png) = cache.get(&key) {
+        return Ok(Some((ContentType::PNG, png)));
+    }
+
+    let img = match map.as_ref() {
+        CompleteMapEnum::Globe(cmap) => tile_from_config(cmap, &view_config, zoom, x, y),
+        CompleteMapEnum::Cylinder(cmap) => tile_from_config(cmap, &view_config, zoom, x, y),
+        CompleteMapEnum::CubeSphere(cmap) => tile_from_config(cmap, &view_config, zoom, x, y),
+        CompleteMapEnum::Flat(cmap) => tile_from_config(cmap, &view_config, zoom, x, y),
+        CompleteMapEnum::Torus(cmap) => tile_from_config(cmap, &view_config, zoom, x, y),
+        CompleteMapEnum::Disc(cmap) => tile_from_config(cmap, &view_config, zoom, x, y),
+    };
+    let mut buffer = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut buffer)
+        .encode(&img, img.width(), img.height(), image::ColorType::Rgba8)
+        .map_err(|e| internal_error(e.into()))?;
+    if let Err(e) = cache.put(&key, &buffer) {
+        println!("could not cache tile: {}", e);
+    }
+    Ok(Some((ContentType::PNG, buffer)))
+}
+
+fn internal_error(e: MapError) -> Custom<String> {
+    Custom(Status::InternalServerError, e.to_string())
+}
+
 #[derive(Deserialize)]
 struct SaveInput {
     world_name: String,
@@ -192,25 +666,68 @@ struct SaveInput {
 fn save(input: Json<SaveInput>, store: &State<MapStore>) -> Json<Message> {
     let input_inner: SaveInput = input.into_inner();
 
-    let locked_store = store.lock().unwrap();
-    let Some(cmap_enum): Option<&CompleteMapEnum> = locked_store.get(&input_inner.world_name)
-    else {
+    let mut locked_store = lock_store(store);
+    let Some(world): Option<&mut World> = locked_store.get_mut(&input_inner.world_name) else {
+        return Json(Message {
+            message: format!("no map found"),
+        });
+    };
+    let filepath = input_inner.path.as_str();
+    if let Err(e) = world.ensure_layers(&LayerId::ALL) {
+        return Json(Message {
+            message: format!("Error: Failed to save map: {}", e),
+        });
+    }
+    match map_file::save(&world.map, world.config.as_ref(), filepath) {
+        Ok(()) => Json(Message {
+            message: "Ok".to_string(),
+        }),
+        Err(e) => Json(Message {
+            message: format!("Error: Failed to save map: {}", e),
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportInput {
+    world_name: String,
+    layer: String,
+    path: String,
+}
+
+// The format is picked from the extension of the path: .png, .r16, .raw or .tif
+#[post("/export", format = "json", data = "<input>")]
+fn export_layer(input: Json<ExportInput>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner: ExportInput = input.into_inner();
+
+    let mut locked_store = lock_store(store);
+    let Some(world): Option<&mut World> = locked_store.get_mut(&input_inner.world_name) else {
         return Json(Message {
             message: format!("no map found"),
         });
     };
+    let layer_ids: Vec<LayerId> = LayerId::from_name(&input_inner.layer).into_iter().collect();
+    if let Err(e) = world.ensure_layers(&layer_ids) {
+        return Json(Message {
+            message: format!("Error: {}", e),
+        });
+    }
+    let layer = input_inner.layer.as_str();
     let filepath = input_inner.path.as_str();
-    let result = match cmap_enum {
-        CompleteMapEnum::Globe(cmap) => cmap.save(filepath),
-        CompleteMapEnum::Cylinder(cmap) => cmap.save(filepath),
-        CompleteMapEnum::Flat(cmap) => cmap.save(filepath),
+    let result = match world.map.as_ref() {
+        CompleteMapEnum::Globe(cmap) => heightmap::export_layer(cmap, layer, filepath),
+        CompleteMapEnum::Cylinder(cmap) => heightmap::export_layer(cmap, layer, filepath),
+        CompleteMapEnum::CubeSphere(cmap) => heightmap::export_layer(cmap, layer, filepath),
+        CompleteMapEnum::Flat(cmap) => heightmap::export_layer(cmap, layer, filepath),
+        CompleteMapEnum::Torus(cmap) => heightmap::export_layer(cmap, layer, filepath),
+        CompleteMapEnum::Disc(cmap) => heightmap::export_layer(cmap, layer, filepath),
     };
     match result {
-        Ok(()) => Json(Message {
+        Ok(_) => Json(Message {
             message: "Ok".to_string(),
         }),
-        _ => Json(Message {
-            message: "Error: Failed to save map".to_string(),
+        Err(e) => Json(Message {
+            message: format!("Error: Failed to export {}: {}", layer, e),
         }),
     }
 }
@@ -231,42 +748,206 @@ struct GenerationRequest {
 #[post("/generate", format = "json", data = "<input>")]
 fn generate(input: Json<GenerationRequest>, store: &State<MapStore>) -> Json<Message> {
     let input_inner: GenerationRequest = input.into_inner();
-    generate_map(&input_inner, store);
+    if let Err(e) = generate_map(&input_inner, store, None) {
+        return Json(Message {
+            message: format!("Error: {}", e),
+        });
+    }
     Json(Message {
         message: format!("Generated map {}", input_inner.world_name),
     })
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
+struct JobStarted {
+    job_id: u64,
+}
+
+// Generates the map in a background thread. The progress is followed with /jobs/<id>,
+// and the world is available under its name once the job is finished. The status of the
+// job can be read for 10 minutes after it ends.
+#[post("/generate_job", format = "json", data = "<input>")]
+fn generate_job(
+    input: Json<GenerationRequest>,
+    store: &State<MapStore>,
+    jobs: &State<Jobs>,
+) -> Json<JobStarted> {
+    let input_inner: GenerationRequest = input.into_inner();
+    let job_id = start_job(jobs, &input_inner.world_name);
+    let store = store.inner().clone();
+    let jobs = jobs.inner().clone();
+    thread::spawn(move || {
+        let result = generate_map(&input_inner, &store, Some((&jobs, job_id)));
+        finish_job(&jobs, job_id, result);
+    });
+    Json(JobStarted { job_id })
+}
+
+#[get("/jobs/<id>")]
+fn get_job(id: u64, jobs: &State<Jobs>) -> Option<Json<JobStatus>> {
+    job_status(jobs, id).map(Json)
+}
+
+// The job stops before its next step, the running step is not interrupted.
+#[post("/jobs/<id>/cancel")]
+fn cancel(id: u64, jobs: &State<Jobs>) -> Json<Message> {
+    if !cancel_job(jobs, id) {
+        return Json(Message {
+            message: format!("Error: job {} is not running", id),
+        });
+    }
+    Json(Message {
+        message: "Ok".to_string(),
+    })
+}
+
+#[derive(Deserialize)]
+struct RegionRequest {
+    world_name: String,
+    region_name: String,
+    params: RegionConfig,
+}
+
+// Refines a region of a world into a new flat world. The world itself is not changed.
+fn refine_region_map(req_data: &RegionRequest, store: &MapStore) -> MapResult<()> {
+    let start = Instant::now();
+    let (parent, seed) = {
+        let mut locked_store = lock_store(store);
+        let Some(world) = locked_store.get_mut(&req_data.world_name) else {
+            return Err(MapError::InvalidData(format!(
+                "no map found named {}",
+                req_data.world_name
+            )));
+        };
+        world.ensure_layers(&LayerId::ALL)?;
+        // the seed of the world, as in the CLI, so that both refine regions the same way
+        (
+            world.map.clone(),
+            world.config.as_ref().map_or(0, |c| c.seed),
+        )
+    };
+    // the parent is copied so that the store is not locked during the generation
+    let cmap: CompleteMap<Flat> = match parent.as_ref() {
+        CompleteMapEnum::Globe(cmap) => {
+            region_recipe(Arc::new(cmap.clone()), &req_data.params, seed)?.execute()?
+        }
+        CompleteMapEnum::Cylinder(cmap) => {
+            region_recipe(Arc::new(cmap.clone()), &req_data.params, seed)?.execute()?
+        }
+        CompleteMapEnum::CubeSphere(cmap) => {
+            region_recipe(Arc::new(cmap.clone()), &req_data.params, seed)?.execute()?
+        }
+        CompleteMapEnum::Flat(cmap) => {
+            region_recipe(Arc::new(cmap.clone()), &req_data.params, seed)?.execute()?
+        }
+        CompleteMapEnum::Torus(cmap) => {
+            region_recipe(Arc::new(cmap.clone()), &req_data.params, seed)?.execute()?
+        }
+        CompleteMapEnum::Disc(cmap) => {
+            region_recipe(Arc::new(cmap.clone()), &req_data.params, seed)?.execute()?
+        }
+    };
+    let end = Instant::now();
+    let generation_time = (end - start).as_secs_f32();
+    println!("generation time: {generation_time}");
+    let mut world = World::new(CompleteMapEnum::Flat(cmap), None);
+    world.region = Some(RegionSource {
+        parent: req_data.world_name.clone(),
+        config: req_data.params.clone(),
+    });
+    lock_store(store).insert(req_data.region_name.clone(), world);
+    Ok(())
+}
+
+#[post("/refine_region", format = "json", data = "<input>")]
+fn refine_region(input: Json<RegionRequest>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner: RegionRequest = input.into_inner();
+    if let Err(e) = refine_region_map(&input_inner, store) {
+        return Json(Message {
+            message: format!("Error: {}", e),
+        });
+    }
+    Json(Message {
+        message: format!(
+            "Refined a region of {} into {}",
+            input_inner.world_name, input_inner.region_name
+        ),
+    })
+}
+
+#[derive(Deserialize)]
 struct LoadConfig {
     world_name: String,
     file: String,
     shape: ShapeEnum,
+    // how heightmaps given to /generate_from_image are converted to meters
+    #[serde(default)]
+    mapping: Option<ValueMapping>,
+    #[serde(default)]
+    raw_width: Option<usize>,
 }
 
 #[post("/generate_from_image", format = "json", data = "<input>")]
 fn generate_from_image(input: Json<LoadConfig>, store: &State<MapStore>) -> Json<Message> {
     let input_inner: LoadConfig = input.into_inner();
-    generate_map_from_image(
+    let mut load_height = LoadHeight::new(input_inner.file);
+    load_height.mapping = input_inner.mapping;
+    load_height.raw_width = input_inner.raw_width;
+    if let Err(e) = generate_map_from_image(
         input_inner.world_name,
-        input_inner.file,
+        load_height,
         &input_inner.shape,
         store,
-    );
+    ) {
+        return Json(Message {
+            message: format!("Error: {}", e),
+        });
+    }
     Json(Message {
         message: format!("Loaded height map!"),
     })
 }
 
+// Only the height and coastline are read, other layers are read when a request needs them.
+fn load_world(filename: &str, legacy_shape: ShapeEnum) -> MapResult<World> {
+    let mut reader = MapReader::open(filename, legacy_shape)?;
+    if !reader.is_lazy() {
+        let map = reader.read_all()?;
+        return Ok(World::new(map, reader.header.config));
+    }
+    let initial_layers = [LayerId::Height, LayerId::Coastline];
+    let map = reader.read_layers(&initial_layers)?;
+    let missing = LayerId::ALL
+        .iter()
+        .copied()
+        .filter(|layer| !initial_layers.contains(layer))
+        .collect();
+    Ok(World {
+        map: Arc::new(map),
+        revision: next_revision(),
+        config: reader.header.config.clone(),
+        region: None,
+        source: Some((reader, missing)),
+        edits: vec![],
+        undo: VecDeque::new(),
+        redo: vec![],
+    })
+}
+
 #[post("/load", format = "json", data = "<input>")]
 fn load_map(input: Json<LoadConfig>, store: &State<MapStore>) -> Json<Message> {
     let input_inner: LoadConfig = input.into_inner();
     let filename = input_inner.file.as_str();
-    let cmap = load::<Globe>(filename);
-    store
-        .lock()
-        .unwrap()
-        .insert(input_inner.world_name, CompleteMapEnum::Globe(cmap));
+    // the shape of the request is only used for files saved without a header
+    let world = match load_world(filename, input_inner.shape) {
+        Ok(world) => world,
+        Err(e) => {
+            return Json(Message {
+                message: format!("Error: could not load {}: {}", filename, e),
+            })
+        }
+    };
+    lock_store(store).insert(input_inner.world_name, world);
     Json(Message {
         message: format!("Loaded {}!", filename),
     })
@@ -275,22 +956,21 @@ fn load_map(input: Json<LoadConfig>, store: &State<MapStore>) -> Json<Message> {
 #[post("/load_custom_layer", format = "json", data = "<input>")]
 fn load_custom_layer(input: Json<LoadConfig>, store: &State<MapStore>) -> Json<Message> {
     let input_inner: LoadConfig = input.into_inner();
-    let key = input_inner.world_name;
-    let filename = String::from(input_inner.file.as_str());
-    apply_operation!(LoadCustomLayer::new(filename.clone()), key.clone(), store);
-    Json(Message {
-        message: format!("Loaded layer {}!", filename),
-    })
+    let edit = Edit::with_params("load_custom_layer", &input_inner.file);
+    return edit_response(perform_edit(store, &input_inner.world_name, edit));
 }
 
 #[get("/get_layers", format = "json", data = "<input>")]
 fn get_layers(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Vec<String>> {
     let key = input.into_inner().world_name;
-    let locked_store = store.lock().unwrap();
-    let Some(cmap_enum) = &locked_store.get(&key) else {
+    let mut locked_store = lock_store(store);
+    let Some(world) = locked_store.get_mut(&key) else {
         return Json(vec![]);
     };
-    match cmap_enum {
+    if world.ensure_layers(&[LayerId::CustomLayers]).is_err() {
+        return Json(vec![]);
+    }
+    match world.map.as_ref() {
         CompleteMapEnum::Globe(cmap) => {
             let mut layers = vec![];
             for key in cmap.custom_pmaps.keys() {
@@ -305,6 +985,13 @@ fn get_layers(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<
             }
             Json(layers)
         }
+        CompleteMapEnum::CubeSphere(cmap) => {
+            let mut layers = vec![];
+            for key in cmap.custom_pmaps.keys() {
+                layers.push(key.clone());
+            }
+            Json(layers)
+        }
         CompleteMapEnum::Flat(cmap) => {
             let mut layers = vec![];
             for key in cmap.custom_pmaps.keys() {
@@ -312,74 +999,338 @@ fn get_layers(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<
             }
             Json(layers)
         }
+        CompleteMapEnum::Torus(cmap) => {
+            let mut layers = vec![];
+            for key in cmap.custom_pmaps.keys() {
+                layers.push(key.clone());
+            }
+            Json(layers)
+        }
+        CompleteMapEnum::Disc(cmap) => {
+            let mut layers = vec![];
+            for key in cmap.custom_pmaps.keys() {
+                layers.push(key.clone());
+            }
+            Json(layers)
+        }
     }
 }
 
+#[derive(Deserialize)]
+struct EditRequestParams {
+    world_name: String,
+    #[serde(default)]
+    seed: Option<u32>,
+    #[serde(default)]
+    mask: Option<MaskSpec>,
+}
+
+fn edit_seed(store: &MapStore, world_name: &str, requested: Option<u32>) -> Option<u32> {
+    return lock_store(store)
+        .get(world_name)
+        .map(|world| world.edit_seed(requested));
+}
+
+// Applies an edit made through the endpoints below, or read from an edit script.
+// Returns the message of the endpoint, or of the error.
+fn perform_edit(store: &MapStore, key: &str, edit: Edit) -> Result<String, String> {
+    let seed = || {
+        edit.seed
+            .ok_or_else(|| format!("Error: {} needs a seed", edit.operation))
+    };
+    match edit.operation.as_str() {
+        "add_noise" => {
+            let seed = seed()?;
+            apply_edit!(
+                store,
+                key,
+                edit,
+                HeightNoise::new(seed, 30.0, 70.0),
+                HeightNoise::new(seed.wrapping_add(1), 60.0, 70.0),
+                HeightNoise::new(seed.wrapping_add(2), 100.0, 40.0),
+                HeightNoise::new(seed.wrapping_add(3), 200.0, 20.0),
+                DefineCoastline {},
+            );
+            Ok(format!("Successfully added noise with seed {}", seed))
+        }
+        "smooth" => {
+            apply_edit!(store, key, edit, Smooth::new());
+            Ok("Successfully applied smooth".to_string())
+        }
+        "erosion" => {
+            apply_edit!(
+                store,
+                key,
+                edit,
+                HydraulicErosion::new(4),
+                DefineCoastline {}
+            );
+            Ok("Successfully added erosion".to_string())
+        }
+        "resize" => {
+            let step: Resize = edit.params().map_err(|e| format!("Error: {}", e))?;
+            apply_edit!(store, key, edit, step, DefineCoastline {});
+            Ok("Successfully resized".to_string())
+        }
+        "translation_noise" => {
+            let seed = seed()?;
+            apply_edit!(store, key, edit, TranslationNoise::new(seed));
+            Ok(format!(
+                "Successfully added translation noise with seed {}",
+                seed
+            ))
+        }
+        "calculate_climate" => {
+            let climate_config: ClimateConfiguration =
+                edit.params().map_err(|e| format!("Error: {}", e))?;
+            let step = CalculateClimate::new(
+                climate_config.equator_temperature,
+                climate_config.pole_temperature,
+                climate_config.humidity,
+            );
+            apply_edit!(store, key, edit, step);
+            Ok("Successfully calculated the climate".to_string())
+        }
+        "adjust_water_percentage" => {
+            let step: WaterLevel = edit.params().map_err(|e| format!("Error: {}", e))?;
+            apply_edit!(store, key, edit, step, DefineCoastline {});
+            Ok("Successfully adjusted the water percentage".to_string())
+        }
+        "brush" => {
+            let step: Brush = edit.params().map_err(|e| format!("Error: {}", e))?;
+            apply_edit!(store, key, edit, step);
+            Ok("Successfully applied the brush".to_string())
+        }
+        "load_custom_layer" => {
+            let filename: String = edit.params().map_err(|e| format!("Error: {}", e))?;
+            apply_edit!(store, key, edit, LoadCustomLayer::new(filename.clone()));
+            Ok(format!("Loaded layer {}!", filename))
+        }
+        operation => Err(format!("Error: unknown edit {}", operation)),
+    }
+}
+
+fn edit_response(result: Result<String, String>) -> Json<Message> {
+    let message = match result {
+        Ok(message) => message,
+        Err(message) => message,
+    };
+    Json(Message { message })
+}
+
 #[post("/add_noise", format = "json", data = "<input>")]
-fn add_noise(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Message> {
-    let key = input.into_inner().world_name;
-    apply_operation!(
-        HeightNoise::new(rand::random(), 30.0, 70.0),
-        key.clone(),
-        store
-    );
-    apply_operation!(
-        HeightNoise::new(rand::random(), 60.0, 70.0),
-        key.clone(),
-        store
-    );
-    apply_operation!(
-        HeightNoise::new(rand::random(), 100.0, 40.0),
-        key.clone(),
-        store
-    );
-    apply_operation!(
-        HeightNoise::new(rand::random(), 200.0, 20.0),
-        key.clone(),
-        store
-    );
-    apply_operation!(DefineCoastline {}, key, store);
-    Json(Message {
-        message: "Successfully added noise".to_string(),
-    })
+fn add_noise(input: Json<EditRequestParams>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner = input.into_inner();
+    let key = input_inner.world_name;
+    let Some(seed) = edit_seed(store, &key, input_inner.seed) else {
+        return Json(Message {
+            message: format!("no map found"),
+        });
+    };
+    let edit = Edit::seeded("add_noise", seed).masked(input_inner.mask);
+    return edit_response(perform_edit(store, &key, edit));
 }
 
 #[post("/smooth", format = "json", data = "<input>")]
-fn smooth(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Message> {
-    let key = input.into_inner().world_name;
-    apply_operation!(Smooth::new(), key, store);
-    Json(Message {
-        message: "Successfully applied smooth".to_string(),
-    })
+fn smooth(input: Json<EditRequestParams>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner = input.into_inner();
+    let edit = Edit::new("smooth").masked(input_inner.mask);
+    return edit_response(perform_edit(store, &input_inner.world_name, edit));
 }
 
 #[post("/erosion", format = "json", data = "<input>")]
-fn erosion(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Message> {
-    let key = input.into_inner().world_name;
-    apply_operation!(HydraulicErosion::new(4), key.clone(), store);
-    apply_operation!(DefineCoastline {}, key, store);
-    Json(Message {
-        message: "Successfully added erosion".to_string(),
-    })
+fn erosion(input: Json<EditRequestParams>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner = input.into_inner();
+    let edit = Edit::new("erosion").masked(input_inner.mask);
+    return edit_response(perform_edit(store, &input_inner.world_name, edit));
 }
 
 #[post("/resize", format = "json", data = "<input>")]
 fn resize(input: Json<RequestData<Resize>>, store: &State<MapStore>) -> Json<Message> {
     let inner_input = input.into_inner();
-    let key = inner_input.world_name;
-    apply_operation!(inner_input.params, key.clone(), store);
-    apply_operation!(DefineCoastline {}, key, store);
-    Json(Message {
-        message: "Successfully resized".to_string(),
-    })
+    let edit = Edit::with_params("resize", &inner_input.params).masked(inner_input.mask);
+    return edit_response(perform_edit(store, &inner_input.world_name, edit));
 }
 
 #[post("/translation_noise", format = "json", data = "<input>")]
-fn translation_noise(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Message> {
+fn translation_noise(input: Json<EditRequestParams>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner = input.into_inner();
+    let key = input_inner.world_name;
+    let Some(seed) = edit_seed(store, &key, input_inner.seed) else {
+        return Json(Message {
+            message: format!("no map found"),
+        });
+    };
+    let edit = Edit::seeded("translation_noise", seed).masked(input_inner.mask);
+    return edit_response(perform_edit(store, &key, edit));
+}
+
+// Local edit of the height, e.g. from the brushes of the GUI
+#[post("/brush", format = "json", data = "<input>")]
+fn brush(input: Json<RequestData<Brush>>, store: &State<MapStore>) -> Json<Message> {
+    let inner_input = input.into_inner();
+    let key = inner_input.world_name;
+    let mut step = inner_input.params;
+    let mut seed = None;
+    if let BrushOperation::Noise {
+        seed: noise_seed, ..
+    } = &mut step.operation
+    {
+        let Some(world_seed) = edit_seed(store, &key, *noise_seed) else {
+            return Json(Message {
+                message: format!("no map found"),
+            });
+        };
+        *noise_seed = Some(world_seed);
+        seed = Some(world_seed);
+    }
+    let edit = Edit {
+        seed,
+        ..Edit::with_params("brush", &step).masked(inner_input.mask)
+    };
+    return edit_response(perform_edit(store, &key, edit));
+}
+
+// Seeds of the random edits of a world, to repeat them on another machine
+#[get("/get_edit_seeds", format = "json", data = "<input>")]
+fn get_edit_seeds(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Vec<EditSeed>> {
     let key = input.into_inner().world_name;
-    apply_operation!(TranslationNoise::new(rand::random()), key, store);
+    let locked_store = lock_store(store);
+    let Some(world) = locked_store.get(&key) else {
+        return Json(vec![]);
+    };
+    Json(
+        world
+            .edits
+            .iter()
+            .filter_map(|edit| {
+                Some(EditSeed {
+                    operation: edit.operation.clone(),
+                    seed: edit.seed?,
+                })
+            })
+            .collect(),
+    )
+}
+
+#[post("/undo", format = "json", data = "<input>")]
+fn undo(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Message> {
+    let key = input.into_inner().world_name;
+    let mut locked_store = lock_store(store);
+    let Some(world) = locked_store.get_mut(&key) else {
+        return Json(Message {
+            message: format!("no map found"),
+        });
+    };
+    let message = match world.undo() {
+        Some(edit) => format!("Undid {}", edit.operation),
+        None => "Error: nothing to undo".to_string(),
+    };
+    Json(Message { message })
+}
+
+#[post("/redo", format = "json", data = "<input>")]
+fn redo(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Message> {
+    let key = input.into_inner().world_name;
+    let mut locked_store = lock_store(store);
+    let Some(world) = locked_store.get_mut(&key) else {
+        return Json(Message {
+            message: format!("no map found"),
+        });
+    };
+    let message = match world.redo() {
+        Some(edit) => format!("Redid {}", edit.operation),
+        None => "Error: nothing to redo".to_string(),
+    };
+    Json(Message { message })
+}
+
+// Writes the generation configuration and the edits of a world as a json edit script
+#[post("/export_history", format = "json", data = "<input>")]
+fn export_history(input: Json<SaveInput>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner: SaveInput = input.into_inner();
+    let locked_store = lock_store(store);
+    let Some(world) = locked_store.get(&input_inner.world_name) else {
+        return Json(Message {
+            message: format!("no map found"),
+        });
+    };
+    let script = EditScript {
+        world_config: world.config.clone(),
+        region: world.region.clone(),
+        edits: world.edits.clone(),
+    };
+    let result = json::to_pretty_string(&script)
+        .map_err(|e| e.to_string())
+        .and_then(|data| fs::write(&input_inner.path, data).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => Json(Message {
+            message: "Ok".to_string(),
+        }),
+        Err(e) => Json(Message {
+            message: format!("Error: Failed to export the history: {}", e),
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReplayInput {
+    world_name: String,
+    file: String,
+}
+
+// Generates a world from an edit script written by /export_history and applies its edits
+#[post("/replay", format = "json", data = "<input>")]
+fn replay(input: Json<ReplayInput>, store: &State<MapStore>) -> Json<Message> {
+    let input_inner: ReplayInput = input.into_inner();
+    let script: EditScript = match fs::read_to_string(&input_inner.file)
+        .map_err(|e| e.to_string())
+        .and_then(|data| json::from_str(&data).map_err(|e| e.to_string()))
+    {
+        Ok(script) => script,
+        Err(e) => {
+            return Json(Message {
+                message: format!("Error: could not read {}: {}", input_inner.file, e),
+            })
+        }
+    };
+    let result = match (script.world_config, script.region) {
+        (Some(world_config), _) => {
+            let request = GenerationRequest {
+                world_name: input_inner.world_name.clone(),
+                world_config,
+            };
+            generate_map(&request, store, None)
+        }
+        (None, Some(region)) => {
+            let request = RegionRequest {
+                world_name: region.parent,
+                region_name: input_inner.world_name.clone(),
+                params: region.config,
+            };
+            refine_region_map(&request, store)
+        }
+        (None, None) => {
+            return Json(Message {
+                message: "Error: the edit script has no generation configuration".to_string(),
+            })
+        }
+    };
+    if let Err(e) = result {
+        return Json(Message {
+            message: format!("Error: {}", e),
+        });
+    }
+    let n_edits = script.edits.len();
+    for (i, edit) in script.edits.into_iter().enumerate() {
+        if let Err(message) = perform_edit(store, &input_inner.world_name, edit) {
+            return Json(Message {
+                message: format!("edit {}: {}", i, message),
+            });
+        }
+    }
     Json(Message {
-        message: "Successfully added erosion".to_string(),
+        message: format!("Replayed {} edits on {}", n_edits, input_inner.world_name),
     })
 }
 
@@ -389,17 +1340,16 @@ fn post_calculate_climate(
     store: &State<MapStore>,
 ) -> Json<Message> {
     let inner_input = input.into_inner();
-    let key = inner_input.world_name.clone();
-    let climate_config = inner_input.params;
-    let operation = CalculateClimate::new(
-        climate_config.equator_temperature,
-        climate_config.pole_temperature,
-        climate_config.humidity,
-    );
-    apply_operation!(operation, key, store);
-    let locked_store = store.lock().unwrap();
     let key = inner_input.world_name;
-    let Some(cmap_enum): Option<&CompleteMapEnum> = locked_store.get(&key) else {
+    let edit = Edit::with_params("calculate_climate", &inner_input.params).masked(inner_input.mask);
+    let message = match perform_edit(store, &key, edit) {
+        Ok(message) => message,
+        Err(message) => return Json(Message { message }),
+    };
+    let locked_store = lock_store(store);
+    let Some(cmap_enum): Option<&CompleteMapEnum> =
+        locked_store.get(&key).map(|world| world.map.as_ref())
+    else {
         return Json(Message {
             message: format!("no map found"),
         });
@@ -412,13 +1362,20 @@ fn post_calculate_climate(
         CompleteMapEnum::Cylinder(cmap) => {
             draw_precipitation(&cmap);
         }
+        CompleteMapEnum::CubeSphere(cmap) => {
+            draw_precipitation(&cmap);
+        }
         CompleteMapEnum::Flat(cmap) => {
             draw_precipitation(&cmap);
         }
+        CompleteMapEnum::Torus(cmap) => {
+            draw_precipitation(&cmap);
+        }
+        CompleteMapEnum::Disc(cmap) => {
+            draw_precipitation(&cmap);
+        }
     };
-    Json(Message {
-        message: "Successfully added erosion".to_string(),
-    })
+    Json(Message { message })
 }
 
 #[post("/adjust_water_percentage", format = "json", data = "<input>")]
@@ -427,12 +1384,9 @@ fn adjust_water_percentage(
     store: &State<MapStore>,
 ) -> Json<Message> {
     let input_inner = input.into_inner();
-    let key = input_inner.world_name;
-    apply_operation!(input_inner.params, key.clone(), store);
-    apply_operation!(DefineCoastline {}, key, store);
-    Json(Message {
-        message: "Successfully added erosion".to_string(),
-    })
+    let edit =
+        Edit::with_params("adjust_water_percentage", &input_inner.params).masked(input_inner.mask);
+    return edit_response(perform_edit(store, &input_inner.world_name, edit));
 }
 
 #[derive(Serialize)]
@@ -451,9 +1405,8 @@ fn get_size(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Di
     let result = set_current_thread_priority(ThreadPriority::Max);
     if result.is_ok() {
         let key = input.into_inner().world_name;
-        let locked_store: std::sync::MutexGuard<'_, HashMap<String, CompleteMapEnum>> =
-            store.lock().unwrap();
-        let Some(cmap_enum) = &locked_store.get(&key) else {
+        let locked_store: MutexGuard<'_, HashMap<String, World>> = lock_store(store);
+        let Some(cmap_enum) = locked_store.get(&key).map(|world| world.map.as_ref()) else {
             return Json(Dimensions {
                 width: 0,
                 height: 0,
@@ -468,30 +1421,56 @@ fn get_size(input: Json<BasicRequestParams>, store: &State<MapStore>) -> Json<Di
                 width: cmap.height.values[0].len(),
                 height: cmap.height.values.len(),
             }),
+            CompleteMapEnum::CubeSphere(cmap) => Json(Dimensions {
+                width: cmap.height.circunference,
+                height: cmap.height.height,
+            }),
             CompleteMapEnum::Flat(cmap) => Json(Dimensions {
                 width: cmap.height.values[0].len(),
                 height: cmap.height.values.len(),
             }),
+            CompleteMapEnum::Torus(cmap) => Json(Dimensions {
+                width: cmap.height.values[0].len(),
+                height: cmap.height.values.len(),
+            }),
+            CompleteMapEnum::Disc(cmap) => Json(Dimensions {
+                width: cmap.height.values[0].len(),
+                height: cmap.height.values.len(),
+            }),
         }
     } else {
-        todo!()
+        Json(Dimensions {
+            width: 0,
+            height: 0,
+        })
     }
 }
 
 #[launch]
 fn rocket() -> _ {
     rocket::build()
-        .manage(Mutex::new(HashMap::<String, CompleteMapEnum>::new()))
+        .manage(Arc::new(Mutex::new(HashMap::<String, World>::new())))
+        .manage(Jobs::default())
+        .manage(Mutex::new(HashMap::<String, LayerSet>::new()))
+        .manage(TileCache::new(TILE_CACHE_DIR, MAX_CACHED_TILES))
         .mount(
             "/",
             routes![
                 save,
+                export_layer,
                 generate,
+                generate_job,
+                get_job,
+                cancel,
                 draw,
                 get_image,
+                get_geojson,
+                tile_layerset,
+                tiles,
                 load_map,
                 load_custom_layer,
                 generate_from_image,
+                refine_region,
                 add_noise,
                 smooth,
                 erosion,
@@ -500,7 +1479,13 @@ fn rocket() -> _ {
                 resize,
                 post_calculate_climate,
                 get_size,
-                get_layers
+                get_layers,
+                get_edit_seeds,
+                undo,
+                redo,
+                export_history,
+                replay,
+                brush
             ],
         )
 }
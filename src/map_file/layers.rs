@@ -0,0 +1,279 @@
+use std::{collections::BTreeMap, mem::swap};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{complete_map::CompleteMap, error::MapResult, shapes::map_shape::MapShape};
+
+// Groups of CompleteMap fields that are stored, compressed and loaded together.
+// A field added to CompleteMap must be added to one of the layers, otherwise it
+// is not saved. Files without a layer leave its fields at their default values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LayerId {
+    TectonicPlates,
+    Height,
+    Temperature,
+    Winds,
+    Precipitation,
+    AnnualPrecipitation,
+    AtmPressure,
+    Climate,
+    Continentality,
+    IceHeight,
+    Rivers,
+    FreshWater,
+    Coastline,
+    VegetationDensity,
+    Features,
+    CustomLayers,
+}
+
+impl LayerId {
+    pub const ALL: [LayerId; 16] = [
+        LayerId::TectonicPlates,
+        LayerId::Height,
+        LayerId::Temperature,
+        LayerId::Winds,
+        LayerId::Precipitation,
+        LayerId::AnnualPrecipitation,
+        LayerId::AtmPressure,
+        LayerId::Climate,
+        LayerId::Continentality,
+        LayerId::IceHeight,
+        LayerId::Rivers,
+        LayerId::FreshWater,
+        LayerId::Coastline,
+        LayerId::VegetationDensity,
+        LayerId::Features,
+        LayerId::CustomLayers,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayerId::TectonicPlates => "tectonic_plates",
+            LayerId::Height => "height",
+            LayerId::Temperature => "temperature",
+            LayerId::Winds => "winds",
+            LayerId::Precipitation => "precipitation",
+            LayerId::AnnualPrecipitation => "annual_precipitation",
+            LayerId::AtmPressure => "atm_pressure",
+            LayerId::Climate => "climate",
+            LayerId::Continentality => "continentality",
+            LayerId::IceHeight => "ice_height",
+            LayerId::Rivers => "rivers",
+            LayerId::FreshWater => "fresh_water",
+            LayerId::Coastline => "coastline",
+            LayerId::VegetationDensity => "vegetation_density",
+            LayerId::Features => "features",
+            LayerId::CustomLayers => "custom_layers",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        LayerId::ALL
+            .iter()
+            .copied()
+            .find(|layer| layer.name() == name)
+    }
+
+    // The layer of a name used by the layer steps, e.g. "temperature/3" or "custom/rocks"
+    pub fn containing(name: &str) -> Option<Self> {
+        match name.split('/').next()? {
+            "custom" => Some(LayerId::CustomLayers),
+            layer => LayerId::from_name(layer),
+        }
+    }
+
+    // Whether a step has computed the layer. CompleteMap::new allocates the height, that
+    // steps can start from, and the plates and the climate, that are only computed once
+    // the plate directions and the precipitation are.
+    pub fn is_present<S: MapShape>(&self, cmap: &CompleteMap<S>) -> bool {
+        match self {
+            LayerId::TectonicPlates => !cmap.tectonic_plates_directions.is_empty(),
+            LayerId::Height => !cmap.height.values.is_empty(),
+            LayerId::Temperature => !cmap.temperature.is_empty(),
+            LayerId::Winds => !cmap.winds.is_empty(),
+            LayerId::Precipitation => !cmap.precipitation.is_empty(),
+            LayerId::AnnualPrecipitation => !cmap.annual_precipitation.values.is_empty(),
+            LayerId::AtmPressure => !cmap.atm_pressure.is_empty(),
+            LayerId::Climate => !cmap.climate.values.is_empty() && !cmap.precipitation.is_empty(),
+            LayerId::Continentality => !cmap.continentality.values.is_empty(),
+            LayerId::IceHeight => !cmap.ice_height.is_empty(),
+            // computed with the fresh water, a map may have no river at all
+            LayerId::Rivers => !cmap.fresh_water.values.is_empty(),
+            LayerId::FreshWater => !cmap.fresh_water.values.is_empty(),
+            LayerId::Coastline => cmap.coastline.is_some(),
+            LayerId::VegetationDensity => !cmap.vegetation_density.values.is_empty(),
+            LayerId::Features => !cmap.tectonic_edges.is_empty(),
+            LayerId::CustomLayers => !cmap.custom_pmaps.is_empty(),
+        }
+    }
+}
+
+pub fn copy_layer<S: MapShape>(from: &CompleteMap<S>, to: &mut CompleteMap<S>, layer: LayerId) {
+    match layer {
+        LayerId::TectonicPlates => {
+            to.tectonic_plates = from.tectonic_plates.clone();
+            to.tectonic_plates_directions = from.tectonic_plates_directions.clone();
+            to.tectonic_plates_centers = from.tectonic_plates_centers.clone();
+            to.oceanic_plates = from.oceanic_plates.clone();
+        }
+        LayerId::Height => to.height = from.height.clone(),
+        LayerId::Temperature => to.temperature = from.temperature.clone(),
+        LayerId::Winds => to.winds = from.winds.clone(),
+        LayerId::Precipitation => to.precipitation = from.precipitation.clone(),
+        LayerId::AnnualPrecipitation => to.annual_precipitation = from.annual_precipitation.clone(),
+        LayerId::AtmPressure => to.atm_pressure = from.atm_pressure.clone(),
+        LayerId::Climate => to.climate = from.climate.clone(),
+        LayerId::Continentality => to.continentality = from.continentality.clone(),
+        LayerId::IceHeight => to.ice_height = from.ice_height.clone(),
+        LayerId::Rivers => to.rivers = from.rivers.clone(),
+        LayerId::FreshWater => to.fresh_water = from.fresh_water.clone(),
+        LayerId::Coastline => to.coastline = from.coastline.clone(),
+        LayerId::VegetationDensity => to.vegetation_density = from.vegetation_density.clone(),
+        LayerId::Features => {
+            to.tectonic_edges = from.tectonic_edges.clone();
+            to.mountain_chains = from.mountain_chains.clone();
+            to.andean_chains = from.andean_chains.clone();
+            to.hymalayan_chains = from.hymalayan_chains.clone();
+            to.trenches = from.trenches.clone();
+            to.hotspots = from.hotspots.clone();
+        }
+        LayerId::CustomLayers => {
+            to.custom_pmaps = from.custom_pmaps.clone();
+            to.custom_color_schemes = from.custom_color_schemes.clone();
+        }
+    }
+}
+
+pub fn swap_layer<S: MapShape>(a: &mut CompleteMap<S>, b: &mut CompleteMap<S>, layer: LayerId) {
+    match layer {
+        LayerId::TectonicPlates => {
+            swap(&mut a.tectonic_plates, &mut b.tectonic_plates);
+            swap(
+                &mut a.tectonic_plates_directions,
+                &mut b.tectonic_plates_directions,
+            );
+            swap(
+                &mut a.tectonic_plates_centers,
+                &mut b.tectonic_plates_centers,
+            );
+            swap(&mut a.oceanic_plates, &mut b.oceanic_plates);
+        }
+        LayerId::Height => swap(&mut a.height, &mut b.height),
+        LayerId::Temperature => swap(&mut a.temperature, &mut b.temperature),
+        LayerId::Winds => swap(&mut a.winds, &mut b.winds),
+        LayerId::Precipitation => swap(&mut a.precipitation, &mut b.precipitation),
+        LayerId::AnnualPrecipitation => {
+            swap(&mut a.annual_precipitation, &mut b.annual_precipitation)
+        }
+        LayerId::AtmPressure => swap(&mut a.atm_pressure, &mut b.atm_pressure),
+        LayerId::Climate => swap(&mut a.climate, &mut b.climate),
+        LayerId::Continentality => swap(&mut a.continentality, &mut b.continentality),
+        LayerId::IceHeight => swap(&mut a.ice_height, &mut b.ice_height),
+        LayerId::Rivers => swap(&mut a.rivers, &mut b.rivers),
+        LayerId::FreshWater => swap(&mut a.fresh_water, &mut b.fresh_water),
+        LayerId::Coastline => swap(&mut a.coastline, &mut b.coastline),
+        LayerId::VegetationDensity => swap(&mut a.vegetation_density, &mut b.vegetation_density),
+        LayerId::Features => {
+            swap(&mut a.tectonic_edges, &mut b.tectonic_edges);
+            swap(&mut a.mountain_chains, &mut b.mountain_chains);
+            swap(&mut a.andean_chains, &mut b.andean_chains);
+            swap(&mut a.hymalayan_chains, &mut b.hymalayan_chains);
+            swap(&mut a.trenches, &mut b.trenches);
+            swap(&mut a.hotspots, &mut b.hotspots);
+        }
+        LayerId::CustomLayers => {
+            swap(&mut a.custom_pmaps, &mut b.custom_pmaps);
+            swap(&mut a.custom_color_schemes, &mut b.custom_color_schemes);
+        }
+    }
+}
+
+pub fn encode_layer<S: MapShape>(cmap: &CompleteMap<S>, layer: LayerId) -> MapResult<Vec<u8>> {
+    let bytes = match layer {
+        LayerId::TectonicPlates => bincode::serialize(&(
+            &cmap.tectonic_plates,
+            &cmap.tectonic_plates_directions,
+            &cmap.tectonic_plates_centers,
+            &cmap.oceanic_plates,
+        ))?,
+        LayerId::Height => bincode::serialize(&cmap.height)?,
+        LayerId::Temperature => bincode::serialize(&cmap.temperature)?,
+        LayerId::Winds => bincode::serialize(&cmap.winds)?,
+        LayerId::Precipitation => bincode::serialize(&cmap.precipitation)?,
+        LayerId::AnnualPrecipitation => bincode::serialize(&cmap.annual_precipitation)?,
+        LayerId::AtmPressure => bincode::serialize(&cmap.atm_pressure)?,
+        LayerId::Climate => bincode::serialize(&cmap.climate)?,
+        LayerId::Continentality => bincode::serialize(&cmap.continentality)?,
+        LayerId::IceHeight => bincode::serialize(&cmap.ice_height)?,
+        LayerId::Rivers => bincode::serialize(&cmap.rivers)?,
+        LayerId::FreshWater => bincode::serialize(&cmap.fresh_water)?,
+        // sorted, hash sets and maps iterate in a different order on every run
+        LayerId::Coastline => {
+            let coastline = cmap.coastline.as_ref().map(|coastline| {
+                let mut points: Vec<&[usize; 2]> = coastline.iter().collect();
+                points.sort();
+                points
+            });
+            bincode::serialize(&coastline)?
+        }
+        LayerId::VegetationDensity => bincode::serialize(&cmap.vegetation_density)?,
+        LayerId::Features => bincode::serialize(&(
+            &cmap.tectonic_edges,
+            &cmap.mountain_chains,
+            &cmap.andean_chains,
+            &cmap.hymalayan_chains,
+            &cmap.trenches,
+            &cmap.hotspots,
+        ))?,
+        LayerId::CustomLayers => bincode::serialize(&(
+            cmap.custom_pmaps.iter().collect::<BTreeMap<_, _>>(),
+            cmap.custom_color_schemes.iter().collect::<BTreeMap<_, _>>(),
+        ))?,
+    };
+    Ok(bytes)
+}
+
+pub fn decode_layer<S: MapShape + DeserializeOwned>(
+    cmap: &mut CompleteMap<S>,
+    layer: LayerId,
+    bytes: &[u8],
+) -> MapResult<()> {
+    match layer {
+        LayerId::TectonicPlates => {
+            (
+                cmap.tectonic_plates,
+                cmap.tectonic_plates_directions,
+                cmap.tectonic_plates_centers,
+                cmap.oceanic_plates,
+            ) = bincode::deserialize(bytes)?;
+        }
+        LayerId::Height => cmap.height = bincode::deserialize(bytes)?,
+        LayerId::Temperature => cmap.temperature = bincode::deserialize(bytes)?,
+        LayerId::Winds => cmap.winds = bincode::deserialize(bytes)?,
+        LayerId::Precipitation => cmap.precipitation = bincode::deserialize(bytes)?,
+        LayerId::AnnualPrecipitation => cmap.annual_precipitation = bincode::deserialize(bytes)?,
+        LayerId::AtmPressure => cmap.atm_pressure = bincode::deserialize(bytes)?,
+        LayerId::Climate => cmap.climate = bincode::deserialize(bytes)?,
+        LayerId::Continentality => cmap.continentality = bincode::deserialize(bytes)?,
+        LayerId::IceHeight => cmap.ice_height = bincode::deserialize(bytes)?,
+        LayerId::Rivers => cmap.rivers = bincode::deserialize(bytes)?,
+        LayerId::FreshWater => cmap.fresh_water = bincode::deserialize(bytes)?,
+        LayerId::Coastline => cmap.coastline = bincode::deserialize(bytes)?,
+        LayerId::VegetationDensity => cmap.vegetation_density = bincode::deserialize(bytes)?,
+        LayerId::Features => {
+            (
+                cmap.tectonic_edges,
+                cmap.mountain_chains,
+                cmap.andean_chains,
+                cmap.hymalayan_chains,
+                cmap.trenches,
+                cmap.hotspots,
+            ) = bincode::deserialize(bytes)?;
+        }
+        LayerId::CustomLayers => {
+            (cmap.custom_pmaps, cmap.custom_color_schemes) = bincode::deserialize(bytes)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,494 @@
+pub mod layers;
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use rayon::{
+    iter::{IntoParallelIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    complete_map::{CompleteMap, CompleteMapEnum},
+    configuration::{Configuration, ShapeEnum},
+    error::{MapError, MapResult},
+    shapes::map_shape::MapShape,
+};
+
+use self::layers::{decode_layer, encode_layer, LayerId};
+
+// File layout:
+//   magic (6 bytes) | format version (u32 LE) | header length (u32 LE) | header (json) | body
+// The header is json so that it can gain fields without breaking older files.
+//
+// Since version 2 the body is a sequence of deflate compressed chunks, followed by
+// the layer index (json) and the offset of the index (u64 LE) as the last 8 bytes.
+// Every layer is the bincode encoding of some CompleteMap fields, split into chunks
+// of CHUNK_SIZE bytes so that large layers are compressed and decompressed in parallel.
+// Versions 0 (no header) and 1 have the bincode encoded CompleteMap as body.
+pub const MAGIC: &[u8; 6] = b"MAPGEN";
+pub const FORMAT_VERSION: u32 = 2;
+
+// Files written before the header existed are a bare bincode CompleteMap.
+const LEGACY_VERSION: u32 = 0;
+const SINGLE_BODY_VERSION: u32 = 1;
+
+const CHUNK_SIZE: usize = 8 << 20;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapHeader {
+    pub shape: ShapeEnum,
+    pub circunference: usize,
+    pub height: usize,
+    #[serde(default)]
+    pub seed: Option<u32>,
+    #[serde(default)]
+    pub config: Option<Configuration>,
+}
+
+impl MapHeader {
+    pub fn new(cmap: &CompleteMapEnum, config: Option<&Configuration>) -> Self {
+        let [circunference, height] = cmap.dimensions();
+        Self {
+            shape: cmap.shape(),
+            circunference,
+            height,
+            seed: config.map(|c| c.seed),
+            config: config.cloned(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LayerEntry {
+    pub layer: String,
+    pub length: u64,
+    pub chunks: Vec<ChunkEntry>,
+}
+
+#[allow(dead_code)]
+pub struct MapFile {
+    pub version: u32,
+    pub header: MapHeader,
+    pub map: CompleteMapEnum,
+}
+
+pub fn save(
+    cmap: &CompleteMapEnum,
+    config: Option<&Configuration>,
+    filename: &str,
+) -> MapResult<()> {
+    let header = MapHeader::new(cmap, config);
+    let header_bytes = serde_json::to_vec(&header)?;
+
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(header_bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&header_bytes)?;
+
+    let mut offset = (MAGIC.len() + 8 + header_bytes.len()) as u64;
+    let mut index = vec![];
+    for layer in LayerId::ALL.iter().copied() {
+        let data = match cmap {
+            CompleteMapEnum::Cylinder(cmap) => encode_layer(cmap, layer)?,
+            CompleteMapEnum::CubeSphere(cmap) => encode_layer(cmap, layer)?,
+            CompleteMapEnum::Globe(cmap) => encode_layer(cmap, layer)?,
+            CompleteMapEnum::Flat(cmap) => encode_layer(cmap, layer)?,
+            CompleteMapEnum::Torus(cmap) => encode_layer(cmap, layer)?,
+            CompleteMapEnum::Disc(cmap) => encode_layer(cmap, layer)?,
+        };
+        let compressed_chunks = data
+            .par_chunks(CHUNK_SIZE)
+            .map(compress_chunk)
+            .collect::<MapResult<Vec<Vec<u8>>>>()?;
+
+        let mut chunks = vec![];
+        for compressed in compressed_chunks {
+            writer.write_all(&compressed)?;
+            chunks.push(ChunkEntry {
+                offset,
+                length: compressed.len() as u64,
+            });
+            offset += compressed.len() as u64;
+        }
+        index.push(LayerEntry {
+            layer: layer.name().to_string(),
+            length: data.len() as u64,
+            chunks,
+        });
+    }
+
+    writer.write_all(&serde_json::to_vec(&index)?)?;
+    writer.write_all(&offset.to_le_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn compress_chunk(data: &[u8]) -> MapResult<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress_chunk(data: &[u8]) -> MapResult<Vec<u8>> {
+    let mut decompressed = vec![];
+    DeflateDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+fn read_u32(reader: &mut impl Read) -> MapResult<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Reads the magic, version and header, leaving the reader at the start of the body.
+// Returns None for legacy files, with the reader rewound to the start.
+fn read_preamble<R: Read + Seek>(reader: &mut R) -> MapResult<Option<(u32, MapHeader)>> {
+    let mut magic = [0; 6];
+    if reader.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        reader.seek(SeekFrom::Start(0))?;
+        return Ok(None);
+    }
+    let version = read_u32(reader)?;
+    if version > FORMAT_VERSION {
+        return Err(MapError::InvalidData(format!(
+            "map file version {} is newer than the supported version {}",
+            version, FORMAT_VERSION
+        )));
+    }
+    let header_length = read_u32(reader)? as usize;
+    let mut header_bytes = vec![0; header_length];
+    reader.read_exact(&mut header_bytes)?;
+    let header: MapHeader = serde_json::from_slice(&header_bytes)?;
+    Ok(Some((version, header)))
+}
+
+#[allow(dead_code)]
+pub fn read_header(filename: &str) -> MapResult<Option<MapHeader>> {
+    let mut reader = BufReader::new(File::open(filename)?);
+    Ok(read_preamble(&mut reader)?.map(|(_, header)| header))
+}
+
+// Reads a map file, loading only the layers that are asked for.
+pub struct MapReader {
+    reader: BufReader<File>,
+    pub version: u32,
+    pub header: MapHeader,
+    // None for files storing the whole map in a single body
+    index: Option<Vec<LayerEntry>>,
+}
+
+impl MapReader {
+    // `legacy_shape` is only used for files without a header, which do not record their shape.
+    pub fn open(filename: &str, legacy_shape: ShapeEnum) -> MapResult<Self> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let (version, header) = match read_preamble(&mut reader)? {
+            Some(preamble) => preamble,
+            None => (
+                LEGACY_VERSION,
+                MapHeader {
+                    shape: legacy_shape,
+                    circunference: 0,
+                    height: 0,
+                    seed: None,
+                    config: None,
+                },
+            ),
+        };
+        let index = if version > SINGLE_BODY_VERSION {
+            reader.seek(SeekFrom::End(-8))?;
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            let index_offset = u64::from_le_bytes(bytes);
+            let index_end = reader.seek(SeekFrom::End(-8))?;
+            if index_offset > index_end {
+                return Err(MapError::InvalidData(
+                    "map file layer index is out of bounds".to_string(),
+                ));
+            }
+            reader.seek(SeekFrom::Start(index_offset))?;
+            let mut index_bytes = vec![0; (index_end - index_offset) as usize];
+            reader.read_exact(&mut index_bytes)?;
+            Some(serde_json::from_slice(&index_bytes)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            reader,
+            version,
+            header,
+            index,
+        })
+    }
+
+    // Layers stored in the file, with their compressed size.
+    #[allow(dead_code)]
+    pub fn stored_layers(&self) -> Vec<(String, u64)> {
+        match &self.index {
+            Some(index) => index
+                .iter()
+                .map(|entry| {
+                    let size = entry.chunks.iter().map(|chunk| chunk.length).sum();
+                    (entry.layer.clone(), size)
+                })
+                .collect(),
+            None => LayerId::ALL
+                .iter()
+                .map(|layer| (layer.name().to_string(), 0))
+                .collect(),
+        }
+    }
+
+    // True if reading some of the layers is cheaper than reading all of them.
+    pub fn is_lazy(&self) -> bool {
+        self.index.is_some()
+    }
+
+    pub fn read_all(&mut self) -> MapResult<CompleteMapEnum> {
+        self.read_layers(&LayerId::ALL)
+    }
+
+    // Layers that are not read are left empty. Files without a layer index are read whole.
+    pub fn read_layers(&mut self, layers: &[LayerId]) -> MapResult<CompleteMapEnum> {
+        let map = match self.header.shape {
+            ShapeEnum::Cylinder => CompleteMapEnum::Cylinder(self.read_map(layers)?),
+            ShapeEnum::CubeSphere => CompleteMapEnum::CubeSphere(self.read_map(layers)?),
+            ShapeEnum::Globe => CompleteMapEnum::Globe(self.read_map(layers)?),
+            ShapeEnum::Flat => CompleteMapEnum::Flat(self.read_map(layers)?),
+            ShapeEnum::Torus => CompleteMapEnum::Torus(self.read_map(layers)?),
+            ShapeEnum::Disc => CompleteMapEnum::Disc(self.read_map(layers)?),
+        };
+        let [circunference, height] = map.dimensions();
+        if self.version != LEGACY_VERSION
+            && (circunference != self.header.circunference || height != self.header.height)
+        {
+            return Err(MapError::InvalidData(format!(
+                "header says {}x{} but the map is {}x{}",
+                self.header.circunference, self.header.height, circunference, height
+            )));
+        }
+        self.header.circunference = circunference;
+        self.header.height = height;
+        Ok(map)
+    }
+
+    #[allow(dead_code)]
+    pub fn read_layers_into(
+        &mut self,
+        map: &mut CompleteMapEnum,
+        layers: &[LayerId],
+    ) -> MapResult<()> {
+        if map.shape() != self.header.shape {
+            return Err(MapError::InvalidData(format!(
+                "cannot read layers of a {:?} map into a {:?} map",
+                self.header.shape,
+                map.shape()
+            )));
+        }
+        match map {
+            CompleteMapEnum::Cylinder(cmap) => self.read_layers_into_map(cmap, layers),
+            CompleteMapEnum::CubeSphere(cmap) => self.read_layers_into_map(cmap, layers),
+            CompleteMapEnum::Globe(cmap) => self.read_layers_into_map(cmap, layers),
+            CompleteMapEnum::Flat(cmap) => self.read_layers_into_map(cmap, layers),
+            CompleteMapEnum::Torus(cmap) => self.read_layers_into_map(cmap, layers),
+            CompleteMapEnum::Disc(cmap) => self.read_layers_into_map(cmap, layers),
+        }
+    }
+
+    fn read_map<S: MapShape + DeserializeOwned>(
+        &mut self,
+        layers: &[LayerId],
+    ) -> MapResult<CompleteMap<S>> {
+        if self.index.is_none() {
+            let cmap: CompleteMap<S> = bincode::deserialize_from(&mut self.reader)?;
+            return migrate(cmap, self.version);
+        }
+        let mut cmap = CompleteMap::new(self.header.circunference, self.header.height);
+        self.read_layers_into_map(&mut cmap, layers)?;
+        Ok(cmap)
+    }
+
+    fn read_layers_into_map<S: MapShape + DeserializeOwned>(
+        &mut self,
+        cmap: &mut CompleteMap<S>,
+        layers: &[LayerId],
+    ) -> MapResult<()> {
+        let Some(index) = &self.index else {
+            return Err(MapError::InvalidData(format!(
+                "map file version {} has no layer index",
+                self.version
+            )));
+        };
+        let wanted: HashSet<&LayerId> = layers.iter().collect();
+        for entry in index {
+            // layers written by newer versions are skipped
+            let Some(layer) = LayerId::from_name(&entry.layer) else {
+                continue;
+            };
+            if !wanted.contains(&layer) {
+                continue;
+            }
+            let mut compressed_chunks = vec![];
+            for chunk in &entry.chunks {
+                self.reader.seek(SeekFrom::Start(chunk.offset))?;
+                let mut bytes = vec![0; chunk.length as usize];
+                self.reader.read_exact(&mut bytes)?;
+                compressed_chunks.push(bytes);
+            }
+            let chunks = compressed_chunks
+                .into_par_iter()
+                .map(|bytes| decompress_chunk(&bytes))
+                .collect::<MapResult<Vec<Vec<u8>>>>()?;
+            let data = chunks.concat();
+            if data.len() as u64 != entry.length {
+                return Err(MapError::InvalidData(format!(
+                    "layer {} is {} bytes long instead of {}",
+                    entry.layer,
+                    data.len(),
+                    entry.length
+                )));
+            }
+            decode_layer(cmap, layer, &data)?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+pub fn load(filename: &str, legacy_shape: ShapeEnum) -> MapResult<MapFile> {
+    let mut reader = MapReader::open(filename, legacy_shape)?;
+    let map = reader.read_all()?;
+    Ok(MapFile {
+        version: reader.version,
+        header: reader.header,
+        map,
+    })
+}
+
+// Migration hooks for files storing the whole map in a single body, applied in
+// order. Since version 2 layers are stored separately and a layer missing from
+// a file keeps the default value set by CompleteMap::new, so adding a field to
+// CompleteMap only needs it to be part of a layer. Changing the type of a field
+// needs a new layer name, or a format version bump and a hook here.
+fn migrate<S: MapShape>(cmap: CompleteMap<S>, version: u32) -> MapResult<CompleteMap<S>> {
+    let mut cmap = cmap;
+    for from_version in version..FORMAT_VERSION {
+        cmap = match from_version {
+            // legacy files share the layout of version 1
+            LEGACY_VERSION => cmap,
+            // version 2 only changed how the fields are stored
+            SINGLE_BODY_VERSION => cmap,
+            _ => {
+                return Err(MapError::InvalidData(format!(
+                    "no migration from map file version {}",
+                    from_version
+                )))
+            }
+        };
+    }
+    Ok(cmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::torus::Torus;
+
+    fn test_map() -> CompleteMap<Torus> {
+        let mut cmap = CompleteMap::new(16, 8);
+        for (x, row) in cmap.height.values.iter_mut().enumerate() {
+            for (y, value) in row.iter_mut().enumerate() {
+                *value = 100 * x as i32 - 10 * y as i32;
+            }
+        }
+        cmap.tectonic_plates.values[3][5] = 7;
+        cmap.hotspots = vec![[10.0, -20.0]];
+        return cmap;
+    }
+
+    fn test_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("map_file_{}_{}", name, std::process::id()));
+        return path.to_str().unwrap().to_string();
+    }
+
+    fn bytes<S: MapShape>(cmap: &CompleteMap<S>) -> Vec<u8> {
+        return bincode::serialize(cmap).unwrap();
+    }
+
+    fn torus(map: &CompleteMapEnum) -> &CompleteMap<Torus> {
+        let CompleteMapEnum::Torus(cmap) = map else {
+            panic!("expected a torus map");
+        };
+        return cmap;
+    }
+
+    #[test]
+    fn maps_are_read_back() {
+        let cmap = test_map();
+        let path = test_file("round_trip");
+        save(&CompleteMapEnum::Torus(cmap.clone()), None, &path).unwrap();
+
+        // the header gives the shape, so the legacy shape is ignored
+        let file = load(&path, ShapeEnum::Globe).unwrap();
+        assert_eq!(file.version, FORMAT_VERSION);
+        assert_eq!(file.header.shape, ShapeEnum::Torus);
+        assert_eq!([file.header.circunference, file.header.height], [16, 8]);
+        assert_eq!(bytes(torus(&file.map)), bytes(&cmap));
+
+        let mut reader = MapReader::open(&path, ShapeEnum::Globe).unwrap();
+        assert!(reader.is_lazy());
+        let map = reader.read_layers(&[LayerId::Height]).unwrap();
+        assert_eq!(torus(&map).height.values, cmap.height.values);
+        assert!(torus(&map).hotspots.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn older_versions_are_migrated() {
+        let cmap = test_map();
+        let body = bytes(&cmap);
+
+        // legacy files are a bare map, of the shape given when loading them
+        let legacy = test_file("legacy");
+        std::fs::write(&legacy, &body).unwrap();
+        let file = load(&legacy, ShapeEnum::Torus).unwrap();
+        assert_eq!(file.version, LEGACY_VERSION);
+        assert_eq!([file.header.circunference, file.header.height], [16, 8]);
+        assert_eq!(bytes(torus(&file.map)), body);
+
+        let header = MapHeader::new(&CompleteMapEnum::Torus(cmap.clone()), None);
+        let header_bytes = serde_json::to_vec(&header).unwrap();
+        let mut single_body = MAGIC.to_vec();
+        single_body.extend(SINGLE_BODY_VERSION.to_le_bytes());
+        single_body.extend((header_bytes.len() as u32).to_le_bytes());
+        single_body.extend(&header_bytes);
+        single_body.extend(&body);
+        let version_1 = test_file("version_1");
+        std::fs::write(&version_1, &single_body).unwrap();
+        let file = load(&version_1, ShapeEnum::Globe).unwrap();
+        assert_eq!(file.version, SINGLE_BODY_VERSION);
+        assert_eq!(bytes(torus(&file.map)), body);
+
+        // files of newer versions are rejected
+        single_body[MAGIC.len()..MAGIC.len() + 4]
+            .copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&version_1, &single_body).unwrap();
+        assert!(load(&version_1, ShapeEnum::Globe).is_err());
+
+        std::fs::remove_file(&legacy).unwrap();
+        std::fs::remove_file(&version_1).unwrap();
+    }
+}
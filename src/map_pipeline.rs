@@ -1,22 +1,53 @@
-use std::{fs, time::Instant};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use image::Rgba;
+use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
+    checkpoint::{prefix_keys, CheckpointCache},
     complete_map::CompleteMap,
-    map_view::{
-        color_scheme::DEFAULT_COLORS, map_view::MapView,
-        parallels_meridians_layer::ParallelsMeridiansLayer, partial_map_layer::PartialMapLayer,
-        projection::equirectangular::Equirectangular, rivers_layer::RiversLayer,
+    error::{MapError, MapResult},
+    pipeline_observer::{PipelineObserver, ProgressObserver},
+    pipeline_steps::pipeline_step::{
+        apply_concurrently, independent, step_name, validate_steps, PipelineStep, SubStepTiming,
     },
-    pipeline_steps::pipeline_step::PipelineStep,
     shapes::map_shape::MapShape,
 };
 
+// Reported after every step of MapPipeline::execute. Durations are in seconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct StepProgress {
+    pub index: usize,
+    pub steps: usize,
+    pub name: String,
+    pub duration: f32,
+    pub elapsed: f32,
+    // read from a checkpoint instead of run
+    pub cached: bool,
+    // of the steps run by this one
+    pub sub_steps: Vec<SubStepTiming>,
+    // why the map after the step could not be saved as a checkpoint
+    pub checkpoint_error: Option<String>,
+}
+
 pub struct MapPipeline<S: MapShape> {
     pub circunference: usize,
     pub height: usize,
     pub steps: Vec<Box<dyn PipelineStep<S> + 'static>>,
+    // what a step does, for checkpoints. Steps without a key are never cached, nor the
+    // steps after them.
+    step_keys: Vec<Option<String>>,
+    checkpoints: Option<CheckpointCache>,
+    observers: Vec<Box<dyn PipelineObserver<S>>>,
+    // checked before every step, a running step is not interrupted
+    cancel: Option<Arc<AtomicBool>>,
+    // progress of every step of the last execution
+    report: Vec<StepProgress>,
 }
 
 impl<S: MapShape + 'static> MapPipeline<S> {
@@ -25,51 +56,238 @@ impl<S: MapShape + 'static> MapPipeline<S> {
             circunference: 400,
             height: 200,
             steps: vec![],
+            step_keys: vec![],
+            checkpoints: None,
+            observers: vec![],
+            cancel: None,
+            report: vec![],
         }
     }
 
+    pub fn add_observer(&mut self, observer: impl PipelineObserver<S> + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    #[allow(dead_code)]
+    pub fn on_progress(&mut self, callback: impl FnMut(&StepProgress) + Send + 'static) {
+        self.add_observer(ProgressObserver(callback));
+    }
+
+    #[allow(dead_code)]
+    pub fn set_cancel_flag(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
+    }
+
+    #[allow(dead_code)]
+    pub fn step_names(&self) -> Vec<String> {
+        return self
+            .steps
+            .iter()
+            .map(|step| step_name(step.as_ref()))
+            .collect();
+    }
+
+    // The progress of every step of the last execution, run or cached, in order
+    pub fn report(&self) -> &[StepProgress] {
+        return &self.report;
+    }
+
+    pub fn set_checkpoints(&mut self, checkpoints: CheckpointCache) {
+        self.checkpoints = Some(checkpoints);
+    }
+
     pub fn add_step(&mut self, step: impl PipelineStep<S> + 'static) {
-        self.steps.push(Box::new(step));
+        self.add_boxed_step(Box::new(step));
+    }
+
+    pub fn add_boxed_step(&mut self, step: Box<dyn PipelineStep<S> + 'static>) {
+        self.step_keys.resize(self.steps.len(), None);
+        self.steps.push(step);
+        self.step_keys.push(None);
     }
 
-    pub fn execute(&mut self) -> CompleteMap<S> {
+    pub fn add_keyed_step(&mut self, step: Box<dyn PipelineStep<S> + 'static>, key: String) {
+        self.add_boxed_step(step);
+        if let Some(last) = self.step_keys.last_mut() {
+            *last = Some(key);
+        }
+    }
+
+    // Fails on the first step reading a layer that no step before it computes.
+    pub fn validate(&self) -> MapResult<()> {
+        let empty_map = CompleteMap::new(self.circunference, self.height);
+        return validate_steps(self.steps.iter().map(|step| step.as_ref()), &empty_map);
+    }
+
+    // Starts from the checkpoint of the longest run of steps already executed, if any.
+    // Consecutive steps that do not use the layers written by each other run at the same time.
+    pub fn execute(&mut self) -> MapResult<CompleteMap<S>>
+    where
+        S: DeserializeOwned,
+    {
+        self.validate()?;
+        self.report.clear();
         let mut complete_map = CompleteMap::new(self.circunference, self.height);
         let start = Instant::now();
-        for (i, step) in self.steps.iter_mut().enumerate() {
+        let n_steps = self.steps.len();
+
+        let mut keys = vec![];
+        let mut first_step = 0;
+        if let Some(checkpoints) = &self.checkpoints {
+            self.step_keys.resize(n_steps, None);
+            keys = prefix_keys::<S>(self.circunference, self.height, &self.step_keys);
+            if let Some((i, cmap)) = keys
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(i, key)| Some((i, checkpoints.load(*key)?)))
+            {
+                complete_map = cmap;
+                first_step = i + 1;
+            }
+        }
+        for (i, step) in self.steps.iter().enumerate().take(first_step) {
+            let progress = StepProgress {
+                index: i,
+                steps: n_steps,
+                name: step_name(step.as_ref()),
+                duration: 0.0,
+                elapsed: start.elapsed().as_secs_f32(),
+                cached: true,
+                sub_steps: vec![],
+                checkpoint_error: None,
+            };
+            let description = format!("{:?}", step);
+            for observer in &mut self.observers {
+                observer.cached_step(&progress, &description)?;
+            }
+            self.report.push(progress);
+        }
+
+        let mut i = first_step;
+        while i < n_steps {
+            if let Some(cancel) = &self.cancel {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(MapError::Cancelled);
+                }
+            }
+            let mut end = i + 1;
+            while end < n_steps
+                && (i..end).all(|j| independent(self.steps[j].as_ref(), self.steps[end].as_ref()))
+            {
+                end += 1;
+            }
+            let batch: Vec<&dyn PipelineStep<S>> = self.steps[i..end]
+                .iter()
+                .map(|step| step.as_ref())
+                .collect();
+            let descriptions: Vec<String> =
+                batch.iter().map(|step| format!("{:?}", step)).collect();
+            for (k, description) in descriptions.iter().enumerate() {
+                for observer in &mut self.observers {
+                    observer.before_step(i + k, description, &complete_map);
+                }
+            }
             let t1 = Instant::now();
-            complete_map = step.apply(&mut complete_map);
+            let mut timings = vec![];
+            for (k, result) in apply_concurrently(&batch, &mut complete_map)
+                .into_iter()
+                .enumerate()
+            {
+                timings.push(result.map_err(|e| MapError::Step {
+                    index: i + k,
+                    source: Box::new(e),
+                })?);
+            }
             let t2 = Instant::now();
-            dbg!("step", i);
-            dbg!("duration: ", t2 - t1);
-            draw_step(&complete_map, i);
+            let mut checkpoint_error = None;
+            if let (Some(checkpoints), Some(key)) = (&self.checkpoints, keys.get(end - 1)) {
+                if (t2 - t1).as_secs_f32() >= checkpoints.min_step_duration {
+                    checkpoint_error = checkpoints
+                        .save(*key, &complete_map)
+                        .err()
+                        .map(|e| e.to_string());
+                }
+            }
+            for (k, (step, (duration, sub_steps))) in batch.iter().zip(timings).enumerate() {
+                let progress = StepProgress {
+                    index: i + k,
+                    steps: n_steps,
+                    name: step_name(*step),
+                    duration,
+                    elapsed: (t2 - start).as_secs_f32(),
+                    cached: false,
+                    sub_steps,
+                    // the checkpoint is taken after the last step of the batch
+                    checkpoint_error: if i + k == end - 1 {
+                        checkpoint_error.clone()
+                    } else {
+                        None
+                    },
+                };
+                for observer in &mut self.observers {
+                    observer.after_step(&progress, &descriptions[k], &complete_map)?;
+                }
+                self.report.push(progress);
+            }
+            i = end;
+        }
+        let elapsed = start.elapsed().as_secs_f32();
+        for observer in &mut self.observers {
+            observer.finished(&complete_map, elapsed)?;
         }
-        let end = Instant::now();
-        dbg!("Total generation time: ", end - start);
-        return complete_map;
+        return Ok(complete_map);
     }
 }
 
-fn draw_step<S: MapShape + 'static>(cmap: &CompleteMap<S>, i: usize) {
-    let output_path = "out/pipeline/";
-    if !std::path::Path::new(&output_path).exists() {
-        let _ = fs::create_dir_all(output_path);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline_steps::water_level::AddHeight, shapes::flat::Flat};
+
+    fn pipeline(dir: &str, last_step: i32) -> MapPipeline<Flat> {
+        let mut map_pipeline = MapPipeline::new();
+        map_pipeline.circunference = 8;
+        map_pipeline.height = 4;
+        map_pipeline.add_keyed_step(Box::new(AddHeight::new(10)), "add 10".to_string());
+        map_pipeline.add_keyed_step(Box::new(AddHeight::new(20)), "add 20".to_string());
+        map_pipeline.add_keyed_step(
+            Box::new(AddHeight::new(last_step)),
+            format!("add {}", last_step),
+        );
+        let mut checkpoints = CheckpointCache::new(dir);
+        checkpoints.min_step_duration = 0.0;
+        map_pipeline.set_checkpoints(checkpoints);
+        return map_pipeline;
+    }
+
+    fn cached_steps(map_pipeline: &MapPipeline<Flat>) -> Vec<bool> {
+        return map_pipeline
+            .report()
+            .iter()
+            .map(|step| step.cached)
+            .collect();
+    }
+
+    #[test]
+    fn execution_resumes_from_checkpoints() {
+        let dir = std::env::temp_dir().join(format!("pipeline_checkpoints_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+
+        let mut first = pipeline(dir, 30);
+        let cmap = first.execute().unwrap();
+        assert_eq!(cached_steps(&first), vec![false, false, false]);
+        assert_eq!(cmap.height.values[0][0], 60);
+
+        let mut again = pipeline(dir, 30);
+        assert_eq!(again.execute().unwrap().height.values, cmap.height.values);
+        assert_eq!(cached_steps(&again), vec![true, true, true]);
+
+        // only the steps from the first changed one run again
+        let mut changed = pipeline(dir, 5);
+        assert_eq!(changed.execute().unwrap().height.values[0][0], 35);
+        assert_eq!(cached_steps(&changed), vec![true, true, false]);
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
-    let mut mv: MapView<Equirectangular, S> = MapView::new();
-
-    let mut pm_layer = ParallelsMeridiansLayer::default();
-    pm_layer.color = Rgba([255, 255, 255, 150]);
-    mv.resolution = [cmap.height.circunference, cmap.height.height];
-    mv.center[1] = 0.0;
-    mv.layers = vec![
-        Box::new(PartialMapLayer::new(
-            |m| &m.height,
-            Box::new(DEFAULT_COLORS.clone()),
-        )),
-        Box::new(RiversLayer::default()),
-        Box::new(pm_layer),
-    ];
-    mv.draw(
-        &cmap,
-        ("out/pipeline/".to_owned() + &i.to_string() + ".png").as_str(),
-    );
 }
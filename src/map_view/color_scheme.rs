@@ -1,12 +1,17 @@
-use crate::pipeline_steps::climate::Climate;
+use crate::{
+    pipeline_steps::climate::Climate,
+    seed::{seeded_rng, SeededRng},
+};
 use image::{open, DynamicImage, GenericImageView, Rgba};
 use lazy_static::lazy_static;
 use rand::Rng;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
-use std::{collections::HashMap, hash::Hash};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
 
-fn generate_random_color() -> Rgba<u8> {
-    let mut rng = rand::thread_rng();
+fn generate_random_color(rng: &mut SeededRng) -> Rgba<u8> {
     Rgba([rng.gen::<u8>(), rng.gen::<u8>(), rng.gen::<u8>(), 255])
 }
 
@@ -55,7 +60,7 @@ where
     Ok(result)
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct GradientColorScheme {
     #[serde(deserialize_with = "deserialize_rgba_tuple_vec")]
     pub points: Vec<(i32, Rgba<u8>)>,
@@ -187,9 +192,10 @@ impl Serialize for CategoryColorScheme {
     where
         S: Serializer,
     {
+        // sorted, so that saved maps do not depend on the order of the hash map
         #[derive(Serialize)]
         struct Helper {
-            color_map: HashMap<usize, [u8; 4]>,
+            color_map: BTreeMap<usize, [u8; 4]>,
         }
 
         let helper = Helper {
@@ -205,10 +211,12 @@ impl Serialize for CategoryColorScheme {
 }
 
 impl CategoryColorScheme {
-    pub fn new_random(n_categories: u32) -> Self {
+    // The same seed always gives the same colors, so that views do not change between renders.
+    pub fn new_random(n_categories: u32, seed: u64) -> Self {
+        let mut rng = seeded_rng(seed);
         let mut color_map = HashMap::new();
         for i in 0..n_categories {
-            color_map.insert(i as usize, generate_random_color());
+            color_map.insert(i as usize, generate_random_color(&mut rng));
         }
         Self { color_map }
     }
@@ -1,10 +1,19 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, hash::Hash};
 
 use image::{ImageBuffer, Rgba};
 
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap,
+    export::geojson::{layer_grid, trace_boundaries},
+    partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
-use super::{layer::MapViewLayer, projection::projection::Projection};
+use super::{
+    layer::MapViewLayer,
+    projection::projection::Projection,
+    svg::{project_line, SvgPath},
+};
 
 pub struct ContourLayer<S, T, F>
 where
@@ -78,6 +87,43 @@ where
         return false;
     }
 
+    // Lines between pixels with different values, traced along the pixel edges.
+    fn boundary_paths<V: Copy + Eq + Ord + Hash, P: Projection>(
+        &self,
+        cells: &[V],
+        pmap: &PartialMap<S, T>,
+        img_width: u32,
+        img_height: u32,
+        projection: &P,
+        center_longitude: f32,
+    ) -> Vec<SvgPath> {
+        let width = pmap.circunference;
+        let height = pmap.values.len();
+        let mut paths = vec![];
+        if self.thickness == 0 {
+            return paths;
+        }
+        for (_, corners) in trace_boundaries(width, height, cells) {
+            let points: Vec<[f32; 2]> = corners
+                .iter()
+                .map(|[row, column]| {
+                    [
+                        *row as f32 * 180.0 / height as f32 - 90.0,
+                        *column as f32 * 360.0 / width as f32 - 180.0,
+                    ]
+                })
+                .collect();
+            for line in project_line(&points, projection, img_width, img_height, center_longitude) {
+                paths.push(SvgPath {
+                    points: line,
+                    color: self.color,
+                    width: self.thickness as f32,
+                });
+            }
+        }
+        return paths;
+    }
+
     fn draw_quick<P: Projection>(
         &self,
         base_img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
@@ -156,11 +202,7 @@ where
                     base_img.height() as u32,
                     center_longitude,
                 ) {
-                    let map_x = (height_map.height as f32 * (lat + 90.0).rem_euclid(180.0) / 180.0)
-                        as usize;
-                    let row_len = height_map.values[map_x].len();
-                    let map_y =
-                        (row_len as f32 * (long + 180.0).rem_euclid(360.0) / 360.0) as usize;
+                    let [map_x, map_y] = height_map.convert_to_vec_coords(lat, long);
                     if height_map.values[map_x][map_y] <= 0 {
                         'outer_loop: for k in -(self.thickness as i32)..=self.thickness as i32 {
                             if i as i32 + k < 0 || i as i32 + k >= base_img.width() as i32 {
@@ -177,15 +219,8 @@ where
                                     base_img.height() as u32,
                                     center_longitude,
                                 ) {
-                                    let map_x2 = (height_map.height as f32
-                                        * (lat2 + 90.0).rem_euclid(180.0)
-                                        / 180.0)
-                                        as usize;
-                                    let row_len = height_map.values[map_x2].len();
-                                    let map_y2: usize = (row_len as f32
-                                        * (long2 + 180.0).rem_euclid(360.0)
-                                        / 360.0)
-                                        as usize;
+                                    let [map_x2, map_y2] =
+                                        height_map.convert_to_vec_coords(lat2, long2);
 
                                     if height_map.values[map_x2][map_y2] > 0 {
                                         base_img.put_pixel(i as u32, j as u32, self.color);
@@ -199,6 +234,29 @@ where
             }
         }
     }
+
+    fn vector_paths(
+        &self,
+        img_width: u32,
+        img_height: u32,
+        projection: &P,
+        center_longitude: f32,
+        complete_map: &CompleteMap<S>,
+    ) -> Option<Vec<SvgPath>> {
+        let height_map = (self.selector)(complete_map);
+        let land: Vec<bool> = layer_grid(height_map, height_map.circunference)
+            .iter()
+            .map(|value| *value > 0)
+            .collect();
+        return Some(self.boundary_paths(
+            &land,
+            height_map,
+            img_width,
+            img_height,
+            projection,
+            center_longitude,
+        ));
+    }
 }
 
 impl<S, P, F> MapViewLayer<P, S> for ContourLayer<S, usize, F>
@@ -227,11 +285,7 @@ where
                     base_img.height() as u32,
                     center_longitude,
                 ) {
-                    let map_x = (height_map.height as f32 * (lat + 90.0).rem_euclid(180.0) / 180.0)
-                        as usize;
-                    let row_len = height_map.values[map_x].len();
-                    let map_y =
-                        (row_len as f32 * (long + 180.0).rem_euclid(360.0) / 360.0) as usize;
+                    let [map_x, map_y] = height_map.convert_to_vec_coords(lat, long);
                     for k in -(self.thickness as i32)..=self.thickness as i32 {
                         if i as i32 + k < 0 || i as i32 + k >= base_img.width() as i32 {
                             continue;
@@ -247,13 +301,8 @@ where
                                 base_img.height() as u32,
                                 center_longitude,
                             ) {
-                                let map_x2 = (height_map.height as f32
-                                    * (lat2 + 90.0).rem_euclid(180.0)
-                                    / 180.0) as usize;
-                                let row_len = height_map.values[map_x2].len();
-                                let map_y2: usize =
-                                    (row_len as f32 * (long2 + 180.0).rem_euclid(360.0) / 360.0)
-                                        as usize;
+                                let [map_x2, map_y2] =
+                                    height_map.convert_to_vec_coords(lat2, long2);
 
                                 if height_map.values[map_x][map_y]
                                     < height_map.values[map_x2][map_y2]
@@ -267,4 +316,24 @@ where
             }
         }
     }
+
+    fn vector_paths(
+        &self,
+        img_width: u32,
+        img_height: u32,
+        projection: &P,
+        center_longitude: f32,
+        complete_map: &CompleteMap<S>,
+    ) -> Option<Vec<SvgPath>> {
+        let category_map = (self.selector)(complete_map);
+        let categories = layer_grid(category_map, category_map.circunference);
+        return Some(self.boundary_paths(
+            &categories,
+            category_map,
+            img_width,
+            img_height,
+            projection,
+            center_longitude,
+        ));
+    }
 }
@@ -15,7 +15,7 @@ pub struct CustomPartialMapLayer {
 impl CustomPartialMapLayer {
     pub fn new(layer_name: String) -> Self {
         Self {
-            // color_scheme: CategoryColorScheme::new_random(1000),
+            // color_scheme: CategoryColorScheme::new_random(1000, 0),
             layer_name,
         }
     }
@@ -2,7 +2,7 @@ use image::{ImageBuffer, Rgba};
 
 use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
 
-use super::projection::projection::Projection;
+use super::{projection::projection::Projection, svg::SvgPath};
 
 pub trait MapViewLayer<P: Projection, S: MapShape> {
     fn draw_layer(
@@ -12,6 +12,19 @@ pub trait MapViewLayer<P: Projection, S: MapShape> {
         center_longitude: f32,
         complete_map: &CompleteMap<S>,
     );
+
+    // Lines of the layer, used instead of draw_layer when rendering to SVG.
+    // Layers returning None are rasterized and embedded in the SVG as images.
+    fn vector_paths(
+        &self,
+        _img_width: u32,
+        _img_height: u32,
+        _projection: &P,
+        _center_longitude: f32,
+        _complete_map: &CompleteMap<S>,
+    ) -> Option<Vec<SvgPath>> {
+        None
+    }
 }
 
 // struct RiversLayer<S: MapShape> {
@@ -2,7 +2,7 @@ use image::{ImageBuffer, Rgba};
 
 use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
 
-use super::{layer::MapViewLayer, projection::projection::Projection};
+use super::{layer::MapViewLayer, projection::projection::Projection, svg::SvgDocument};
 
 pub struct MapView<P: Projection, S: MapShape> {
     pub projection: P,
@@ -48,4 +48,43 @@ impl<P: Projection, S: MapShape> MapView<P, S> {
 
         return imgbuffer;
     }
+
+    pub fn draw_svg(&self, complete_map: &CompleteMap<S>, filename: &str) {
+        let _ = self.return_svg(complete_map).save(filename);
+    }
+
+    // Line layers become SVG paths. Consecutive raster layers are drawn together
+    // and embedded as a single image between them.
+    pub fn return_svg(&self, complete_map: &CompleteMap<S>) -> SvgDocument {
+        let width = self.resolution[0] as u32;
+        let height = self.resolution[1] as u32;
+        let mut svg = SvgDocument::new(width, height);
+
+        let mut raster: Option<ImageBuffer<Rgba<u8>, Vec<u8>>> = None;
+        for layer in &self.layers {
+            match layer.vector_paths(
+                width,
+                height,
+                &self.projection,
+                self.center[1],
+                complete_map,
+            ) {
+                Some(paths) => {
+                    if let Some(imgbuffer) = raster.take() {
+                        svg.add_image(&imgbuffer);
+                    }
+                    svg.add_paths(&paths);
+                }
+                None => {
+                    let imgbuffer = raster.get_or_insert_with(|| ImageBuffer::new(width, height));
+                    layer.draw_layer(imgbuffer, &self.projection, self.center[1], complete_map);
+                }
+            }
+        }
+        if let Some(imgbuffer) = raster {
+            svg.add_image(&imgbuffer);
+        }
+
+        return svg;
+    }
 }
@@ -12,6 +12,7 @@ pub mod rhumb_lines;
 pub mod rivers_layer;
 pub mod satellite;
 pub mod shadow_layer;
+pub mod svg;
 pub mod texture_layer;
 pub mod tree_img_layer;
 mod util;
@@ -2,7 +2,12 @@ use image::{ImageBuffer, Rgba};
 
 use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
 
-use super::{layer::MapViewLayer, projection::projection::Projection, util::color_over};
+use super::{
+    layer::MapViewLayer,
+    projection::projection::Projection,
+    svg::{project_line, SvgPath},
+    util::color_over,
+};
 
 pub struct ParallelsMeridiansLayer {
     pub color: Rgba<u8>,
@@ -172,4 +177,42 @@ where
         self.add_meridians(base_img, projection, center_longitude);
         self.add_parallels(base_img, projection, center_longitude);
     }
+
+    fn vector_paths(
+        &self,
+        img_width: u32,
+        img_height: u32,
+        projection: &P,
+        center_longitude: f32,
+        _complete_map: &CompleteMap<S>,
+    ) -> Option<Vec<SvgPath>> {
+        // sampled as in add_meridians and add_parallels
+        let n_points = 2 * img_width;
+        let mut lines: Vec<Vec<[f32; 2]>> = vec![];
+        for longitude in &self.meridians {
+            lines.push(
+                (0..=n_points)
+                    .map(|i| [i as f32 * 90.0 / img_width as f32 - 90.0, *longitude])
+                    .collect(),
+            );
+        }
+        for latitude in &self.parallels {
+            lines.push(
+                (0..=n_points)
+                    .map(|i| [*latitude, i as f32 * 180.0 / img_width as f32 - 180.0])
+                    .collect(),
+            );
+        }
+        let mut paths = vec![];
+        for points in lines {
+            for line in project_line(&points, projection, img_width, img_height, center_longitude) {
+                paths.push(SvgPath {
+                    points: line,
+                    color: self.color,
+                    width: 1.0,
+                });
+            }
+        }
+        return Some(paths);
+    }
 }
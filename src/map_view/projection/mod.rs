@@ -8,3 +8,4 @@ pub mod oblique;
 pub mod orthographic;
 pub mod projection;
 pub mod pseudocylindrical;
+pub mod tile;
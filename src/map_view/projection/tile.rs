@@ -0,0 +1,101 @@
+use super::projection::Projection;
+
+pub const TILE_SIZE: u32 = 256;
+// beyond this the f32 coordinates of the projections are not precise to the pixel
+#[allow(dead_code)]
+pub const MAX_ZOOM: u32 = 16;
+
+// One tile of a square image of TILE_SIZE * 2^zoom pixels drawn with the inner
+// projection, as in slippy maps. x is the column of the tile and y its row.
+pub struct Tile<P: Projection> {
+    pub zoom: u32,
+    pub x: u32,
+    pub y: u32,
+    projection: P,
+}
+
+impl<P: Projection> Tile<P> {
+    fn full_size(&self) -> u32 {
+        return TILE_SIZE << self.zoom;
+    }
+}
+
+impl<P: Projection> Projection for Tile<P> {
+    fn new() -> Self {
+        Self {
+            zoom: 0,
+            x: 0,
+            y: 0,
+            projection: P::new(),
+        }
+    }
+
+    fn img_to_map_coords(
+        &self,
+        x: u32,
+        y: u32,
+        _img_width: u32,
+        _img_height: u32,
+        center_longitude: f32,
+    ) -> Option<[f32; 2]> {
+        self.projection.img_to_map_coords(
+            x + self.y * TILE_SIZE,
+            y + self.x * TILE_SIZE,
+            self.full_size(),
+            self.full_size(),
+            center_longitude,
+        )
+    }
+
+    fn map_to_img_coords(
+        &self,
+        latitude: f32,
+        longitude: f32,
+        _img_width: u32,
+        _img_height: u32,
+        center_longitude: f32,
+    ) -> Option<[u32; 2]> {
+        let [img_x, img_y] = self.projection.map_to_img_coords(
+            latitude,
+            longitude,
+            self.full_size(),
+            self.full_size(),
+            center_longitude,
+        )?;
+        let img_x = img_x.checked_sub(self.y * TILE_SIZE)?;
+        let img_y = img_y.checked_sub(self.x * TILE_SIZE)?;
+        if img_x >= TILE_SIZE || img_y >= TILE_SIZE {
+            return None;
+        }
+        return Some([img_x, img_y]);
+    }
+
+    fn get_latitude(&self, img_x: u32, img_y: u32, _img_width: u32, _img_height: u32) -> f32 {
+        return self.projection.get_latitude(
+            img_x + self.y * TILE_SIZE,
+            img_y + self.x * TILE_SIZE,
+            self.full_size(),
+            self.full_size(),
+        );
+    }
+
+    fn get_img_y(
+        &self,
+        latitude: f32,
+        longitude: f32,
+        _img_width: u32,
+        _img_height: u32,
+        center_longitude: f32,
+    ) -> u32 {
+        return self
+            .projection
+            .get_img_y(
+                latitude,
+                longitude,
+                self.full_size(),
+                self.full_size(),
+                center_longitude,
+            )
+            .wrapping_sub(self.x * TILE_SIZE);
+    }
+}
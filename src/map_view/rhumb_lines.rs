@@ -2,7 +2,12 @@ use image::{ImageBuffer, Rgba};
 
 use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
 
-use super::{layer::MapViewLayer, projection::projection::Projection, util::color_over};
+use super::{
+    layer::MapViewLayer,
+    projection::projection::Projection,
+    svg::{project_line, SvgPath},
+    util::color_over,
+};
 
 pub struct RhumbLinesLayer {
     pub color: Rgba<u8>,
@@ -28,6 +33,43 @@ impl RhumbLinesLayer {
         }
     }
 
+    // Same points as add_line, as [latitude, longitude] runs interrupted by land.
+    fn line_points<P: Projection, S: MapShape>(
+        &self,
+        [img_width, img_height]: [u32; 2],
+        projection: &P,
+        center_longitude: f32,
+        center_point: [f32; 2],
+        angle: f32,
+        complete_map: &CompleteMap<S>,
+    ) -> Vec<Vec<[f32; 2]>> {
+        let mut lines = vec![vec![]];
+        let angle_sin = angle.to_radians().sin();
+        let angle_cos = angle.to_radians().cos();
+        for i in 0..4 * img_width {
+            let latitude = center_point[0] + angle_cos * i as f32 * (180.0 / img_width as f32);
+            let longitude = center_point[1] + angle_sin * i as f32 * (180.0 / img_width as f32);
+            if !self.in_land && complete_map.height.get(latitude, longitude) > 0 {
+                lines.push(vec![]);
+                continue;
+            }
+            if projection
+                .map_to_img_coords(latitude, longitude, img_width, img_height, center_longitude)
+                .is_none()
+            {
+                break;
+            }
+            if latitude >= 90.0 || latitude <= -90.0 {
+                break;
+            }
+            if angle == 0.0 && longitude >= center_point[1] + 360.0 {
+                break;
+            }
+            lines.last_mut().unwrap().push([latitude, longitude]);
+        }
+        return lines;
+    }
+
     fn add_line<P: Projection, S: MapShape>(
         &self,
         base_img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
@@ -104,4 +146,39 @@ where
             }
         }
     }
+
+    fn vector_paths(
+        &self,
+        img_width: u32,
+        img_height: u32,
+        projection: &P,
+        center_longitude: f32,
+        complete_map: &CompleteMap<S>,
+    ) -> Option<Vec<SvgPath>> {
+        let mut paths = vec![];
+        for center_point in &self.center_points {
+            for i in 0..self.lines_per_point {
+                let angle = (i * 360 / self.lines_per_point) as f32;
+                for line in self.line_points(
+                    [img_width, img_height],
+                    projection,
+                    center_longitude,
+                    *center_point,
+                    angle,
+                    complete_map,
+                ) {
+                    for path in
+                        project_line(&line, projection, img_width, img_height, center_longitude)
+                    {
+                        paths.push(SvgPath {
+                            points: path,
+                            color: self.color,
+                            width: 1.0,
+                        });
+                    }
+                }
+            }
+        }
+        return Some(paths);
+    }
 }
@@ -2,7 +2,12 @@ use image::{ImageBuffer, Rgba};
 
 use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
 
-use super::{layer::MapViewLayer, projection::projection::Projection, util::color_over};
+use super::{
+    layer::MapViewLayer,
+    projection::projection::Projection,
+    svg::{project_line, SvgPath},
+    util::color_over,
+};
 
 pub struct RiversLayer {
     pub color: Rgba<u8>,
@@ -35,11 +40,9 @@ where
             let mut opt_prev_img_x = None;
             let mut opt_prev_img_y = None;
             for point in river {
-                let latitude =
-                    point.position[0] as f32 * 180.0 / complete_map.height.height as f32 - 90.0;
-                let longitude = point.position[1] as f32 * 360.0
-                    / complete_map.height.values[point.position[0]].len() as f32
-                    - 180.0;
+                let [latitude, longitude] = complete_map
+                    .height
+                    .convert_coords(point.position[0], point.position[1]);
                 if let Some([img_x, img_y]) = projection.map_to_img_coords(
                     latitude,
                     longitude,
@@ -83,4 +86,34 @@ where
             }
         }
     }
+
+    fn vector_paths(
+        &self,
+        img_width: u32,
+        img_height: u32,
+        projection: &P,
+        center_longitude: f32,
+        complete_map: &CompleteMap<S>,
+    ) -> Option<Vec<SvgPath>> {
+        let mut paths = vec![];
+        for river in complete_map.rivers.iter() {
+            let points: Vec<[f32; 2]> = river
+                .iter()
+                .map(|point| {
+                    let [latitude, longitude] = complete_map
+                        .height
+                        .convert_coords(point.position[0], point.position[1]);
+                    [latitude, longitude]
+                })
+                .collect();
+            for line in project_line(&points, projection, img_width, img_height, center_longitude) {
+                paths.push(SvgPath {
+                    points: line,
+                    color: self.color,
+                    width: 1.0,
+                });
+            }
+        }
+        return Some(paths);
+    }
 }
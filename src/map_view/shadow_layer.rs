@@ -88,11 +88,7 @@ where
                         base_img.height() as u32,
                         center_longitude,
                     ) {
-                        let map_x2 = (height_map.height as f32 * (lat2 + 90.0).rem_euclid(180.0)
-                            / 180.0) as usize;
-                        let row_len = height_map.values[map_x2].len();
-                        let map_y2: usize =
-                            (row_len as f32 * (long2 + 180.0).rem_euclid(360.0) / 360.0) as usize;
+                        let [map_x2, map_y2] = height_map.convert_to_vec_coords(lat2, long2);
 
                         if height_map.values[map_x2][map_y2] > 0 {
                             return sqrt((k.pow(2) + l.pow(2)) as u32);
@@ -192,11 +188,7 @@ where
                     base_img.height() as u32,
                     center_longitude,
                 ) {
-                    let map_x = (height_map.height as f32 * (lat + 90.0).rem_euclid(180.0) / 180.0)
-                        as usize;
-                    let row_len = height_map.values[map_x].len();
-                    let map_y =
-                        (row_len as f32 * (long + 180.0).rem_euclid(360.0) / 360.0) as usize;
+                    let [map_x, map_y] = height_map.convert_to_vec_coords(lat, long);
                     if height_map.values[map_x][map_y] <= 0 {
                         let dist = self.pixel_dist_from_shore(
                             base_img,
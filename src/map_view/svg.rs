@@ -0,0 +1,158 @@
+use std::{fmt::Write, fs, io};
+
+use image::{codecs::png::PngEncoder, ColorType, ImageBuffer, Rgba};
+
+use super::projection::projection::Projection;
+
+// A line in image coordinates, [horizontal, vertical] from the top left corner.
+pub struct SvgPath {
+    pub points: Vec<[f32; 2]>,
+    pub color: Rgba<u8>,
+    pub width: f32,
+}
+
+// Projects a line of [latitude, longitude] points. The line is split where the
+// projection is undefined, and where it jumps across the image, as when it crosses
+// the edge of the map.
+pub fn project_line<P: Projection>(
+    points: &[[f32; 2]],
+    projection: &P,
+    img_width: u32,
+    img_height: u32,
+    center_longitude: f32,
+) -> Vec<Vec<[f32; 2]>> {
+    let max_jump = (img_width.max(img_height) / 16) as f32;
+    let mut lines = vec![];
+    let mut line: Vec<[f32; 2]> = vec![];
+    for [latitude, longitude] in points {
+        let Some([img_x, img_y]) = projection.map_to_img_coords(
+            *latitude,
+            *longitude,
+            img_width,
+            img_height,
+            center_longitude,
+        ) else {
+            lines.push(std::mem::take(&mut line));
+            continue;
+        };
+        // map_to_img_coords returns [row, column]
+        let point = [img_y as f32, img_x as f32];
+        if let Some(last) = line.last() {
+            if *last == point {
+                continue;
+            }
+            if (last[0] - point[0]).abs() > max_jump || (last[1] - point[1]).abs() > max_jump {
+                lines.push(std::mem::take(&mut line));
+            }
+        }
+        line.push(point);
+    }
+    lines.push(line);
+    lines.retain(|line| line.len() > 1);
+    return lines;
+}
+
+pub struct SvgDocument {
+    width: u32,
+    height: u32,
+    body: String,
+    n_layers: usize,
+}
+
+impl SvgDocument {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            body: String::new(),
+            n_layers: 0,
+        }
+    }
+
+    // Every call adds an Inkscape layer, so that the parts of the map can be edited separately.
+    fn open_layer(&mut self, label: &str) {
+        self.n_layers += 1;
+        let _ = writeln!(
+            self.body,
+            r#"<g inkscape:groupmode="layer" inkscape:label="{} {}" id="layer{}">"#,
+            label, self.n_layers, self.n_layers
+        );
+    }
+
+    pub fn add_image(&mut self, img: &ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let mut png = Vec::new();
+        if PngEncoder::new(&mut png)
+            .encode(img, img.width(), img.height(), ColorType::Rgba8)
+            .is_err()
+        {
+            return;
+        }
+        self.open_layer("raster");
+        let _ = writeln!(
+            self.body,
+            r#"<image width="{}" height="{}" xlink:href="data:image/png;base64,{}"/>"#,
+            img.width(),
+            img.height(),
+            base64(&png)
+        );
+        self.body.push_str("</g>\n");
+    }
+
+    pub fn add_paths(&mut self, paths: &[SvgPath]) {
+        self.open_layer("lines");
+        for path in paths {
+            let mut d = String::new();
+            for (i, [x, y]) in path.points.iter().enumerate() {
+                let command = if i == 0 { 'M' } else { 'L' };
+                let _ = write!(d, "{}{:.1} {:.1} ", command, x, y);
+            }
+            let _ = writeln!(
+                self.body,
+                r#"<path d="{}" fill="none" stroke="rgb({},{},{})" stroke-opacity="{:.3}" stroke-width="{}" stroke-linejoin="round" stroke-linecap="round"/>"#,
+                d.trim_end(),
+                path.color[0],
+                path.color[1],
+                path.color[2],
+                path.color[3] as f32 / 255.0,
+                path.width
+            );
+        }
+        self.body.push_str("</g>\n");
+    }
+
+    pub fn to_svg_string(&self) -> String {
+        return format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                "\n",
+                r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" "#,
+                r#"xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" "#,
+                r#"width="{}" height="{}" viewBox="0 0 {} {}">"#,
+                "\n{}</svg>\n"
+            ),
+            self.width, self.height, self.width, self.height, self.body
+        );
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        fs::write(filename, self.to_svg_string())
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    return encoded;
+}
@@ -3,7 +3,10 @@ use std::time::Instant;
 use image::{open, DynamicImage, GenericImageView, ImageBuffer, Rgba};
 use rand::Rng;
 
-use crate::{complete_map::CompleteMap, pipeline_steps::temperature, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, pipeline_steps::temperature, seed::seeded_rng,
+    shapes::map_shape::MapShape,
+};
 
 use super::{layer::MapViewLayer, projection::projection::Projection, util::color_over};
 
@@ -122,6 +125,8 @@ impl TreeImgLayer {
         complete_map: &CompleteMap<S>,
     ) -> Vec<[u32; 2]> {
         let mut positions = vec![];
+        // the same view always gets the same trees
+        let mut rng = seeded_rng(0);
 
         let height = base_img.height();
         let width = base_img.width();
@@ -164,7 +169,6 @@ impl TreeImgLayer {
                         icon_height,
                         icon_width,
                     ) {
-                        let mut rng = rand::thread_rng();
                         let r1 = rng.gen_range(-4..=4);
                         let r2 = rng.gen_range(-4..=4);
                         positions.push([(img_x as i32 + r1) as u32, (img_y as i32 + r2) as u32]);
@@ -2,12 +2,19 @@ use std::fs;
 
 use crate::{
     complete_map::CompleteMap,
+    map_file::layers::LayerId,
     map_view::{
         projection::{
-            azimutal::Azimutal, double_azimutal::DoubleAzimutal,
-            double_orthographic::DoubleOrthographic, equirectangular::Equirectangular,
-            mercator::Mercator, mollweide::Mollweide, oblique::Oblique, orthographic::Orthographic,
+            azimutal::Azimutal,
+            double_azimutal::DoubleAzimutal,
+            double_orthographic::DoubleOrthographic,
+            equirectangular::Equirectangular,
+            mercator::Mercator,
+            mollweide::Mollweide,
+            oblique::Oblique,
+            orthographic::Orthographic,
             pseudocylindrical::PseudoCylindrical,
+            tile::{Tile, TILE_SIZE},
         },
         util::deserialize_rgba,
     },
@@ -61,6 +68,12 @@ pub struct ViewConfiguration {
     #[serde(deserialize_with = "deserialize_rgba")]
     pub parallels_color: Rgba<u8>,
     pub height_colors: Option<GradientColorScheme>,
+    // draw svg files, with line layers as paths, instead of png files
+    #[serde(default)]
+    pub svg: bool,
+    // seed of the random colors of categories, as in the plates layer
+    #[serde(default)]
+    pub color_seed: u64,
 }
 
 impl ViewConfiguration {
@@ -80,8 +93,53 @@ impl ViewConfiguration {
             parallels_interval: 0.0,
             parallels_color: Rgba([0, 0, 0, 0]),
             height_colors: None,
+            svg: false,
+            color_seed: 0,
         }
     }
+
+    // Default view with the given layers, as used for tiles of unregistered layer sets.
+    #[allow(dead_code)]
+    pub fn with_layers(layers: Vec<String>) -> Self {
+        let mut view_config = Self::default();
+        view_config.layers = layers;
+        return view_config;
+    }
+
+    // Map layers read by the view, so that only those are loaded from a map file.
+    #[allow(dead_code)]
+    pub fn required_layers(&self) -> Vec<LayerId> {
+        let mut required = vec![LayerId::Height, LayerId::Coastline];
+        for layer in &self.layers {
+            let layer_ids: &[LayerId] = match layer.as_str() {
+                "climate" => &[LayerId::Climate],
+                "annual_precipitation" => &[LayerId::AnnualPrecipitation],
+                "plates" => &[LayerId::TectonicPlates],
+                "rivers" => &[LayerId::Rivers],
+                "continentality" => &[LayerId::Continentality],
+                "satellite" => &[
+                    LayerId::Climate,
+                    LayerId::Temperature,
+                    LayerId::VegetationDensity,
+                ],
+                "temperature" => &[LayerId::Temperature],
+                "trees" => &[LayerId::Temperature, LayerId::VegetationDensity],
+                "rhumb_lines"
+                | "parallels_and_meridians"
+                | "contour"
+                | "paper_texture"
+                | "mountains"
+                | "relief_shadow" => &[],
+                _ => &[LayerId::CustomLayers],
+            };
+            for layer_id in layer_ids {
+                if !required.contains(layer_id) {
+                    required.push(*layer_id);
+                }
+            }
+        }
+        return required;
+    }
 }
 
 pub fn create_view<P: Projection, S: MapShape + 'static>(
@@ -120,7 +178,7 @@ pub fn create_view<P: Projection, S: MapShape + 'static>(
                 if mv.layers.len() == 0 {
                     mv.layers.push(pmap_layer!(
                         tectonic_plates,
-                        CategoryColorScheme::new_random(50)
+                        CategoryColorScheme::new_random(50, view_config.color_seed)
                     ));
                 } else {
                     mv.layers.push(Box::new(ContourLayer::new(
@@ -205,7 +263,11 @@ pub fn draw_with_config<S: MapShape + 'static>(
                 mv.center[1] = $rotation;
                 mv.projection.longitude = i as f32 * 360.0 / view_config.rotation_frames as f32;
                 mv.projection.latitude = $latitude;
-                mv.draw(&$cmap, &format!("{}/{}.png", $view_config.output_path, i));
+                if $view_config.svg {
+                    mv.draw_svg(&$cmap, &format!("{}/{}.svg", $view_config.output_path, i));
+                } else {
+                    mv.draw(&$cmap, &format!("{}/{}.png", $view_config.output_path, i));
+                }
             }
         }};
     }
@@ -355,3 +417,22 @@ pub fn img_from_config<S: MapShape + 'static>(
         ),
     }
 }
+
+// Web Mercator tile of the whole map, as used by slippy maps. The center, rotation,
+// resolution and projection of the configuration are not used.
+#[allow(dead_code)]
+pub fn tile_from_config<S: MapShape + 'static>(
+    cmap: &CompleteMap<S>,
+    view_config: &ViewConfiguration,
+    zoom: u32,
+    x: u32,
+    y: u32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut mv = create_view::<Tile<Mercator>, S>(view_config);
+    mv.center = [0.0, 0.0];
+    mv.resolution = [TILE_SIZE as usize, TILE_SIZE as usize];
+    mv.projection.zoom = zoom;
+    mv.projection.x = x;
+    mv.projection.y = y;
+    return mv.return_image_buffer(cmap);
+}
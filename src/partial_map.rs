@@ -5,7 +5,7 @@ use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelI
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    complete_map::CompleteMap, map_view::color_scheme::CategoryColorScheme,
+    complete_map::CompleteMap, error::MapResult, map_view::color_scheme::CategoryColorScheme,
     shapes::map_shape::MapShape,
 };
 
@@ -29,33 +29,45 @@ impl<S: MapShape, T: Clone + Default> PartialMap<S, T> {
             values: S::new_vec(circunference, height),
         }
     }
+
+    // Map of the rows of an image or grid covering the world, the first one at latitude -90.
+    // Shapes whose rows are not bands of latitude take the value of the grid at their cells.
+    pub fn from_rows(rows: Vec<Vec<T>>) -> Self {
+        let width = rows.first().map_or(0, |row| row.len());
+        let mut pmap = Self::new(width, rows.len());
+        if S::LAT_LON_GRID || width == 0 {
+            pmap.values = rows;
+            return pmap;
+        }
+        for x in 0..pmap.values.len() {
+            for y in 0..pmap.values[x].len() {
+                let [latitude, longitude] = pmap.convert_coords(x, y);
+                let row =
+                    (((latitude + 90.0) / 180.0 * rows.len() as f32) as usize).min(rows.len() - 1);
+                let column = (((longitude + 180.0) / 360.0 * width as f32) as usize).min(width - 1);
+                pmap.values[x][y] = rows[row][column].clone();
+            }
+        }
+        return pmap;
+    }
 }
 
 impl<S: MapShape, T: Clone> PartialMap<S, T> {
     pub fn get(&self, latitude: f32, longitude: f32) -> T {
-        let n_rows = self.values.len() as f32;
-        let x = ((n_rows * (latitude + 90.0) / 180.0) as usize).min(self.values.len() - 1);
-        let n_cols = self.values[x].len() as f32;
-        let y = (n_cols * (longitude + 180.0) / 360.0)
-            .rem_euclid(n_cols)
-            .min(n_cols - 1.0) as usize;
+        let [x, y] = self.shape.cell_at(&self.values, latitude, longitude);
         return self.values[x][y].clone();
     }
 
     pub fn convert_coords(&self, x: usize, y: usize) -> [f32; 2] {
-        let latitude = x as f32 * 180.0 / self.values.len() as f32 - 90.0;
-        let longitude = y as f32 * 360.0 / self.values[x].len() as f32 - 180.0;
-        return [latitude, longitude];
+        return self.shape.cell_coords(&self.values, x, y);
+    }
+
+    pub fn cell_center(&self, x: usize, y: usize) -> [f32; 2] {
+        return self.shape.cell_center(&self.values, x, y);
     }
 
     pub fn convert_to_vec_coords(&self, latitude: f32, longitude: f32) -> [usize; 2] {
-        let n_rows = self.values.len() as f32;
-        let x = ((n_rows * (latitude + 90.0) / 180.0) as usize).min(self.values.len() - 1);
-        let n_cols = self.values[x].len() as f32;
-        let y = (n_cols * (longitude + 180.0) / 360.0)
-            .rem_euclid(n_cols)
-            .min(n_cols - 1.0) as usize;
-        return [x, y];
+        return self.shape.cell_at(&self.values, latitude, longitude);
     }
 
     pub fn convert_to_spatial_coords(&self, x: usize, y: usize) -> [f32; 3] {
@@ -86,6 +98,19 @@ impl<S: MapShape, T: Clone> PartialMap<S, T> {
     }
 }
 
+impl<S: MapShape, T: Clone + Into<f64>> PartialMap<S, T> {
+    // Value at a point, interpolated between the closest cells
+    pub fn interpolate_at(&self, latitude: f32, longitude: f32) -> f32 {
+        let cells = self
+            .shape
+            .interpolation_cells(&self.values, latitude, longitude);
+        return cells
+            .iter()
+            .map(|([x, y], weight)| self.values[*x][*y].clone().into() as f32 * weight)
+            .sum();
+    }
+}
+
 impl<S: MapShape, T: Clone + Send + Sync> PartialMap<S, T> {
     pub fn iterate_operator<'a, F>(&mut self, input_map: &'a CompleteMap<S>, operator: F)
     where
@@ -132,65 +157,41 @@ impl<S: MapShape> PartialMap<S, i32> {
         return value as i32;
     }
 
-    pub fn save_as_img(&self, filename: &str, min_value: i32, max_value: i32) {
+    pub fn save_as_img(&self, filename: &str, min_value: i32, max_value: i32) -> MapResult<()> {
         let mut imgbuffer: ImageBuffer<Rgba<u8>, Vec<u8>> =
             ImageBuffer::new(self.circunference as u32, self.height as u32);
         for img_x in 0..self.circunference {
             for img_y in 0..self.height {
-                let y = (img_x * self.values[img_y].len() / self.circunference)
-                    .min(self.values[img_y].len() - 1);
-                if self.values[img_y][y] > 0 {
-                    let value = ((255 * self.values[img_y][y] / max_value) as u8).max(1);
+                let height = if S::LAT_LON_GRID {
+                    let y = (img_x * self.values[img_y].len() / self.circunference)
+                        .min(self.values[img_y].len() - 1);
+                    self.values[img_y][y]
+                } else {
+                    self.get(
+                        (img_y as f32 + 0.5) * 180.0 / self.height as f32 - 90.0,
+                        (img_x as f32 + 0.5) * 360.0 / self.circunference as f32 - 180.0,
+                    )
+                };
+                if height > 0 {
+                    let value = ((255 * height / max_value) as u8).max(1);
                     imgbuffer.put_pixel(img_x as u32, img_y as u32, Rgba([0, value, 0, 255]));
                 } else {
-                    let value = ((-255 * self.values[img_y][y] / min_value) as u8).max(1);
+                    let value = ((-255 * height / min_value) as u8).max(1);
                     imgbuffer.put_pixel(img_x as u32, img_y as u32, Rgba([0, 0, value, 255]));
                 }
             }
         }
-        imgbuffer
-            .save(filename)
-            .expect("Could not save partial map image!");
+        imgbuffer.save(filename)?;
+        Ok(())
     }
 }
 
-pub fn load_from_img<S: MapShape>(
-    filename: &str,
-    min_value: i32,
-    max_value: i32,
-) -> PartialMap<S, i32> {
-    let imgbuffer = image::open(filename).expect("File not found!");
-
-    let mut pmap =
-        PartialMap::<S, i32>::new(imgbuffer.width() as usize, imgbuffer.height() as usize);
-
-    for img_y in 0..imgbuffer.height() {
-        let mut line = vec![];
-        for img_x in 0..imgbuffer.width() {
-            let color = imgbuffer.get_pixel(img_x, img_y);
-            let value;
-            if color[1] > color[2] {
-                // land
-                value = max_value * color[1] as i32 / 254;
-            } else {
-                // water
-                value = min_value * (255 - color[2] as i32) / 255;
-            }
-            line.push(value);
-        }
-        pmap.values[img_y as usize] = line;
-    }
-    return pmap;
-}
-
 pub fn load_categories_from_img<S: MapShape>(
     filename: &str,
-) -> (PartialMap<S, usize>, CategoryColorScheme) {
-    let imgbuffer = image::open(filename).expect("File not found!");
-
-    let mut pmap =
-        PartialMap::<S, usize>::new(imgbuffer.width() as usize, imgbuffer.height() as usize);
+) -> MapResult<(PartialMap<S, usize>, CategoryColorScheme)> {
+    let imgbuffer = image::open(filename)?;
 
+    let mut rows = vec![];
     let mut colors: HashMap<Rgba<u8>, usize> = HashMap::new();
     let mut color_map: HashMap<usize, Rgba<u8>> = HashMap::new();
 
@@ -211,12 +212,13 @@ pub fn load_categories_from_img<S: MapShape>(
                 }
             }
         }
-        pmap.values[img_y as usize] = line;
+        rows.push(line);
     }
+    let pmap = PartialMap::<S, usize>::from_rows(rows);
 
     let color_scheme = CategoryColorScheme {
         color_map: color_map,
     };
 
-    return (pmap, color_scheme);
+    return Ok((pmap, color_scheme));
 }
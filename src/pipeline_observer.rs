@@ -0,0 +1,214 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use image::Rgba;
+use serde::Serialize;
+
+use crate::{
+    complete_map::CompleteMap,
+    error::MapResult,
+    map_pipeline::StepProgress,
+    map_view::{
+        color_scheme::DEFAULT_COLORS, map_view::MapView,
+        parallels_meridians_layer::ParallelsMeridiansLayer, partial_map_layer::PartialMapLayer,
+        projection::equirectangular::Equirectangular, rivers_layer::RiversLayer,
+    },
+    pipeline_steps::pipeline_step::SubStepTiming,
+    shapes::map_shape::MapShape,
+};
+
+// Notified by MapPipeline::execute around every step. `description` is the Debug output
+// of the step, with its parameters. An error stops the execution and is returned by it.
+pub trait PipelineObserver<S: MapShape>: Send {
+    fn before_step(&mut self, _index: usize, _description: &str, _map: &CompleteMap<S>) {}
+
+    fn after_step(
+        &mut self,
+        _progress: &StepProgress,
+        _description: &str,
+        _map: &CompleteMap<S>,
+    ) -> MapResult<()> {
+        return Ok(());
+    }
+
+    // A step skipped because the map after it was read from a checkpoint
+    fn cached_step(&mut self, _progress: &StepProgress, _description: &str) -> MapResult<()> {
+        return Ok(());
+    }
+
+    fn finished(&mut self, _map: &CompleteMap<S>, _elapsed: f32) -> MapResult<()> {
+        return Ok(());
+    }
+}
+
+// Calls a function with the progress of every step, run or cached.
+pub struct ProgressObserver<F: FnMut(&StepProgress) + Send>(pub F);
+
+impl<S: MapShape, F: FnMut(&StepProgress) + Send> PipelineObserver<S> for ProgressObserver<F> {
+    fn after_step(
+        &mut self,
+        progress: &StepProgress,
+        _description: &str,
+        _map: &CompleteMap<S>,
+    ) -> MapResult<()> {
+        (self.0)(progress);
+        return Ok(());
+    }
+
+    fn cached_step(&mut self, progress: &StepProgress, _description: &str) -> MapResult<()> {
+        (self.0)(progress);
+        return Ok(());
+    }
+}
+
+// Draws the height and the rivers after every step, as <dir>/<step index>.png.
+pub struct SnapshotWriter {
+    dir: PathBuf,
+}
+
+impl SnapshotWriter {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+}
+
+impl<S: MapShape + 'static> PipelineObserver<S> for SnapshotWriter {
+    fn after_step(
+        &mut self,
+        progress: &StepProgress,
+        _description: &str,
+        map: &CompleteMap<S>,
+    ) -> MapResult<()> {
+        let mut mv: MapView<Equirectangular, S> = MapView::new();
+        let mut pm_layer = ParallelsMeridiansLayer::default();
+        pm_layer.color = Rgba([255, 255, 255, 150]);
+        mv.resolution = [map.height.circunference, map.height.height];
+        mv.center[1] = 0.0;
+        mv.layers = vec![
+            Box::new(PartialMapLayer::new(
+                |m| &m.height,
+                Box::new(DEFAULT_COLORS.clone()),
+            )),
+            Box::new(RiversLayer::default()),
+            Box::new(pm_layer),
+        ];
+        fs::create_dir_all(&self.dir)?;
+        mv.return_image_buffer(map)
+            .save(self.dir.join(format!("{}.png", progress.index)))?;
+        return Ok(());
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StepStats {
+    pub index: usize,
+    pub name: String,
+    pub duration: f32,
+    pub cached: bool,
+    pub sub_steps: Vec<SubStepTiming>,
+    // of the map after the step, absent for cached steps
+    pub height_min: Option<i32>,
+    pub height_max: Option<i32>,
+    pub height_mean: Option<f32>,
+    // fraction of the pixels above the sea level
+    pub land: Option<f32>,
+    pub rivers: Option<usize>,
+}
+
+// Keeps the statistics of the map after every step, readable through `stats()` and
+// written as JSON to `output` when the pipeline finishes.
+pub struct StatsCollector {
+    stats: Arc<Mutex<Vec<StepStats>>>,
+    output: Option<PathBuf>,
+}
+
+impl StatsCollector {
+    pub fn new(output: Option<&str>) -> Self {
+        Self {
+            stats: Arc::new(Mutex::new(vec![])),
+            output: output.map(PathBuf::from),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn stats(&self) -> Arc<Mutex<Vec<StepStats>>> {
+        return self.stats.clone();
+    }
+
+    fn push(&self, step_stats: StepStats) {
+        self.stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(step_stats);
+    }
+}
+
+impl<S: MapShape> PipelineObserver<S> for StatsCollector {
+    fn after_step(
+        &mut self,
+        progress: &StepProgress,
+        _description: &str,
+        map: &CompleteMap<S>,
+    ) -> MapResult<()> {
+        let mut min = None;
+        let mut max = None;
+        let mut sum = 0.0;
+        let mut land = 0;
+        let mut count = 0;
+        for value in map.height.values.iter().flatten() {
+            min = Some(min.map_or(*value, |m: i32| m.min(*value)));
+            max = Some(max.map_or(*value, |m: i32| m.max(*value)));
+            sum += *value as f64;
+            if *value > 0 {
+                land += 1;
+            }
+            count += 1;
+        }
+        self.push(StepStats {
+            index: progress.index,
+            name: progress.name.clone(),
+            duration: progress.duration,
+            cached: false,
+            sub_steps: progress.sub_steps.clone(),
+            height_min: min,
+            height_max: max,
+            height_mean: (count > 0).then(|| (sum / count as f64) as f32),
+            land: (count > 0).then(|| land as f32 / count as f32),
+            rivers: Some(map.rivers.len()),
+        });
+        return Ok(());
+    }
+
+    fn cached_step(&mut self, progress: &StepProgress, _description: &str) -> MapResult<()> {
+        self.push(StepStats {
+            index: progress.index,
+            name: progress.name.clone(),
+            duration: 0.0,
+            cached: true,
+            sub_steps: vec![],
+            height_min: None,
+            height_max: None,
+            height_mean: None,
+            land: None,
+            rivers: None,
+        });
+        return Ok(());
+    }
+
+    fn finished(&mut self, _map: &CompleteMap<S>, _elapsed: f32) -> MapResult<()> {
+        let Some(output) = &self.output else {
+            return Ok(());
+        };
+        let stats = self
+            .stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        fs::write(output, serde_json::to_string_pretty(&*stats)?)?;
+        return Ok(());
+    }
+}
@@ -1,7 +1,9 @@
-use std::sync::Arc;
-
 use crate::{
-    complete_map::CompleteMap, partial_map::PartialMap, pipeline_steps::util::percentile_2d_vector,
+    complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    map_file::layers::LayerId,
+    partial_map::PartialMap,
+    pipeline_steps::{layer_step::LayerValue, util::percentile_2d_vector},
     shapes::map_shape::MapShape,
 };
 
@@ -10,15 +12,21 @@ use super::pipeline_step::PipelineStep;
 pub fn adjust_percentiles<S: MapShape>(
     percentiles: Vec<(f32, i32)>,
     pmap: &mut PartialMap<S, i32>,
-) {
-    let minimum = percentile_2d_vector(&pmap.values, 0.0).unwrap();
+) -> MapResult<()> {
+    let invalid_percentile =
+        |p: f32| MapError::InvalidData(format!("cannot compute percentile {} of the map", p));
+    let minimum = percentile_2d_vector(&pmap.values, 0.0).ok_or(invalid_percentile(0.0))?;
 
     let mut v = vec![];
     for (key, value) in &percentiles {
-        let curr_value = percentile_2d_vector(&pmap.values, *key).unwrap();
+        let curr_value =
+            percentile_2d_vector(&pmap.values, *key).ok_or_else(|| invalid_percentile(*key))?;
         v.push((*value, curr_value))
     }
-    let min_value = minimum.min(percentiles[0].1);
+    let Some((_, first_value)) = percentiles.first() else {
+        return Err(MapError::InvalidData("empty percentile list".to_string()));
+    };
+    let min_value = minimum.min(*first_value);
 
     for i in 0..pmap.values.len() {
         for j in 0..pmap.values[i].len() {
@@ -37,28 +45,39 @@ pub fn adjust_percentiles<S: MapShape>(
             }
         }
     }
+    Ok(())
 }
 
+// Adjusts the height, or any other integer layer named by `layer`
 #[derive(Debug)]
 pub struct AdjustHeightPercentiles {
     percentiles: Vec<(f32, i32)>,
+    pub layer: String,
 }
 
 impl AdjustHeightPercentiles {
     pub fn new(percentiles: Vec<(f32, i32)>) -> Self {
-        Self { percentiles }
+        Self {
+            percentiles,
+            layer: "height".to_string(),
+        }
     }
 }
 
 impl<S: MapShape> PipelineStep<S> for AdjustHeightPercentiles {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let Some(pmap) = i32::layer_mut(map, &self.layer) else {
+            return Err(MapError::MissingLayer(self.layer.clone()));
+        };
+        return adjust_percentiles(self.percentiles.clone(), pmap);
     }
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        adjust_percentiles(self.percentiles.clone(), &mut output_map.height);
-        return output_map;
+    fn reads(&self) -> Vec<LayerId> {
+        return PipelineStep::<S>::writes(self);
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return LayerId::containing(&self.layer).into_iter().collect();
     }
 }
 
@@ -66,6 +85,7 @@ impl<S: MapShape> PipelineStep<S> for AdjustHeightPercentiles {
 pub struct AdjustLandHeightPercentiles {
     percentiles: Vec<(f32, i32)>,
     water_percentage: f32,
+    pub layer: String,
 }
 
 impl AdjustLandHeightPercentiles {
@@ -73,23 +93,30 @@ impl AdjustLandHeightPercentiles {
         Self {
             percentiles: percentiles.clone(),
             water_percentage,
+            layer: "height".to_string(),
         }
     }
 }
 
 impl<S: MapShape> PipelineStep<S> for AdjustLandHeightPercentiles {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
         let mut percentiles = vec![(self.water_percentage, 0)];
         for (k, v) in &self.percentiles {
             let k2: f32 = self.water_percentage + k * (100.0 - self.water_percentage) / 100.0;
             percentiles.push((k2, *v));
         }
 
-        return AdjustHeightPercentiles::new(percentiles).apply(input_map);
+        let mut step = AdjustHeightPercentiles::new(percentiles);
+        step.layer = self.layer.clone();
+        return step.apply(map);
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return PipelineStep::<S>::writes(self);
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return LayerId::containing(&self.layer).into_iter().collect();
     }
 }
 
@@ -97,6 +124,7 @@ impl<S: MapShape> PipelineStep<S> for AdjustLandHeightPercentiles {
 pub struct AdjustOceanDepthPercentiles {
     percentiles: Vec<(f32, i32)>,
     water_percentage: f32,
+    pub layer: String,
 }
 
 impl AdjustOceanDepthPercentiles {
@@ -104,23 +132,30 @@ impl AdjustOceanDepthPercentiles {
         Self {
             percentiles: percentiles.clone(),
             water_percentage,
+            layer: "height".to_string(),
         }
     }
 }
 
 impl<S: MapShape> PipelineStep<S> for AdjustOceanDepthPercentiles {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
         let mut percentiles = vec![];
         for (k, v) in &self.percentiles {
             let k2: f32 = k * self.water_percentage / 100.0;
             percentiles.push((k2, *v));
         }
 
-        return AdjustHeightPercentiles::new(percentiles).apply(input_map);
+        let mut step = AdjustHeightPercentiles::new(percentiles);
+        step.layer = self.layer.clone();
+        return step.apply(map);
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return PipelineStep::<S>::writes(self);
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return LayerId::containing(&self.layer).into_iter().collect();
     }
 }
 
@@ -138,15 +173,18 @@ impl AdjustPrecipitationPercentiles {
 }
 
 impl<S: MapShape> PipelineStep<S> for AdjustPrecipitationPercentiles {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        for pmap in map.precipitation.iter_mut() {
+            adjust_percentiles(self.percentiles.clone(), pmap)?;
+        }
+        return Ok(());
     }
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        for pmap in output_map.precipitation.iter_mut() {
-            adjust_percentiles(self.percentiles.clone(), pmap);
-        }
-        return output_map;
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Precipitation];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Precipitation];
     }
 }
@@ -1,9 +1,8 @@
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
-
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
-use std::sync::Arc;
-
-use super::pipeline_step::PipelineStep;
+use super::{layer_step::apply_to_layer, pipeline_step::PipelineStep, smooth::Smooth};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 #[derive(Debug)]
 pub struct SmoothPrecipitation {
@@ -17,70 +16,61 @@ impl SmoothPrecipitation {
 }
 
 impl<S: MapShape> PipelineStep<S> for SmoothPrecipitation {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let neighbors = input_map
-            .annual_precipitation
-            .get_pixel_neighbours([x, y], self.pixel_distance);
-        let mut sum = 0;
-        let mut len = 0;
-        for i in 0..neighbors.len() {
-            for j in 0..neighbors[i].len() {
-                sum += neighbors[i][j];
-                len += 1;
-            }
-        }
-        let mean = sum / len;
-        return mean;
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        map.require_annual_precipitation()?;
+        let smooth = Smooth {
+            pixel_distance: self.pixel_distance,
+        };
+        return apply_to_layer::<S, i32, _>(&smooth, "annual_precipitation", map);
     }
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::AnnualPrecipitation];
+    }
 
-        // process elements in parallel with rayon
-        output_map
-            .annual_precipitation
-            .values
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(x, inner_vec)| {
-                inner_vec.iter_mut().enumerate().for_each(|(y, num)| {
-                    *num = self.process_element(x, y, Arc::new(input_map));
-                });
-            });
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::AnnualPrecipitation];
     }
 }
 
 #[derive(Debug)]
 pub struct CalculateAnnualPrecipitation {}
 
-impl<S: MapShape> PipelineStep<S> for CalculateAnnualPrecipitation {
-    fn process_element(&self, x: usize, y: usize, complete_map: Arc<&CompleteMap<S>>) -> i32 {
+impl CalculateAnnualPrecipitation {
+    fn process_element<S: MapShape>(
+        &self,
+        x: usize,
+        y: usize,
+        complete_map: &CompleteMap<S>,
+    ) -> i32 {
         let mut total = 0;
         for precipitation_map in &complete_map.precipitation {
             total += precipitation_map.values[x][y];
         }
         total * 12 / complete_map.precipitation.len() as i32
     }
+}
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.annual_precipitation = PartialMap::new(
-            input_map.precipitation[0].circunference,
-            input_map.precipitation[0].height,
+impl<S: MapShape> PipelineStep<S> for CalculateAnnualPrecipitation {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        map.require_precipitation()?;
+        let mut annual_precipitation = PartialMap::new(
+            map.precipitation[0].circunference,
+            map.precipitation[0].height,
         );
-        output_map
-            .annual_precipitation
-            .values
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(x, inner_vec)| {
-                inner_vec.iter_mut().enumerate().for_each(|(y, num)| {
-                    *num = self.process_element(x, y, Arc::new(input_map));
-                });
-            });
-        let output_map = SmoothPrecipitation::new().apply(&output_map);
-        let output_map = SmoothPrecipitation::new().apply(&output_map);
-        return output_map;
+        annual_precipitation
+            .iterate_operator(map, |x, y, arc_map| self.process_element(x, y, &arc_map));
+        map.annual_precipitation = annual_precipitation;
+        SmoothPrecipitation::new().apply(map)?;
+        SmoothPrecipitation::new().apply(map)?;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Precipitation];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::AnnualPrecipitation];
     }
 }
@@ -0,0 +1,311 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+use super::{
+    define_coastlines::update_coastline, layer_step::ElementStep, pipeline_step::PipelineStep,
+    smooth::Smooth, util::CustomNoise,
+};
+
+// Area of a brush, in latitude and longitude. Polygon edges are straight lines in those coordinates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BrushArea {
+    Circle { center: [f32; 2], radius: f32 },
+    Polygon { points: Vec<[f32; 2]> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BrushOperation {
+    Raise {
+        amount: i32,
+    },
+    Lower {
+        amount: i32,
+    },
+    // Without a height, flattens to the mean height of the area
+    Flatten {
+        #[serde(default)]
+        height: Option<i32>,
+    },
+    Smooth {
+        #[serde(default = "default_smooth_distance")]
+        pixel_distance: usize,
+    },
+    Noise {
+        #[serde(default)]
+        seed: Option<u32>,
+        frequency: f32,
+        intensity: f32,
+    },
+}
+
+fn default_smooth_distance() -> usize {
+    return 2;
+}
+
+// Changes the height inside an area and updates the coastline around it.
+// Distances (radius and falloff) are in degrees, measured with the distance of the map shape.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Brush {
+    pub area: BrushArea,
+    pub operation: BrushOperation,
+    // Width of the border of the area where the effect fades out
+    #[serde(default)]
+    pub falloff: f32,
+}
+
+// Distance between two points in degrees, so that a radius means the same on every shape
+fn distance_degrees<S: MapShape, T: Clone>(
+    pmap: &PartialMap<S, T>,
+    p1: &[f32; 2],
+    p2: &[f32; 2],
+) -> f32 {
+    return pmap.get_distance(p1, p2) / pmap.get_distance(&[0.0, 0.0], &[1.0, 0.0]);
+}
+
+// A longitude moved by whole turns to within 180 degrees of `reference`, on shapes where
+// longitudes wrap around, so that polygons crossing the antimeridian keep their short edges
+fn relative_longitude<S: MapShape>(longitude: f32, reference: f32) -> f32 {
+    if !S::WRAPS_COLUMNS {
+        return longitude;
+    }
+    return reference + (longitude - reference + 180.0).rem_euclid(360.0) - 180.0;
+}
+
+// Vertex `i` of a polygon, with its longitude relative to the first vertex
+fn polygon_vertex<S: MapShape>(points: &[[f32; 2]], i: usize) -> [f32; 2] {
+    let [lat, lon] = points[i % points.len()];
+    return [lat, relative_longitude::<S>(lon, points[0][1])];
+}
+
+fn inside_polygon<S: MapShape>(points: &[[f32; 2]], [lat, lon]: [f32; 2]) -> bool {
+    let lon = relative_longitude::<S>(lon, points[0][1]);
+    let mut inside = false;
+    for i in 0..points.len() {
+        let [lat1, lon1] = polygon_vertex::<S>(points, i);
+        let [lat2, lon2] = polygon_vertex::<S>(points, i + 1);
+        if (lon1 > lon) != (lon2 > lon) && lat < lat1 + (lon - lon1) * (lat2 - lat1) / (lon2 - lon1)
+        {
+            inside = !inside;
+        }
+    }
+    return inside;
+}
+
+// Distance from a point to the closest point of the edges of a polygon
+fn polygon_edge_distance<S: MapShape, T: Clone>(
+    pmap: &PartialMap<S, T>,
+    points: &[[f32; 2]],
+    [lat, lon]: [f32; 2],
+) -> f32 {
+    let point = [lat, relative_longitude::<S>(lon, points[0][1])];
+    let mut min_distance = f32::MAX;
+    for i in 0..points.len() {
+        let a = polygon_vertex::<S>(points, i);
+        let b = polygon_vertex::<S>(points, i + 1);
+        let edge = [b[0] - a[0], b[1] - a[1]];
+        let length = edge[0] * edge[0] + edge[1] * edge[1];
+        let t = if length > 0.0 {
+            (((point[0] - a[0]) * edge[0] + (point[1] - a[1]) * edge[1]) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let closest = [a[0] + t * edge[0], a[1] + t * edge[1]];
+        min_distance = min_distance.min(distance_degrees(pmap, &point, &closest));
+    }
+    return min_distance;
+}
+
+impl BrushArea {
+    // Strength, between 0 and 1, at a point of an area whose border fades out over `falloff` degrees
+    pub fn weight<S: MapShape, T: Clone>(
+        &self,
+        falloff: f32,
+        pmap: &PartialMap<S, T>,
+        point: [f32; 2],
+    ) -> f32 {
+        let edge_distance = match self {
+            BrushArea::Circle { center, radius } => radius - distance_degrees(pmap, center, &point),
+            BrushArea::Polygon { points } => {
+                if points.len() < 3 || !inside_polygon::<S>(points, point) {
+                    return 0.0;
+                }
+                polygon_edge_distance(pmap, points, point)
+            }
+        };
+        if edge_distance <= 0.0 {
+            return 0.0;
+        }
+        if falloff <= 0.0 {
+            return 1.0;
+        }
+        return (edge_distance / falloff).min(1.0);
+    }
+}
+
+impl Brush {
+    // Pixels inside the area, with the strength of the brush on them
+    fn weights<S: MapShape, T: Clone>(&self, pmap: &PartialMap<S, T>) -> Vec<([usize; 2], f32)> {
+        // longitudes of polygons are compared relative to their first vertex
+        let mut bounds = [-90.0, 90.0, -180.0, 180.0];
+        let mut reference = 0.0;
+        if let BrushArea::Polygon { points } = &self.area {
+            bounds = [f32::MAX, f32::MIN, f32::MAX, f32::MIN];
+            reference = points.first().map_or(0.0, |[_, lon]| *lon);
+            for i in 0..points.len() {
+                let [lat, lon] = polygon_vertex::<S>(points, i);
+                bounds = [
+                    bounds[0].min(lat),
+                    bounds[1].max(lat),
+                    bounds[2].min(lon),
+                    bounds[3].max(lon),
+                ];
+            }
+        }
+        let mut weights = vec![];
+        for x in 0..pmap.values.len() {
+            for y in 0..pmap.values[x].len() {
+                let [lat, lon] = pmap.convert_coords(x, y);
+                let relative_lon = match self.area {
+                    BrushArea::Polygon { .. } => relative_longitude::<S>(lon, reference),
+                    BrushArea::Circle { .. } => lon,
+                };
+                if lat < bounds[0]
+                    || lat > bounds[1]
+                    || relative_lon < bounds[2]
+                    || relative_lon > bounds[3]
+                {
+                    continue;
+                }
+                let weight = self.area.weight(self.falloff, pmap, [lat, lon]);
+                if weight > 0.0 {
+                    weights.push(([x, y], weight));
+                }
+            }
+        }
+        return weights;
+    }
+}
+
+impl<S: MapShape> PipelineStep<S> for Brush {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let weights = self.weights(&map.height);
+        if weights.is_empty() {
+            return Ok(());
+        }
+        let height = &map.height;
+        let flat_height = match self.operation {
+            BrushOperation::Flatten { height: Some(h) } => h as f32,
+            BrushOperation::Flatten { height: None } => {
+                let sum: f32 = weights
+                    .iter()
+                    .map(|([x, y], weight)| height.values[*x][*y] as f32 * weight)
+                    .sum();
+                sum / weights.iter().map(|(_, weight)| weight).sum::<f32>()
+            }
+            _ => 0.0,
+        };
+        let noise = match self.operation {
+            BrushOperation::Noise {
+                seed,
+                frequency,
+                intensity,
+            } => {
+                let Some(seed) = seed else {
+                    return Err(MapError::InvalidData(
+                        "the noise of a brush needs a seed".to_string(),
+                    ));
+                };
+                Some(CustomNoise::new(seed, frequency, intensity))
+            }
+            _ => None,
+        };
+
+        let new_values: Vec<i32> = weights
+            .iter()
+            .map(|([x, y], weight)| {
+                let old = height.values[*x][*y];
+                let change = match &self.operation {
+                    BrushOperation::Raise { amount } => *amount as f32,
+                    BrushOperation::Lower { amount } => -*amount as f32,
+                    BrushOperation::Flatten { .. } => flat_height - old as f32,
+                    BrushOperation::Smooth { pixel_distance } => {
+                        let smooth = Smooth {
+                            pixel_distance: *pixel_distance,
+                        };
+                        (smooth.process_element(*x, *y, height, map) - old) as f32
+                    }
+                    BrushOperation::Noise { .. } => {
+                        let [lat, lon] = height.convert_coords(*x, *y);
+                        noise
+                            .as_ref()
+                            .map_or(0.0, |noise| noise.get_spheric_f32::<S, i32>(lat, lon))
+                    }
+                };
+                old + (change * weight).round() as i32
+            })
+            .collect();
+
+        for (([x, y], _), value) in weights.iter().zip(new_values) {
+            map.height.values[*x][*y] = value;
+        }
+        let pixels: Vec<[usize; 2]> = weights.into_iter().map(|(pixel, _)| pixel).collect();
+        update_coastline(map, &pixels);
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::Coastline];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::{flat::Flat, globe::Globe};
+
+    // A square from longitude 170 to -170, across the antimeridian on wrapping shapes
+    fn antimeridian_brush() -> Brush {
+        return Brush {
+            area: BrushArea::Polygon {
+                points: vec![
+                    [-10.0, 170.0],
+                    [10.0, 170.0],
+                    [10.0, -170.0],
+                    [-10.0, -170.0],
+                ],
+            },
+            operation: BrushOperation::Raise { amount: 100 },
+            falloff: 0.0,
+        };
+    }
+
+    fn selected_longitudes<S: MapShape>(pmap: &PartialMap<S, i32>) -> Vec<f32> {
+        return antimeridian_brush()
+            .weights(pmap)
+            .iter()
+            .map(|([x, y], _)| pmap.cell_center(*x, *y)[1])
+            .collect();
+    }
+
+    #[test]
+    fn polygons_cross_the_antimeridian() {
+        let globe = selected_longitudes(&PartialMap::<Globe, i32>::new(72, 36));
+        assert!(!globe.is_empty());
+        assert!(globe.iter().all(|lon| lon.abs() > 165.0));
+
+        // flat maps do not wrap, so the polygon spans the map between its longitudes
+        let flat = selected_longitudes(&PartialMap::<Flat, i32>::new(72, 36));
+        assert!(flat.iter().any(|lon| lon.abs() < 10.0));
+        assert!(flat.iter().all(|lon| lon.abs() < 175.0));
+    }
+}
@@ -1,16 +1,18 @@
-use std::sync::Arc;
-
-use rocket::tokio::time::Instant;
-
 use crate::{
     complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    map_file::layers::LayerId,
     pipeline_steps::{rivers::CreateRivers, vegetation::Vegetation},
     shapes::map_shape::MapShape,
 };
 
 use super::{
-    annual_precipitation::CalculateAnnualPrecipitation, climate::DefineKoppenClimate,
-    continentality::CalculateContinentality, pipeline_step::PipelineStep,
+    annual_precipitation::CalculateAnnualPrecipitation,
+    climate::DefineKoppenClimate,
+    continentality::CalculateContinentality,
+    pipeline_step::{
+        apply_concurrently, sequence_reads, sequence_writes, step_name, PipelineStep, SubStepTiming,
+    },
     precipitation::CalculatePrecipitation,
     temperature_from_continentality::TemperatureFromContinentality,
 };
@@ -32,36 +34,52 @@ impl CalculateClimate {
     }
 }
 
+impl CalculateClimate {
+    // The steps of the climate, in order. The steps of a stage run at the same time.
+    fn stages<S: MapShape>(&self) -> Vec<Vec<Box<dyn PipelineStep<S>>>> {
+        // DefinePressure, DefineWindsGradient and AdjustPrecipitationPercentiles are not used
+        return vec![
+            vec![Box::new(CalculateContinentality {})],
+            vec![Box::new(TemperatureFromContinentality::new(
+                self.equator_temperature,
+                self.pole_temperature,
+            ))],
+            vec![Box::new(CalculatePrecipitation::new(self.humidity))],
+            vec![Box::new(CalculateAnnualPrecipitation {})],
+            vec![Box::new(DefineKoppenClimate {})],
+            vec![Box::new(Vegetation::new()), Box::new(CreateRivers {})],
+        ];
+    }
+}
+
 impl<S: MapShape> PipelineStep<S> for CalculateClimate {
-    fn process_element(&self, _x: usize, _y: usize, _complete_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        self.apply_timed(map)?;
+        return Ok(());
     }
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
+    fn apply_timed(&self, map: &mut CompleteMap<S>) -> MapResult<Vec<SubStepTiming>> {
+        let mut timings = vec![];
+        for stage in self.stages::<S>() {
+            let steps: Vec<&dyn PipelineStep<S>> = stage.iter().map(|step| step.as_ref()).collect();
+            for (step, result) in steps.iter().zip(apply_concurrently(&steps, map)) {
+                let (duration, _) = result?;
+                timings.push(SubStepTiming {
+                    name: step_name(*step),
+                    duration,
+                });
+            }
+        }
+        return Ok(timings);
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        let stages = self.stages::<S>();
+        return sequence_reads(stages.iter().flatten().map(|step| step.as_ref()));
+    }
 
-        let i0 = Instant::now();
-        output_map = CalculateContinentality {}.apply(&output_map);
-        let i1 = Instant::now();
-        dbg!("CalculateContinentality time:", i1 - i0);
-        output_map =
-            TemperatureFromContinentality::new(self.equator_temperature, self.pole_temperature)
-                .apply(&output_map);
-        let i2 = Instant::now();
-        dbg!("TemperatureFromContinentality time:", i2 - i1);
-        // output_map = DefinePressure::new().apply(&output_map);
-        // output_map = DefineWindsGradient::new().apply(&output_map);
-        output_map = CalculatePrecipitation::new(self.humidity).apply(&output_map);
-        let i3 = Instant::now();
-        dbg!("CalculatePrecipitation time:", i3 - i2);
-        // output_map =
-        //     AdjustPrecipitationPercentiles::new(&self.precipitation_percentiles).apply(&output_map);
-        output_map = CalculateAnnualPrecipitation {}.apply(&output_map);
-        output_map = DefineKoppenClimate {}.apply(&output_map);
-        output_map = Vegetation::new().apply(&output_map);
-        output_map = CreateRivers {}.apply(&output_map);
-        let i3 = Instant::now();
-        dbg!("Total climate calculation time:", i3 - i0);
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        let stages = self.stages::<S>();
+        return sequence_writes(stages.iter().flatten().map(|step| step.as_ref()));
     }
 }
@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 
@@ -438,27 +440,29 @@ impl DefineKoppenClimate {
 }
 
 impl<S: MapShape> PipelineStep<S> for DefineKoppenClimate {
-    fn process_element(&self, _x: usize, _y: usize, _complete_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        map.require_climate()?;
+        map.require_annual_precipitation()?;
+
+        map.climate = PartialMap::new(map.height.circunference, map.height.height);
+        let mut climate = map.climate.clone();
+        climate.iterate_operator(map, |x, y, arc_map| {
+            self.process_climate_element(x, y, arc_map)
+        });
+        map.climate = climate;
+        return Ok(());
     }
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![
+            LayerId::Height,
+            LayerId::Temperature,
+            LayerId::Precipitation,
+            LayerId::AnnualPrecipitation,
+        ];
+    }
 
-        output_map.climate =
-            PartialMap::new(output_map.height.circunference, output_map.height.height);
-        // process elements in parallel with rayon
-        let input_map2 = output_map.clone();
-        output_map
-            .climate
-            .values
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(x, inner_vec)| {
-                inner_vec.iter_mut().enumerate().for_each(|(y, num)| {
-                    *num = self.process_climate_element(x, y, Arc::new(&input_map2));
-                });
-            });
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Climate];
     }
 }
@@ -1,8 +1,9 @@
 use std::{f32::consts::PI, sync::Arc};
 
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
-
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::{
     pipeline_step::PipelineStep,
@@ -110,36 +111,31 @@ impl CalculateContinentality {
     }
 }
 impl<S: MapShape> PipelineStep<S> for CalculateContinentality {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.continentality = PartialMap::new(500, 250);
-        let in2 = output_map.clone();
-        // process elements in parallel with rayon
-        output_map
-            .continentality
-            .values
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(x, inner_vec)| {
-                inner_vec.iter_mut().enumerate().for_each(|(y, num)| {
-                    *num = self.process_cont_element(x, y, Arc::new(&in2));
-                });
-            });
-        while output_map.continentality.values.len() < output_map.height.values.len() {
-            let factor = (output_map.height.values.len() as f32
-                / output_map.continentality.values.len() as f32)
-                .min(2.0);
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        map.continentality = PartialMap::new(500, 250);
+        let mut continentality = map.continentality.clone();
+        continentality.iterate_operator(map, |x, y, arc_map| {
+            self.process_cont_element(x, y, arc_map)
+        });
+        while continentality.values.len() < map.height.values.len() {
+            let factor =
+                (map.height.values.len() as f32 / continentality.values.len() as f32).min(2.0);
             if factor == 2.0 {
-                resize_f32(&mut output_map.continentality, 2.0);
+                resize_f32(&mut continentality, 2.0);
             } else {
-                resize(&mut output_map.continentality, factor);
+                resize(&mut continentality, factor);
             }
         }
+        map.continentality = continentality;
+
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
 
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Continentality];
     }
 }
@@ -1,7 +1,10 @@
 // use fasthash::spooky::Hash128;
-use std::{collections::HashSet, sync::Arc};
+use std::collections::HashSet;
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 
@@ -9,28 +12,57 @@ use super::pipeline_step::PipelineStep;
 pub struct DefineCoastline {}
 
 impl<S: MapShape> PipelineStep<S> for DefineCoastline {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
         let mut coastline = HashSet::new();
         // let mut coastline = HashSet::with_hasher(Hash128);
-        for x in 0..input_map.height.values.len() {
-            for y in 0..input_map.height.values[x].len() {
-                if input_map.height.values[x][y] <= 0 {
-                    for row in input_map.height.get_pixel_neighbours([x, y], 1) {
-                        for value in row {
-                            if value > 0 {
-                                coastline.insert([x, y]);
-                            }
-                        }
-                    }
+        for x in 0..map.height.values.len() {
+            for y in 0..map.height.values[x].len() {
+                if is_coast(&map.height, x, y) {
+                    coastline.insert([x, y]);
                 }
             }
         }
-        output_map.coastline = Some(coastline);
-        return output_map;
+        map.coastline = Some(coastline);
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Coastline];
+    }
+}
+
+// Water pixels next to land
+fn is_coast<S: MapShape>(height: &PartialMap<S, i32>, x: usize, y: usize) -> bool {
+    return height.values[x][y] <= 0
+        && height
+            .get_pixel_neighbours([x, y], 1)
+            .iter()
+            .flatten()
+            .any(|value| *value > 0);
+}
+
+// Updates the coastline around pixels whose height changed, without going through the whole map
+pub fn update_coastline<S: MapShape>(map: &mut CompleteMap<S>, pixels: &[[usize; 2]]) {
+    let Some(coastline) = map.coastline.as_mut() else {
+        let _ = DefineCoastline {}.apply(map);
+        return;
+    };
+    for pixel in pixels {
+        for [x, y] in map
+            .height
+            .get_pixel_neighbours_coords(*pixel, 1)
+            .into_iter()
+            .flatten()
+        {
+            if is_coast(&map.height, x, y) {
+                coastline.insert([x, y]);
+            } else {
+                coastline.remove(&[x, y]);
+            }
+        }
     }
 }
@@ -1,6 +1,7 @@
-use std::sync::Arc;
-
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 
@@ -81,13 +82,17 @@ fn diamond_square_usize<S: MapShape>(pmap: &mut PartialMap<S, usize>) {
 }
 
 impl<S: MapShape> PipelineStep<S> for DiamondSquare {
-    fn process_element(&self, _x: usize, _y: usize, _pmap: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        diamond_square(&mut map.height);
+        diamond_square_usize(&mut map.tectonic_plates);
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
     }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        diamond_square(&mut output_map.height);
-        diamond_square_usize(&mut output_map.tectonic_plates);
-        return output_map;
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::TectonicPlates];
     }
 }
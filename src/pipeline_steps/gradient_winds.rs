@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::{pipeline_step::PipelineStep, util::rotate_vector};
 
@@ -55,22 +58,26 @@ impl DefineWindsGradient {
 }
 
 impl<S: MapShape> PipelineStep<S> for DefineWindsGradient {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.winds = vec![];
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let mut winds = vec![];
         for t in 0..self.year_divisions {
             let mut wind_map = PartialMap::new(
-                output_map.atm_pressure[t as usize].circunference,
-                output_map.atm_pressure[t as usize].height,
+                map.atm_pressure[t as usize].circunference,
+                map.atm_pressure[t as usize].height,
             );
             let operator = |x, y, arc_map| self.process_element(x, y, arc_map, t as usize);
-            wind_map.iterate_operator(input_map, operator);
-            output_map.winds.push(wind_map);
+            wind_map.iterate_operator(map, operator);
+            winds.push(wind_map);
         }
-        return output_map;
+        map.winds = winds;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::AtmPressure];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Winds];
     }
 }
@@ -1,8 +1,14 @@
-use std::{collections::HashMap, sync::Arc};
+use std::collections::HashMap;
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    layer_step::{height_step, ElementStep},
+    util::CustomNoise,
+};
 
 #[derive(Debug)]
 pub struct HeightInPlates {
@@ -33,13 +39,25 @@ impl HeightInPlates {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for HeightInPlates {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let noise = self.get_noise(input_map.tectonic_plates.values[x][y]);
-
-        let [lat, lon] = input_map.height.convert_coords(x, y);
+impl<S: MapShape> ElementStep<S, i32> for HeightInPlates {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, i32>,
+        map: &CompleteMap<S>,
+    ) -> i32 {
+        let noise = self.get_noise(map.tectonic_plates.values[x][y]);
+
+        let [lat, lon] = layer.convert_coords(x, y);
         let noise_value = noise.get_spheric::<S, i32>(lat, lon);
 
-        return input_map.height.values[x][y] + noise_value as i32;
+        return layer.values[x][y] + noise_value as i32;
+    }
+
+    fn map_reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::TectonicPlates];
     }
 }
+
+height_step!(HeightInPlates);
@@ -1,8 +1,9 @@
-use std::sync::Arc;
+use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
-
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    layer_step::{height_step, ElementStep, NumericValue},
+    util::CustomNoise,
+};
 
 #[derive(Debug)]
 pub struct HeightNoise {
@@ -17,11 +18,19 @@ impl HeightNoise {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for HeightNoise {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let [lat, lon] = input_map.height.convert_coords(x, y);
+impl<S: MapShape, T: NumericValue> ElementStep<S, T> for HeightNoise {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, T>,
+        _map: &CompleteMap<S>,
+    ) -> T {
+        let [lat, lon] = layer.convert_coords(x, y);
         let noise_value = self.noise.get_spheric::<S, i32>(lat, lon);
 
-        return input_map.height.values[x][y] + noise_value;
+        return layer.values[x][y].offset(noise_value as f32);
     }
 }
+
+height_step!(HeightNoise);
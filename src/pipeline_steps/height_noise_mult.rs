@@ -1,8 +1,9 @@
-use std::sync::Arc;
+use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
-
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    layer_step::{height_step, ElementStep, NumericValue},
+    util::CustomNoise,
+};
 
 #[derive(Debug)]
 pub struct HeightNoiseMult {
@@ -17,15 +18,23 @@ impl HeightNoiseMult {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for HeightNoiseMult {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let num = input_map.height.values[x][y];
-        if num < 15 {
+impl<S: MapShape, T: NumericValue> ElementStep<S, T> for HeightNoiseMult {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, T>,
+        _map: &CompleteMap<S>,
+    ) -> T {
+        let num = layer.values[x][y];
+        if num.to_f32() < 15.0 {
             return num;
         }
-        let [lat, lon] = input_map.height.convert_coords(x, y);
+        let [lat, lon] = layer.convert_coords(x, y);
         let noise_value = self.noise.get_spheric_f32::<S, i32>(lat, lon);
         let multiplier = (1.0 + noise_value).max(0.2);
-        return (num as f32 * multiplier) as i32;
+        return num.scale(multiplier);
     }
 }
+
+height_step!(HeightNoiseMult);
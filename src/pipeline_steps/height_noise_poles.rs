@@ -1,8 +1,11 @@
-use std::{f64::consts::PI, sync::Arc};
+use std::f64::consts::PI;
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
 
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    layer_step::{height_step, ElementStep, NumericValue},
+    util::CustomNoise,
+};
 
 #[derive(Debug)]
 pub struct HeightNoisePoles {
@@ -17,13 +20,21 @@ impl HeightNoisePoles {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for HeightNoisePoles {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let pole_factor = (PI as f32 * (x as f32) / input_map.height.height as f32)
+impl<S: MapShape, T: NumericValue> ElementStep<S, T> for HeightNoisePoles {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, T>,
+        _map: &CompleteMap<S>,
+    ) -> T {
+        let pole_factor = (PI as f32 * (x as f32) / layer.height as f32)
             .cos()
             .powf(2.0);
-        let [lat, lon] = input_map.height.convert_coords(x, y);
+        let [lat, lon] = layer.convert_coords(x, y);
         let noise_value = self.noise.get_spheric_f32::<S, i32>(lat, lon);
-        return input_map.height.values[x][y] + (noise_value * pole_factor) as i32;
+        return layer.values[x][y].offset(noise_value * pole_factor);
     }
 }
+
+height_step!(HeightNoisePoles);
@@ -1,6 +1,4 @@
-use std::sync::Arc;
-
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{complete_map::CompleteMap, error::MapError, shapes::map_shape::MapShape};
 
 use super::pipeline_step::PipelineStep;
 
@@ -83,24 +81,23 @@ impl HydraulicErosion {
 }
 
 impl<S: MapShape> PipelineStep<S> for HydraulicErosion {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        // the edges of latitude/longitude grids are left out, but faces of other shapes have
+        // neighbours all around
+        let margin = if S::LAT_LON_GRID { 3 } else { 0 };
         for _ in 0..self.iterations {
             for r1 in 0..10 {
                 for r2 in 0..10 {
-                    for x in 3..output_map.height.values.len() - 3 {
-                        for y in 3..output_map.height.values[x].len() - 3 {
+                    for x in margin..map.height.values.len() - margin {
+                        for y in margin..map.height.values[x].len() - margin {
                             if x % 10 == r1 && y % 10 == r2 {
-                                droplet(&mut output_map, x, y);
+                                droplet(map, x, y);
                             }
                         }
                     }
                 }
             }
         }
-        return output_map;
+        return Ok(());
     }
 }
@@ -1,6 +1,5 @@
-use std::sync::Arc;
 
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{error::MapError, complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
 
 use super::pipeline_step::PipelineStep;
 
@@ -107,13 +106,9 @@ impl HydraulicErosion {
 }
 
 impl<S: MapShape> PipelineStep<S> for HydraulicErosion {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        hydraulic_erosion(&mut output_map, 10, 0.1, 0.05, 1.0);
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        hydraulic_erosion(map, 10, 0.1, 0.05, 1.0);
         
-        return output_map;
+        return Ok(());
     }
 }
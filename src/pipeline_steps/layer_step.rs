@@ -0,0 +1,324 @@
+use std::{fmt, marker::PhantomData};
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+
+use crate::{
+    complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    map_file::layers::LayerId,
+    partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+use super::pipeline_step::PipelineStep;
+
+// Type of the values of some layers of CompleteMap, which are found by name:
+//   i32: "height", "annual_precipitation", "vegetation_density", "fresh_water",
+//        "precipitation/<month>", "atm_pressure/<month>", "ice_height/<month>"
+//   f32: "continentality", "temperature/<month>"
+//   usize: "tectonic_plates", "custom/<name>"
+pub trait LayerValue: Clone + Default + fmt::Debug + Send + Sync + 'static {
+    fn layer<'a, S: MapShape>(
+        map: &'a CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a PartialMap<S, Self>>;
+
+    fn layer_mut<'a, S: MapShape>(
+        map: &'a mut CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a mut PartialMap<S, Self>>;
+
+    // whether `name` is a layer of this type, even if the map does not have it yet
+    fn is_layer(name: &str) -> bool;
+}
+
+// Layer values that can be shifted, scaled and averaged, as heights are.
+pub trait NumericValue: LayerValue + Copy {
+    fn to_f32(self) -> f32;
+    // integer layers add the truncated delta
+    fn offset(self, delta: f32) -> Self;
+    fn scale(self, factor: f32) -> Self;
+    fn mean(values: &[Vec<Self>]) -> Self;
+}
+
+// The month of a monthly layer, e.g. 3 for "temperature/3"
+fn month(name: &str, layer: &str) -> Option<usize> {
+    return name.strip_prefix(layer)?.strip_prefix('/')?.parse().ok();
+}
+
+impl LayerValue for i32 {
+    fn layer<'a, S: MapShape>(
+        map: &'a CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a PartialMap<S, i32>> {
+        match name {
+            "height" => Some(&map.height),
+            "annual_precipitation" => Some(&map.annual_precipitation),
+            "vegetation_density" => Some(&map.vegetation_density),
+            "fresh_water" => Some(&map.fresh_water),
+            _ => {
+                if let Some(m) = month(name, "precipitation") {
+                    map.precipitation.get(m)
+                } else if let Some(m) = month(name, "atm_pressure") {
+                    map.atm_pressure.get(m)
+                } else {
+                    map.ice_height.get(month(name, "ice_height")?)
+                }
+            }
+        }
+    }
+
+    fn layer_mut<'a, S: MapShape>(
+        map: &'a mut CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a mut PartialMap<S, i32>> {
+        match name {
+            "height" => Some(&mut map.height),
+            "annual_precipitation" => Some(&mut map.annual_precipitation),
+            "vegetation_density" => Some(&mut map.vegetation_density),
+            "fresh_water" => Some(&mut map.fresh_water),
+            _ => {
+                if let Some(m) = month(name, "precipitation") {
+                    map.precipitation.get_mut(m)
+                } else if let Some(m) = month(name, "atm_pressure") {
+                    map.atm_pressure.get_mut(m)
+                } else {
+                    map.ice_height.get_mut(month(name, "ice_height")?)
+                }
+            }
+        }
+    }
+
+    fn is_layer(name: &str) -> bool {
+        return matches!(
+            name,
+            "height" | "annual_precipitation" | "vegetation_density" | "fresh_water"
+        ) || ["precipitation", "atm_pressure", "ice_height"]
+            .iter()
+            .any(|layer| month(name, layer).is_some());
+    }
+}
+
+impl NumericValue for i32 {
+    fn to_f32(self) -> f32 {
+        return self as f32;
+    }
+
+    fn offset(self, delta: f32) -> Self {
+        return self + delta as i32;
+    }
+
+    fn scale(self, factor: f32) -> Self {
+        return (self as f32 * factor) as i32;
+    }
+
+    fn mean(values: &[Vec<Self>]) -> Self {
+        let mut sum = 0;
+        let mut len = 0;
+        for value in values.iter().flatten() {
+            sum += value;
+            len += 1;
+        }
+        return sum / len;
+    }
+}
+
+impl LayerValue for f32 {
+    fn layer<'a, S: MapShape>(
+        map: &'a CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a PartialMap<S, f32>> {
+        match name {
+            "continentality" => Some(&map.continentality),
+            _ => map.temperature.get(month(name, "temperature")?),
+        }
+    }
+
+    fn layer_mut<'a, S: MapShape>(
+        map: &'a mut CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a mut PartialMap<S, f32>> {
+        match name {
+            "continentality" => Some(&mut map.continentality),
+            _ => map.temperature.get_mut(month(name, "temperature")?),
+        }
+    }
+
+    fn is_layer(name: &str) -> bool {
+        return name == "continentality" || month(name, "temperature").is_some();
+    }
+}
+
+impl NumericValue for f32 {
+    fn to_f32(self) -> f32 {
+        return self;
+    }
+
+    fn offset(self, delta: f32) -> Self {
+        return self + delta;
+    }
+
+    fn scale(self, factor: f32) -> Self {
+        return self * factor;
+    }
+
+    fn mean(values: &[Vec<Self>]) -> Self {
+        let mut sum = 0.0;
+        let mut len = 0;
+        for value in values.iter().flatten() {
+            sum += value;
+            len += 1;
+        }
+        return sum / len as f32;
+    }
+}
+
+impl LayerValue for usize {
+    fn layer<'a, S: MapShape>(
+        map: &'a CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a PartialMap<S, usize>> {
+        match name {
+            "tectonic_plates" => Some(&map.tectonic_plates),
+            _ => map.custom_pmaps.get(name.strip_prefix("custom/")?),
+        }
+    }
+
+    fn layer_mut<'a, S: MapShape>(
+        map: &'a mut CompleteMap<S>,
+        name: &str,
+    ) -> Option<&'a mut PartialMap<S, usize>> {
+        match name {
+            "tectonic_plates" => Some(&mut map.tectonic_plates),
+            _ => map.custom_pmaps.get_mut(name.strip_prefix("custom/")?),
+        }
+    }
+
+    fn is_layer(name: &str) -> bool {
+        return name == "tectonic_plates" || name.starts_with("custom/");
+    }
+}
+
+// A step computing every value of a layer from the old values of that layer and the rest
+// of the map. The same step can run on any layer with values of type T.
+pub trait ElementStep<S: MapShape, T: LayerValue>: Sync + fmt::Debug {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, T>,
+        map: &CompleteMap<S>,
+    ) -> T;
+
+    // Other layers of the map read by process_element
+    fn map_reads(&self) -> Vec<LayerId> {
+        return vec![];
+    }
+}
+
+pub fn apply_to_layer<S, T, E>(
+    step: &E,
+    layer_name: &str,
+    map: &mut CompleteMap<S>,
+) -> MapResult<()>
+where
+    S: MapShape,
+    T: LayerValue,
+    E: ElementStep<S, T> + ?Sized,
+{
+    let Some(input) = T::layer(map, layer_name) else {
+        return Err(MapError::MissingLayer(layer_name.to_string()));
+    };
+    if input.values.is_empty() {
+        return Err(MapError::MissingLayer(layer_name.to_string()));
+    }
+    let mut output = input.clone();
+    output
+        .values
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(x, inner_vec)| {
+            inner_vec.iter_mut().enumerate().for_each(|(y, value)| {
+                *value = step.process_element(x, y, input, map);
+            });
+        });
+    if let Some(layer) = T::layer_mut(map, layer_name) {
+        *layer = output;
+    }
+    return Ok(());
+}
+
+// An element step run on a layer other than the one it is usually written for.
+pub struct OnLayer<E, T> {
+    pub step: E,
+    pub layer: String,
+    value: PhantomData<T>,
+}
+
+impl<E, T> OnLayer<E, T> {
+    pub fn new(step: E, layer: &str) -> Self {
+        Self {
+            step,
+            layer: layer.to_string(),
+            value: PhantomData,
+        }
+    }
+}
+
+// The name of the step comes first, as for the other steps
+impl<E: fmt::Debug, T> fmt::Debug for OnLayer<E, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} on {}", self.step, self.layer)
+    }
+}
+
+impl<S, T, E> PipelineStep<S> for OnLayer<E, T>
+where
+    S: MapShape,
+    T: LayerValue,
+    E: ElementStep<S, T>,
+{
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        return apply_to_layer(&self.step, &self.layer, map);
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        let mut reads = PipelineStep::<S>::writes(self);
+        reads.extend(self.step.map_reads());
+        return reads;
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return LayerId::containing(&self.layer).into_iter().collect();
+    }
+}
+
+// Element steps written for the height run on it when used directly as pipeline steps.
+macro_rules! height_step {
+    ($($step:ty),+ $(,)?) => {
+        $(
+            impl<S: crate::shapes::map_shape::MapShape>
+                crate::pipeline_steps::pipeline_step::PipelineStep<S> for $step
+            {
+                fn apply(
+                    &self,
+                    map: &mut crate::complete_map::CompleteMap<S>,
+                ) -> Result<(), crate::error::MapError> {
+                    return crate::pipeline_steps::layer_step::apply_to_layer::<S, i32, _>(
+                        self, "height", map,
+                    );
+                }
+
+                fn reads(&self) -> Vec<crate::map_file::layers::LayerId> {
+                    let mut reads = vec![crate::map_file::layers::LayerId::Height];
+                    reads.extend(
+                        crate::pipeline_steps::layer_step::ElementStep::<S, i32>::map_reads(self),
+                    );
+                    return reads;
+                }
+            }
+        )+
+    };
+}
+
+pub(crate) use height_step;
@@ -1,7 +1,6 @@
-use std::sync::Arc;
-
 use crate::{
-    complete_map::CompleteMap, partial_map::load_categories_from_img, shapes::map_shape::MapShape,
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId,
+    partial_map::load_categories_from_img, shapes::map_shape::MapShape,
 };
 
 use super::pipeline_step::PipelineStep;
@@ -18,26 +17,23 @@ impl LoadCustomLayer {
 }
 
 impl<S: MapShape> PipelineStep<S> for LoadCustomLayer {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let (custom_pmap, color_scheme) = load_categories_from_img(&self.filename)?;
+        let layer_name = self.filename.rsplit('/').next().unwrap_or(&self.filename);
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        dbg!("loading layer");
-        dbg!(&self.filename);
-        let mut output_map = input_map.clone();
-        let (custom_pmap, color_scheme) = load_categories_from_img(&self.filename);
+        map.custom_pmaps.insert(layer_name.to_string(), custom_pmap);
 
-        output_map.custom_pmaps.insert(
-            self.filename.split('/').last().unwrap().to_string(),
-            custom_pmap,
-        );
+        map.custom_color_schemes
+            .insert(layer_name.to_string(), color_scheme);
 
-        output_map.custom_color_schemes.insert(
-            self.filename.split('/').last().unwrap().to_string(),
-            color_scheme,
-        );
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![];
+    }
 
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::CustomLayers];
     }
 }
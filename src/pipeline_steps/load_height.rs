@@ -1,30 +1,44 @@
-use std::sync::Arc;
-
-use crate::{complete_map::CompleteMap, partial_map::load_from_img, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap,
+    error::MapError,
+    import::heightmap::{load_heightmap, ValueMapping},
+    map_file::layers::LayerId,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 
 #[derive(Debug)]
 pub struct LoadHeight {
     filename: String,
+    // defaults to the green/blue encoding for images and meters for other files
+    pub mapping: Option<ValueMapping>,
+    // width of raw files, which do not store their size
+    pub raw_width: Option<usize>,
 }
 
 impl LoadHeight {
     pub fn new(filename: String) -> Self {
-        Self { filename }
+        Self {
+            filename,
+            mapping: None,
+            raw_width: None,
+        }
     }
 }
 
 impl<S: MapShape> PipelineStep<S> for LoadHeight {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        map.height = load_heightmap(&self.filename, self.mapping.as_ref(), self.raw_width)?;
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
+        return Ok(());
+    }
 
-        output_map.height = load_from_img(&self.filename, -5000, 6400);
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![];
+    }
 
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
     }
 }
@@ -0,0 +1,386 @@
+use std::{collections::HashSet, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    map_file::layers::{copy_layer, LayerId},
+    partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+use super::{brush::BrushArea, layer_step::NumericValue, pipeline_step::PipelineStep};
+
+// How a mask is given in recipes and requests:
+//   {"area": <brush area>, "falloff": <degrees>}, {"plates": [<plate>, ...]} or {"image": <file>}
+// Images are grayscale weights covering the whole map, white being 1.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MaskSpec {
+    Area {
+        area: BrushArea,
+        #[serde(default)]
+        falloff: f32,
+    },
+    Plates {
+        plates: Vec<usize>,
+    },
+    Image {
+        image: String,
+    },
+}
+
+impl MaskSpec {
+    pub fn build<S: MapShape>(&self) -> MapResult<Mask<S>> {
+        match self {
+            MaskSpec::Area { area, falloff } => Ok(Mask::Area {
+                area: area.clone(),
+                falloff: *falloff,
+            }),
+            MaskSpec::Plates { plates } => Ok(Mask::Plates(plates.clone())),
+            MaskSpec::Image { image } => {
+                let imgbuffer = image::open(image)?.to_luma8();
+                let rows = imgbuffer
+                    .rows()
+                    .map(|row| row.map(|pixel| pixel[0] as f32 / 255.0).collect())
+                    .collect();
+                Ok(Mask::Weights(PartialMap::from_rows(rows)))
+            }
+        }
+    }
+}
+
+// Weight, between 0 and 1, of the changes of a step at every point of the map
+pub enum Mask<S: MapShape> {
+    Weights(PartialMap<S, f32>),
+    Area { area: BrushArea, falloff: f32 },
+    Plates(Vec<usize>),
+}
+
+impl<S: MapShape> Mask<S> {
+    // The weights at the resolution of the height
+    fn weights(&self, map: &CompleteMap<S>) -> PartialMap<S, f32> {
+        let mut weights = PartialMap::new(map.height.circunference, map.height.height);
+        for x in 0..weights.values.len() {
+            for y in 0..weights.values[x].len() {
+                let [lat, lon] = weights.convert_coords(x, y);
+                weights.values[x][y] = match self {
+                    Mask::Weights(pmap) => pmap.get(lat, lon),
+                    Mask::Area { area, falloff } => area.weight(*falloff, &weights, [lat, lon]),
+                    Mask::Plates(plates) => {
+                        if plates.contains(&map.tectonic_plates.get(lat, lon)) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    }
+                };
+            }
+        }
+        return weights;
+    }
+}
+
+// Layers whose values can be mixed pixel by pixel
+const MASKABLE_LAYERS: [LayerId; 12] = [
+    LayerId::Height,
+    LayerId::Temperature,
+    LayerId::Winds,
+    LayerId::Precipitation,
+    LayerId::AnnualPrecipitation,
+    LayerId::AtmPressure,
+    LayerId::Climate,
+    LayerId::Continentality,
+    LayerId::IceHeight,
+    LayerId::FreshWater,
+    LayerId::Coastline,
+    LayerId::VegetationDensity,
+];
+
+// Applies a step only where a mask is set: the layers it writes are mixed with their
+// values before the step by the weight of the mask, which fades the step out on its border.
+// Categorical values (climates, coastline) take the value after the step where the weight is
+// at least 0.5. Steps writing rivers, plates, features or custom layers cannot be masked.
+pub struct Masked<S: MapShape> {
+    pub step: Box<dyn PipelineStep<S>>,
+    pub mask: Mask<S>,
+}
+
+impl<S: MapShape> Masked<S> {
+    pub fn new(step: Box<dyn PipelineStep<S>>, mask: Mask<S>) -> Self {
+        Self { step, mask }
+    }
+}
+
+impl<S: MapShape> fmt::Debug for Masked<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Masked({:?})", self.step)
+    }
+}
+
+fn mix<T: NumericValue>(before: &T, after: &T, weight: f32) -> T {
+    return before.offset((after.to_f32() - before.to_f32()) * weight);
+}
+
+fn choose<T: Clone>(before: &T, after: &T, weight: f32) -> T {
+    if weight >= 0.5 {
+        return after.clone();
+    }
+    return before.clone();
+}
+
+fn size_error(layer: LayerId) -> MapError {
+    return MapError::InvalidData(format!(
+        "a masked step must keep the size of {}",
+        layer.name()
+    ));
+}
+
+fn blend<S: MapShape, T: Clone>(
+    before: &PartialMap<S, T>,
+    after: &mut PartialMap<S, T>,
+    weights: &PartialMap<S, f32>,
+    layer: LayerId,
+    mix: impl Fn(&T, &T, f32) -> T,
+) -> MapResult<()> {
+    if before.values.len() != after.values.len()
+        || before
+            .values
+            .iter()
+            .zip(&after.values)
+            .any(|(row_before, row_after)| row_before.len() != row_after.len())
+    {
+        return Err(size_error(layer));
+    }
+    for x in 0..after.values.len() {
+        for y in 0..after.values[x].len() {
+            let [lat, lon] = after.convert_coords(x, y);
+            after.values[x][y] = mix(
+                &before.values[x][y],
+                &after.values[x][y],
+                weights.get(lat, lon),
+            );
+        }
+    }
+    return Ok(());
+}
+
+// Blends every month of a monthly layer
+fn blend_months<S: MapShape, T: Clone>(
+    before: &[PartialMap<S, T>],
+    after: &mut [PartialMap<S, T>],
+    weights: &PartialMap<S, f32>,
+    layer: LayerId,
+    mix: impl Fn(&T, &T, f32) -> T + Copy,
+) -> MapResult<()> {
+    if before.len() != after.len() {
+        return Err(size_error(layer));
+    }
+    for (month_before, month_after) in before.iter().zip(after.iter_mut()) {
+        blend(month_before, month_after, weights, layer, mix)?;
+    }
+    return Ok(());
+}
+
+fn blend_layer<S: MapShape>(
+    before: &CompleteMap<S>,
+    map: &mut CompleteMap<S>,
+    layer: LayerId,
+    weights: &PartialMap<S, f32>,
+) -> MapResult<()> {
+    match layer {
+        LayerId::Height => blend(&before.height, &mut map.height, weights, layer, mix),
+        LayerId::Temperature => blend_months(
+            &before.temperature,
+            &mut map.temperature,
+            weights,
+            layer,
+            mix,
+        ),
+        LayerId::Winds => blend_months(&before.winds, &mut map.winds, weights, layer, |a, b, w| {
+            [
+                mix(&a[0], &b[0], w),
+                mix(&a[1], &b[1], w),
+                mix(&a[2], &b[2], w),
+            ]
+        }),
+        LayerId::Precipitation => blend_months(
+            &before.precipitation,
+            &mut map.precipitation,
+            weights,
+            layer,
+            mix,
+        ),
+        LayerId::AnnualPrecipitation => blend(
+            &before.annual_precipitation,
+            &mut map.annual_precipitation,
+            weights,
+            layer,
+            mix,
+        ),
+        LayerId::AtmPressure => blend_months(
+            &before.atm_pressure,
+            &mut map.atm_pressure,
+            weights,
+            layer,
+            mix,
+        ),
+        LayerId::Climate => blend(&before.climate, &mut map.climate, weights, layer, choose),
+        LayerId::Continentality => blend(
+            &before.continentality,
+            &mut map.continentality,
+            weights,
+            layer,
+            mix,
+        ),
+        LayerId::IceHeight => {
+            blend_months(&before.ice_height, &mut map.ice_height, weights, layer, mix)
+        }
+        LayerId::FreshWater => blend(
+            &before.fresh_water,
+            &mut map.fresh_water,
+            weights,
+            layer,
+            mix,
+        ),
+        LayerId::VegetationDensity => blend(
+            &before.vegetation_density,
+            &mut map.vegetation_density,
+            weights,
+            layer,
+            mix,
+        ),
+        LayerId::Coastline => {
+            let empty = HashSet::new();
+            let coastline_before = before.coastline.as_ref().unwrap_or(&empty);
+            let height = &map.height;
+            let coastline_after = map.coastline.get_or_insert_with(HashSet::new);
+            let inside = |[x, y]: &[usize; 2]| {
+                let [lat, lon] = height.convert_coords(*x, *y);
+                return weights.get(lat, lon) >= 0.5;
+            };
+            coastline_after.retain(|pixel| inside(pixel));
+            coastline_after.extend(coastline_before.iter().filter(|pixel| !inside(pixel)));
+            Ok(())
+        }
+        _ => Err(MapError::InvalidData(format!(
+            "{} cannot be masked",
+            layer.name()
+        ))),
+    }
+}
+
+impl<S: MapShape> PipelineStep<S> for Masked<S> {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let writes = self.step.writes();
+        if let Some(layer) = writes.iter().find(|layer| !MASKABLE_LAYERS.contains(layer)) {
+            return Err(MapError::InvalidData(format!(
+                "{:?} writes {}, which cannot be masked",
+                self.step,
+                layer.name()
+            )));
+        }
+        let weights = self.mask.weights(map);
+        let mut before = CompleteMap::new(0, 0);
+        for layer in &writes {
+            copy_layer(map, &mut before, *layer);
+        }
+        self.step.apply(map)?;
+        for layer in writes {
+            blend_layer(&before, map, layer, &weights)?;
+        }
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        let mut reads = self.step.reads();
+        if matches!(self.mask, Mask::Plates(_)) && !reads.contains(&LayerId::TectonicPlates) {
+            reads.push(LayerId::TectonicPlates);
+        }
+        return reads;
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return self.step.writes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline_steps::{noisy_voronoi::NoisyVoronoi, water_level::AddHeight},
+        shapes::flat::Flat,
+    };
+
+    // Plate 1 on the eastern half of the map, plate 0 on the western one
+    fn test_map() -> CompleteMap<Flat> {
+        let mut cmap = CompleteMap::new(8, 4);
+        for row in cmap.tectonic_plates.values.iter_mut() {
+            for (y, plate) in row.iter_mut().enumerate() {
+                *plate = (y >= 4) as usize;
+            }
+        }
+        cmap.coastline = Some(HashSet::new());
+        return cmap;
+    }
+
+    fn masked(mask: Mask<Flat>) -> Masked<Flat> {
+        return Masked::new(Box::new(AddHeight::new(100)), mask);
+    }
+
+    #[test]
+    fn steps_only_change_the_masked_plates() {
+        let mut cmap = test_map();
+        let step = masked(Mask::Plates(vec![1]));
+        assert!(step.reads().contains(&LayerId::TectonicPlates));
+        step.apply(&mut cmap).unwrap();
+        for row in &cmap.height.values {
+            assert_eq!(row, &vec![0, 0, 0, 0, 100, 100, 100, 100]);
+        }
+    }
+
+    #[test]
+    fn weights_blend_the_values_before_and_after() {
+        let mut cmap = test_map();
+        let half = PartialMap::from_rows(vec![vec![0.5; 8]; 4]);
+        masked(Mask::Weights(half)).apply(&mut cmap).unwrap();
+        assert!(cmap
+            .height
+            .values
+            .iter()
+            .flatten()
+            .all(|height| *height == 50));
+
+        assert_eq!(mix(&10.0f32, &20.0, 0.25), 12.5);
+        assert_eq!(choose(&1, &2, 0.49), 1);
+        assert_eq!(choose(&1, &2, 0.5), 2);
+    }
+
+    #[test]
+    fn area_masks_fade_out() {
+        let mut cmap = test_map();
+        let area = BrushArea::Circle {
+            center: [0.0, 0.0],
+            radius: 90.0,
+        };
+        masked(Mask::Area {
+            area,
+            falloff: 90.0,
+        })
+        .apply(&mut cmap)
+        .unwrap();
+        // full at the center, at [2, 4], and nothing beyond the radius
+        assert_eq!(cmap.height.values[2][4], 100);
+        assert_eq!(cmap.height.values[0][0], 0);
+        assert!((1..100).contains(&cmap.height.values[2][5]));
+    }
+
+    #[test]
+    fn steps_writing_other_layers_cannot_be_masked() {
+        let mut cmap = test_map();
+        let step = Masked::new(Box::new(NoisyVoronoi::new(1, 4)), Mask::Plates(vec![1]));
+        assert!(step.apply(&mut cmap).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod adjust_percentiles;
 pub mod annual_precipitation;
+pub mod brush;
 pub mod calculate_climate;
 pub mod climate;
 pub mod continentality;
@@ -13,17 +14,22 @@ pub mod height_noise_poles;
 pub mod hotspots;
 pub mod hydraulic_erosion;
 pub mod ice;
+pub mod layer_step;
 pub mod load_custom_layer;
 pub mod load_height;
+pub mod mask;
 pub mod mountains;
 pub mod noisy_voronoi;
 pub mod noisy_voronoi_supercontinent;
+pub mod ocean_rim;
 pub mod pipeline_step;
 pub mod plate_gap;
 pub mod precipitation;
 pub mod pressure;
+pub mod region;
 pub mod resize;
 pub mod rivers;
+pub mod sketch;
 pub mod smooth;
 pub mod supercontinent_height_noise;
 pub mod tectonic_edges;
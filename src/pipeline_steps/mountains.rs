@@ -1,8 +1,14 @@
-use std::{fmt, sync::Arc};
+use std::fmt;
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    layer_step::{height_step, ElementStep},
+    util::CustomNoise,
+};
 
 pub struct AddMountains {
     noise: CustomNoise,
@@ -30,10 +36,16 @@ impl fmt::Debug for AddMountains {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for AddMountains {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
+impl<S: MapShape> ElementStep<S, i32> for AddMountains {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, i32>,
+        input_map: &CompleteMap<S>,
+    ) -> i32 {
         let plate1 = input_map.tectonic_plates.values[x][y];
-        let mut height = input_map.height.values[x][y];
+        let mut height = layer.values[x][y];
         let direction1 = input_map.tectonic_plates_directions[plate1];
 
         let [lat, lon] = input_map.tectonic_plates.convert_coords(x, y);
@@ -58,7 +70,7 @@ impl<S: MapShape> PipelineStep<S> for AddMountains {
             let collision = scalar_prod(&direction1, &pixel_direction)
                 - scalar_prod(&pixel_direction, &direction2);
             if plate1 != plate2 && collision > 0.5 {
-                let height2 = input_map.height.get(lat2, lon2);
+                let height2 = layer.get(lat2, lon2);
                 let noise_value =
                     2.0 * (self.intensity - self.noise.get_spheric_f32::<S, i32>(lat2, lon2).abs());
                 if height2 > -300 {
@@ -75,4 +87,10 @@ impl<S: MapShape> PipelineStep<S> for AddMountains {
         }
         return height;
     }
+
+    fn map_reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::TectonicPlates];
+    }
 }
+
+height_step!(AddMountains);
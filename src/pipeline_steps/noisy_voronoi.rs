@@ -1,16 +1,25 @@
 use std::fmt;
 use std::sync::Arc;
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use crate::seed::mix;
 
 use crate::partial_map::PartialMap;
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId,
+    shapes::map_shape::MapShape,
+};
 
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    pipeline_step::PipelineStep,
+    sketch::{is_sketched_sea, sketch_water_percentage},
+    util::CustomNoise,
+};
 
 pub struct NoisyVoronoi {
     pub n_regions: usize,
+    // custom layer with a painted sketch: the first plates are centered in its sea, in
+    // proportion to it, so that AddPlateGap turns them into oceans, and the others on its land
+    pub sketch: Option<String>,
     seed: u32,
     noises: [CustomNoise; 2],
 }
@@ -19,6 +28,7 @@ impl NoisyVoronoi {
     pub fn new(seed: u32, n_regions: usize) -> Self {
         NoisyVoronoi {
             n_regions: n_regions,
+            sketch: None,
             seed,
             noises: [
                 CustomNoise::new(seed, 3.0, 10.0),
@@ -28,10 +38,27 @@ impl NoisyVoronoi {
     }
 }
 
+// Plate centers following a sketch: sea plates first, then land plates, among points drawn
+// from the seed.
+fn sketched_points<S: MapShape>(
+    tectonic_plates: &PartialMap<S, usize>,
+    sketch: &PartialMap<S, usize>,
+    n_regions: usize,
+    seed: u32,
+) -> Vec<[usize; 2]> {
+    let n_sea = (n_regions as f32 * sketch_water_percentage(sketch) / 100.0).round() as usize;
+    let candidates = tectonic_plates.get_random_points_from_seed(20 * n_regions, seed);
+    let (sea, land): (Vec<[usize; 2]>, Vec<[usize; 2]>) = candidates
+        .iter()
+        .partition(|point| is_sketched_sea(sketch, tectonic_plates, **point));
+    let mut points: Vec<[usize; 2]> = sea.iter().take(n_sea).copied().collect();
+    let others = land.iter().chain(sea.iter().skip(n_sea));
+    points.extend(others.take(n_regions - points.len()));
+    return points;
+}
+
 fn pseudo_random_float(seed: u32) -> f32 {
-    let mut hasher = DefaultHasher::new();
-    seed.hash(&mut hasher);
-    let hash = hasher.finish();
+    let hash = mix(seed as u64);
     let random_float: f32 = hash as f32 / u64::MAX as f32;
     return random_float;
 }
@@ -74,24 +101,24 @@ impl fmt::Debug for NoisyVoronoi {
 }
 
 impl<S: MapShape> PipelineStep<S> for NoisyVoronoi {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.tectonic_plates =
-            PartialMap::<S, usize>::new(input_map.height.circunference, input_map.height.height);
-
-        let points: Vec<[usize; 2]> = output_map
-            .tectonic_plates
-            .get_random_points_from_seed(self.n_regions, self.seed);
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let mut tectonic_plates =
+            PartialMap::<S, usize>::new(map.height.circunference, map.height.height);
+
+        let points: Vec<[usize; 2]> = match &self.sketch {
+            Some(layer) => {
+                let Some(sketch) = map.custom_pmaps.get(layer) else {
+                    return Err(MapError::MissingLayer(format!("custom/{}", layer)));
+                };
+                sketched_points(&tectonic_plates, sketch, self.n_regions, self.seed)
+            }
+            None => tectonic_plates.get_random_points_from_seed(self.n_regions, self.seed),
+        };
 
-        output_map.tectonic_plates_centers = vec![];
+        let mut centers = vec![];
         for i in 0..points.len() {
             let [x, y] = points[i];
-            let p_coords = output_map.tectonic_plates.convert_coords(x, y);
-            output_map.tectonic_plates_centers.push(p_coords);
+            centers.push(tectonic_plates.convert_coords(x, y));
         }
 
         for i in 0..self.n_regions {
@@ -99,15 +126,26 @@ impl<S: MapShape> PipelineStep<S> for NoisyVoronoi {
             let random_float1 = pseudo_random_float(temp_seed);
             let random_float2 = pseudo_random_float(temp_seed + 100);
             let direction = [random_float1 * 2.0 - 1.0, random_float2 * 2.0 - 1.0];
-            output_map.tectonic_plates_directions.push(direction);
+            map.tectonic_plates_directions.push(direction);
         }
 
         let operator =
             move |x, y, arc_map| self.process_element_voronoi(x, y, arc_map, points.clone());
-        output_map
-            .tectonic_plates
-            .iterate_operator(input_map, operator);
+        tectonic_plates.iterate_operator(map, operator);
+        map.tectonic_plates = tectonic_plates;
+        map.tectonic_plates_centers = centers;
+
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        if self.sketch.is_some() {
+            return vec![LayerId::Height, LayerId::CustomLayers];
+        }
+        return vec![LayerId::Height];
+    }
 
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::TectonicPlates];
     }
 }
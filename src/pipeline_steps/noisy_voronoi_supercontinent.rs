@@ -2,7 +2,10 @@ use std::fmt;
 use std::sync::Arc;
 
 use crate::partial_map::PartialMap;
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId,
+    shapes::map_shape::MapShape,
+};
 
 use super::{pipeline_step::PipelineStep, util::CustomNoise};
 
@@ -63,36 +66,37 @@ impl NoisyVoronoiSupercontinent {
 }
 
 impl<S: MapShape> PipelineStep<S> for NoisyVoronoiSupercontinent {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.tectonic_plates =
-            PartialMap::<S, usize>::new(input_map.height.circunference, input_map.height.height);
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let mut tectonic_plates =
+            PartialMap::<S, usize>::new(map.height.circunference, map.height.height);
 
-        let points: Vec<[usize; 2]> = output_map
-            .tectonic_plates
-            .get_random_points_from_seed(self.n_regions, self.seed);
+        let points: Vec<[usize; 2]> =
+            tectonic_plates.get_random_points_from_seed(self.n_regions, self.seed);
 
-        output_map.tectonic_plates_centers = vec![];
+        let mut centers = vec![];
         for [x, y] in &points {
-            let p_coords = output_map.tectonic_plates.convert_coords(*x, *y);
-            output_map.tectonic_plates_centers.push(p_coords);
+            centers.push(tectonic_plates.convert_coords(*x, *y));
         }
 
-        for p in &output_map.tectonic_plates_centers {
+        for p in &centers {
             let direction = [-p[0] as f32 / 90.0, -p[1] / 180.0];
-            output_map.tectonic_plates_directions.push(direction);
+            map.tectonic_plates_directions.push(direction);
         }
 
         let operator =
             move |x, y, arc_map| self.process_element_voronoi(x, y, arc_map, points.clone());
-        output_map
-            .tectonic_plates
-            .iterate_operator(input_map, operator);
+        tectonic_plates.iterate_operator(map, operator);
+        map.tectonic_plates = tectonic_plates;
+        map.tectonic_plates_centers = centers;
+
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
 
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::TectonicPlates];
     }
 }
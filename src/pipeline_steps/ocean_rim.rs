@@ -0,0 +1,46 @@
+use crate::{
+    complete_map::CompleteMap, partial_map::PartialMap, shapes::disc::disc_radius,
+    shapes::map_shape::MapShape,
+};
+
+use super::layer_step::{height_step, ElementStep};
+
+// Sinks the heights near the rim of a disc world down to the ocean floor, and keeps the
+// corners of the map outside of the disc as deep ocean
+#[derive(Debug)]
+pub struct OceanRim {
+    // width of the rim, as a fraction of the radius of the disc
+    pub width: f32,
+    pub depth: i32,
+}
+
+impl OceanRim {
+    pub fn new() -> Self {
+        Self {
+            width: 0.15,
+            depth: -4000,
+        }
+    }
+}
+
+impl<S: MapShape> ElementStep<S, i32> for OceanRim {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, i32>,
+        _map: &CompleteMap<S>,
+    ) -> i32 {
+        let [latitude, longitude] = layer.cell_center(x, y);
+        let inner_radius = 1.0 - self.width;
+        let radius = disc_radius(latitude, longitude);
+        if radius <= inner_radius {
+            return layer.values[x][y];
+        }
+        let t = ((radius - inner_radius) / self.width.max(f32::EPSILON)).min(1.0);
+        let height = layer.values[x][y] as f32 * (1.0 - t) + self.depth as f32 * t;
+        return height.min(layer.values[x][y] as f32) as i32;
+    }
+}
+
+height_step!(OceanRim);
@@ -1,25 +1,258 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{fmt::Debug, time::Instant};
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use serde::Serialize;
 
+use crate::{
+    complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    map_file::layers::{copy_layer, swap_layer, LayerId},
+    shapes::map_shape::MapShape,
+};
+
+// Steps change the map in place. A step that reads the old values of a layer while
+// writing it fills a new buffer for that layer only and replaces the layer at the end.
+// Steps computing a layer pixel by pixel are written as ElementStep (see layer_step).
 pub trait PipelineStep<S: MapShape + Send>: Sync + Debug {
-    fn process_element(&self, x: usize, y: usize, complete_map: Arc<&CompleteMap<S>>) -> i32;
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map
-            .height
-            .iterate_operator(input_map, |x, y, arc_map| {
-                self.process_element(x, y, arc_map)
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError>;
+
+    // Same as apply, returning the duration of every step this one is made of
+    fn apply_timed(&self, map: &mut CompleteMap<S>) -> MapResult<Vec<SubStepTiming>> {
+        self.apply(map)?;
+        return Ok(vec![]);
+    }
+
+    // Layers that must be computed before the step runs
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+
+    // Layers the step changes, possibly from their old values. A step must not touch
+    // any layer it does not declare, since independent steps run at the same time.
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+}
+
+// Duration in seconds of a step run by another one
+#[derive(Clone, Debug, Serialize)]
+pub struct SubStepTiming {
+    pub name: String,
+    pub duration: f32,
+}
+
+// The type of the step, the Debug output without the parameters
+pub fn step_name<S: MapShape>(step: &dyn PipelineStep<S>) -> String {
+    let debug = format!("{:?}", step);
+    let end = debug
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or(debug.len());
+    return debug[..end].to_string();
+}
+
+// Checks that every step only reads layers present in `map` or written by an earlier step.
+pub fn validate_steps<'a, S: MapShape + 'a>(
+    steps: impl IntoIterator<Item = &'a dyn PipelineStep<S>>,
+    map: &CompleteMap<S>,
+) -> MapResult<()> {
+    let mut present: Vec<LayerId> = LayerId::ALL
+        .iter()
+        .copied()
+        .filter(|layer| layer.is_present(map))
+        .collect();
+    for (index, step) in steps.into_iter().enumerate() {
+        if let Some(layer) = step.reads().iter().find(|layer| !present.contains(layer)) {
+            return Err(MapError::Step {
+                index,
+                source: Box::new(MapError::MissingLayer(format!(
+                    "{}, read by {:?} before any step computes it",
+                    layer.name(),
+                    step
+                ))),
             });
+        }
+        present.extend(step.writes());
+    }
+    return Ok(());
+}
 
-        // version without parallelization
-        // output_map.height.values.iter_mut().enumerate().for_each(|(x, inner_vec)| {
-        //     inner_vec.iter_mut().enumerate().for_each(|(y, num)| {
-        //         *num = self.process_element(x, y, Arc::new(input_map));
-        //     });
-        // });
+// Layers read by a sequence of steps that no earlier step of the sequence writes, which are
+// the layers read by a step running all of them in order
+pub fn sequence_reads<'a, S: MapShape + 'a>(
+    steps: impl IntoIterator<Item = &'a dyn PipelineStep<S>>,
+) -> Vec<LayerId> {
+    let mut written: Vec<LayerId> = vec![];
+    let mut reads: Vec<LayerId> = vec![];
+    for step in steps {
+        for layer in step.reads() {
+            if !written.contains(&layer) && !reads.contains(&layer) {
+                reads.push(layer);
+            }
+        }
+        written.extend(step.writes());
+    }
+    return reads;
+}
+
+// Layers written by any step of a sequence
+pub fn sequence_writes<'a, S: MapShape + 'a>(
+    steps: impl IntoIterator<Item = &'a dyn PipelineStep<S>>,
+) -> Vec<LayerId> {
+    let mut writes: Vec<LayerId> = vec![];
+    for layer in steps.into_iter().flat_map(|step| step.writes()) {
+        if !writes.contains(&layer) {
+            writes.push(layer);
+        }
+    }
+    return writes;
+}
+
+// Applies a step to an existing map, without changing it when a layer the step reads is missing.
+#[allow(dead_code)]
+pub fn apply_checked<S: MapShape>(
+    step: &dyn PipelineStep<S>,
+    map: &mut CompleteMap<S>,
+) -> MapResult<()> {
+    if let Some(layer) = step.reads().iter().find(|layer| !layer.is_present(map)) {
+        return Err(MapError::MissingLayer(layer.name().to_string()));
+    }
+    return step.apply(map);
+}
+
+// Whether `later` can run at the same time as `earlier` and give the same map as when run
+// after it: it must not read nor write anything `earlier` writes.
+pub fn independent<S: MapShape>(
+    earlier: &dyn PipelineStep<S>,
+    later: &dyn PipelineStep<S>,
+) -> bool {
+    let written = earlier.writes();
+    return !later
+        .reads()
+        .iter()
+        .chain(later.writes().iter())
+        .any(|layer| written.contains(layer));
+}
+
+// Runs independent steps at the same time, with the same result as running them in order.
+// The first step changes `map`, the others a copy of the layers they use, which are then
+// moved into `map`. Returns the duration in seconds of every step, with the durations of
+// the steps it is made of.
+pub fn apply_concurrently<S: MapShape>(
+    steps: &[&dyn PipelineStep<S>],
+    map: &mut CompleteMap<S>,
+) -> Vec<MapResult<(f32, Vec<SubStepTiming>)>> {
+    let timed = |step: &dyn PipelineStep<S>, map: &mut CompleteMap<S>| {
+        let start = Instant::now();
+        let sub_steps = step.apply_timed(map)?;
+        return Ok((start.elapsed().as_secs_f32(), sub_steps));
+    };
+    let Some((first, others)) = steps.split_first() else {
+        return vec![];
+    };
+    let mut copies: Vec<CompleteMap<S>> = others
+        .iter()
+        .map(|step| {
+            let mut copy = CompleteMap::new(0, 0);
+            for layer in step.reads().into_iter().chain(step.writes()) {
+                copy_layer(map, &mut copy, layer);
+            }
+            copy
+        })
+        .collect();
+
+    let mut results: Vec<MapResult<(f32, Vec<SubStepTiming>)>> =
+        steps.iter().map(|_| Ok((0.0, vec![]))).collect();
+    let (first_result, other_results) = results.split_at_mut(1);
+    rayon::scope(|scope| {
+        for ((step, copy), result) in others.iter().zip(copies.iter_mut()).zip(other_results) {
+            scope.spawn(move |_| *result = timed(*step, copy));
+        }
+        first_result[0] = timed(*first, map);
+    });
+
+    for (step, copy) in others.iter().zip(copies.iter_mut()) {
+        for layer in step.writes() {
+            swap_layer(map, copy, layer);
+        }
+    }
+    return results;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline_steps::{
+            calculate_climate::CalculateClimate, continentality::CalculateContinentality,
+            define_coastlines::DefineCoastline, ice::Ice,
+        },
+        shapes::globe::Globe,
+    };
+
+    // Two continents in an ocean
+    fn test_map() -> CompleteMap<Globe> {
+        let mut map = CompleteMap::new(80, 40);
+        for (x, row) in map.height.values.iter_mut().enumerate() {
+            for (y, value) in row.iter_mut().enumerate() {
+                *value = (2000.0 * (x as f32 / 6.0).sin() * (y as f32 / 9.0).cos()) as i32;
+            }
+        }
+        return map;
+    }
+
+    #[test]
+    fn concurrent_steps_give_the_same_map_as_sequential_steps() {
+        let steps: [&dyn PipelineStep<Globe>; 2] =
+            [&CalculateContinentality {}, &DefineCoastline {}];
+        assert!(independent(steps[0], steps[1]));
+
+        let mut sequential = test_map();
+        for step in steps {
+            step.apply(&mut sequential).unwrap();
+        }
+        let mut concurrent = test_map();
+        for result in apply_concurrently(&steps, &mut concurrent) {
+            result.unwrap();
+        }
+        // the coastline is a set, serialized in no particular order
+        assert!(sequential.coastline.is_some());
+        assert_eq!(sequential.coastline.take(), concurrent.coastline.take());
+        assert_eq!(
+            bincode::serialize(&sequential).unwrap(),
+            bincode::serialize(&concurrent).unwrap()
+        );
+    }
+
+    #[test]
+    fn validate_steps_rejects_ice_before_temperature() {
+        let map: CompleteMap<Globe> = CompleteMap::new(80, 40);
+        let climate = CalculateClimate::new(30.0, -20.0, 1.0);
+        let ice = Ice {};
+
+        let result = validate_steps::<Globe>([&ice as &dyn PipelineStep<Globe>, &climate], &map);
+        assert!(matches!(result, Err(MapError::Step { index: 0, .. })));
+        assert!(
+            validate_steps::<Globe>([&climate as &dyn PipelineStep<Globe>, &ice], &map).is_ok()
+        );
+    }
 
-        return output_map;
+    #[test]
+    fn climate_declares_the_layers_of_its_steps() {
+        let climate = CalculateClimate::new(30.0, -20.0, 1.0);
+        assert_eq!(
+            PipelineStep::<Globe>::reads(&climate),
+            vec![LayerId::Height]
+        );
+        let writes = PipelineStep::<Globe>::writes(&climate);
+        for layer in [
+            LayerId::Continentality,
+            LayerId::Temperature,
+            LayerId::Precipitation,
+            LayerId::AnnualPrecipitation,
+            LayerId::Climate,
+            LayerId::VegetationDensity,
+            LayerId::Rivers,
+        ] {
+            assert!(writes.contains(&layer), "{:?}", layer);
+        }
     }
 }
@@ -1,6 +1,9 @@
-use std::{fmt, sync::Arc};
+use std::fmt;
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 
@@ -36,8 +39,10 @@ impl fmt::Debug for AddPlateGap {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for AddPlateGap {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
+impl AddPlateGap {
+    // Lowers the land next to plates moving in the same direction. Not used by apply.
+    #[allow(dead_code)]
+    fn process_element<S: MapShape>(&self, x: usize, y: usize, input_map: &CompleteMap<S>) -> i32 {
         if input_map.height.values[x][y] < 0 {
             return input_map.height.values[x][y];
         }
@@ -61,39 +66,50 @@ impl<S: MapShape> PipelineStep<S> for AddPlateGap {
         }
         return value;
     }
+}
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        for x in 0..output_map.height.values.len() {
-            for y in 0..output_map.height.values[x].len() {
-                // if output_map.height.values[x][y] < 0 {
+impl<S: MapShape> PipelineStep<S> for AddPlateGap {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        for x in 0..map.height.values.len() {
+            for y in 0..map.height.values[x].len() {
+                // if map.height.values[x][y] < 0 {
                 //     continue;
                 // }
-                let plate1 = output_map.tectonic_plates.values[x][y];
+                let plate1 = map.tectonic_plates.values[x][y];
 
                 if plate1 < self.oceanic_plates {
-                    output_map.height.values[x][y] = output_map.height.values[x][y].min(-2000);
+                    map.height.values[x][y] = map.height.values[x][y].min(-2000);
                     continue;
                 }
 
-                let direction_plate1 = output_map.tectonic_plates_directions[plate1];
-                let x2 = (x as i32 - (self.distance as f32 * direction_plate1[0]) as i32)
-                    .max(0)
-                    .min((output_map.height.values.len() - 1).max(0) as i32)
-                    as usize;
+                let direction_plate1 = map.tectonic_plates_directions[plate1];
+                let x2 = x as i32 - (self.distance as f32 * direction_plate1[0]) as i32;
+                let x2 = if S::WRAPS_ROWS {
+                    x2.rem_euclid(map.height.values.len() as i32) as usize
+                } else {
+                    x2.clamp(0, map.height.values.len() as i32 - 1) as usize
+                };
                 let y2 = (y as i32 - (self.distance as f32 * direction_plate1[1]) as i32)
-                    .rem_euclid((output_map.height.values[x2].len() - 1).max(1) as i32)
+                    .rem_euclid((map.height.values[x2].len() - 1).max(1) as i32)
                     as usize;
-                let plate2 = output_map.tectonic_plates.values[x2][y2];
+                let plate2 = map.tectonic_plates.values[x2][y2];
                 if plate2 != plate1 {
-                    let direction_plate2 = output_map.tectonic_plates_directions[plate2];
+                    let direction_plate2 = map.tectonic_plates_directions[plate2];
 
                     if scalar_prod(&direction_plate1, &direction_plate2) < 0.0 {
-                        output_map.height.values[x][y] = output_map.height.values[x][y].min(-2000);
+                        map.height.values[x][y] = map.height.values[x][y].min(-2000);
                     }
                 }
             }
         }
-        return output_map;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::TectonicPlates];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
     }
 }
@@ -1,4 +1,7 @@
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 use std::{f32::consts::PI, fmt, sync::Arc};
 
 use super::{
@@ -81,22 +84,11 @@ impl CalculatePrecipitation {
     }
     fn process_precipitation_element<S: MapShape>(
         &self,
-        x: usize,
-        y: usize,
+        [latitude, longitude]: [f32; 2],
         complete_map: Arc<&CompleteMap<S>>,
         time_of_year: usize,
         itcz: &Vec<[f32; 2]>,
     ) -> i32 {
-        let pmap_height = 250.0;
-        let latitude = x as f32 * 180.0 / pmap_height - 90.0;
-
-        let v_length = 1.0
-            + (1.99
-                * 2.0
-                * ((pmap_height / 2.0).powf(2.0) - (x as f32 - pmap_height / 2.0).powf(2.0)).sqrt())
-            .ceil() as f32;
-        let longitude = y as f32 * 360.0 / v_length - 180.0;
-
         let itcz_distance = get_itcz_distance(latitude, longitude, itcz);
 
         let mut max_dist;
@@ -219,25 +211,27 @@ impl CalculatePrecipitation {
 }
 
 impl<S: MapShape> PipelineStep<S> for CalculatePrecipitation {
-    fn process_element(&self, _x: usize, _y: usize, _complete_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-
-        output_map.precipitation = vec![];
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let mut precipitation = vec![];
 
-        let half_length = (input_map.temperature.len() as f32 / 2.0).ceil() as usize;
+        let half_length = (map.temperature.len() as f32 / 2.0).ceil() as usize;
+        let grid: &PartialMap<S, i32> = &PartialMap::new(500, 250);
         for i in 0..=half_length {
-            let itcz = calculate_itcz(&input_map, i);
-            let operator =
-                move |x, y, arc_map| self.process_precipitation_element(x, y, arc_map, i, &itcz);
+            let itcz = calculate_itcz(map, i);
+            let operator = move |x, y, arc_map| {
+                // the cells of other shapes are not aligned with the parallels their corner is on
+                let coords = if S::LAT_LON_GRID {
+                    grid.convert_coords(x, y)
+                } else {
+                    grid.cell_center(x, y)
+                };
+                self.process_precipitation_element(coords, arc_map, i, &itcz)
+            };
             let mut prec_map = PartialMap::new(500, 250);
-            prec_map.iterate_operator(input_map, operator);
+            prec_map.iterate_operator(map, operator);
             let mut smooth_prec_map = smooth_pmap(&prec_map, 1);
-            while output_map.height.circunference > smooth_prec_map.circunference {
-                let factor = (output_map.height.circunference as f32
+            while map.height.circunference > smooth_prec_map.circunference {
+                let factor = (map.height.circunference as f32
                     / smooth_prec_map.circunference as f32)
                     .min(2.0);
                 if factor == 2.0 {
@@ -247,14 +241,27 @@ impl<S: MapShape> PipelineStep<S> for CalculatePrecipitation {
                     smooth_prec_map = smooth_pmap(&smooth_prec_map, 1);
                 }
             }
-            output_map.precipitation.push(smooth_prec_map);
+            precipitation.push(smooth_prec_map);
         }
 
         // Remaining months mirror the previous ones
-        for i in half_length + 1..input_map.temperature.len() {
-            let prec_map = output_map.precipitation[input_map.temperature.len() - i].clone();
-            output_map.precipitation.push(prec_map)
+        for i in half_length + 1..map.temperature.len() {
+            let prec_map = precipitation[map.temperature.len() - i].clone();
+            precipitation.push(prec_map)
         }
-        return output_map;
+        map.precipitation = precipitation;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![
+            LayerId::Height,
+            LayerId::Continentality,
+            LayerId::Temperature,
+        ];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Precipitation];
     }
 }
@@ -1,12 +1,27 @@
-use std::{f32::consts::PI, sync::Arc};
+use std::f32::consts::PI;
 
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
-use super::{pipeline_step::PipelineStep, resize::resize};
+use super::{
+    layer_step::apply_to_layer, pipeline_step::PipelineStep, resize::resize, smooth::Smooth,
+};
 
 #[derive(Debug)]
 pub struct DefinePressure {}
 
+// Mean temperature along a parallel
+#[allow(dead_code)]
+fn parallel_temperature<S: MapShape>(temperature_map: &PartialMap<S, f32>, latitude: f32) -> f32 {
+    let mut temperature = 0.0;
+    for t in 0..30 {
+        temperature += temperature_map.get(latitude, t as f32 * 360.0 / 30.0);
+    }
+    return temperature / 30.0;
+}
+
 impl DefinePressure {
     pub fn new() -> Self {
         Self {}
@@ -20,16 +35,27 @@ impl DefinePressure {
     ) -> PartialMap<S, i32> {
         let temperature_map = &input_map.temperature[month as usize];
         let mut pressure_map = PartialMap::<S, i32>::new(250, 125);
-        for i in 0..pressure_map.values.len() {
-            let mut expected_temperature = 0.0;
-            let [latitude, _] = pressure_map.convert_coords(i, 0);
-            for t in 0..30 {
-                expected_temperature += temperature_map.get(latitude, t as f32 * 360.0 / 30.0);
+        // Cells of shapes whose rows are not parallels take the mean temperature of the band
+        // of latitudes they are in
+        let n_bands = pressure_map.values.len();
+        let mut band_temperatures = vec![];
+        if !S::LAT_LON_GRID {
+            for k in 0..n_bands {
+                let latitude = -90.0 + (k as f32 + 0.5) * 180.0 / n_bands as f32;
+                band_temperatures.push(parallel_temperature(temperature_map, latitude));
             }
-            expected_temperature /= 30.0;
+        }
+        for i in 0..pressure_map.values.len() {
+            let row_temperature = S::LAT_LON_GRID.then(|| {
+                parallel_temperature(temperature_map, pressure_map.convert_coords(i, 0)[0])
+            });
             for j in 0..pressure_map.values[i].len() {
                 let displacement = (month as f32 * 2.0 * PI / total_months as f32).cos() * 5.0;
                 let [latitude, longitude] = pressure_map.convert_coords(i, j);
+                let expected_temperature = row_temperature.unwrap_or_else(|| {
+                    let band = (n_bands as f32 * (latitude + 90.0) / 180.0) as usize;
+                    band_temperatures[band.min(n_bands - 1)]
+                });
                 let latitude_displaced = latitude - displacement;
                 let mut temperature = temperature_map.get(latitude, longitude);
                 let mut pressure = 0;
@@ -76,29 +102,33 @@ impl DefinePressure {
 }
 
 impl<S: MapShape> PipelineStep<S> for DefinePressure {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.atm_pressure = vec![];
-        for t in 0..input_map.temperature.len() {
-            output_map.atm_pressure.push(self.define_month_pressure(
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let mut atm_pressure = vec![];
+        for t in 0..map.temperature.len() {
+            atm_pressure.push(self.define_month_pressure(
                 t as u32,
-                input_map.temperature.len() as u32,
-                input_map,
+                map.temperature.len() as u32,
+                map,
             ));
         }
-        for t in 0..output_map.temperature.len() {
-            resize(&mut output_map.temperature[t as usize], 2.0);
+        map.atm_pressure = atm_pressure;
+        for t in 0..map.temperature.len() {
+            resize(&mut map.temperature[t as usize], 2.0);
         }
-        output_map = SmoothPressure::new().apply(&output_map);
-        for t in 0..output_map.temperature.len() {
-            resize(&mut output_map.temperature[t as usize], 2.0);
+        SmoothPressure::new().apply(map)?;
+        for t in 0..map.temperature.len() {
+            resize(&mut map.temperature[t as usize], 2.0);
         }
-        output_map = SmoothPressure::new().apply(&output_map);
-        return output_map;
+        SmoothPressure::new().apply(map)?;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::Temperature];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::AtmPressure, LayerId::Temperature];
     }
 }
 
@@ -113,41 +143,22 @@ impl SmoothPressure {
     }
 }
 
-impl SmoothPressure {
-    fn process_pressure_element<S: MapShape>(
-        &self,
-        x: usize,
-        y: usize,
-        input_map: Arc<&CompleteMap<S>>,
-        time_of_year: usize,
-    ) -> i32 {
-        let neighbors =
-            input_map.atm_pressure[time_of_year].get_pixel_neighbours([x, y], self.pixel_distance);
-        let mut sum = 0;
-        let mut len = 0;
-        for i in 0..neighbors.len() {
-            for j in 0..neighbors[i].len() {
-                sum += neighbors[i][j];
-                len += 1;
-            }
+impl<S: MapShape> PipelineStep<S> for SmoothPressure {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let smooth = Smooth {
+            pixel_distance: self.pixel_distance,
+        };
+        for i in 0..map.atm_pressure.len() {
+            apply_to_layer::<S, i32, _>(&smooth, &format!("atm_pressure/{}", i), map)?;
         }
-        let mean = sum / len;
-        return mean;
+        return Ok(());
     }
-}
 
-impl<S: MapShape> PipelineStep<S> for SmoothPressure {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::AtmPressure];
     }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
 
-        for i in 0..input_map.atm_pressure.len() {
-            let operator = move |x, y, arc_map| self.process_pressure_element(x, y, arc_map, i);
-            // process elements in parallel with rayon
-            output_map.atm_pressure[i].iterate_operator(input_map, operator)
-        }
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::AtmPressure];
     }
 }
@@ -0,0 +1,426 @@
+use std::{collections::HashMap, fmt, fs, sync::Arc};
+
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    map_file::layers::LayerId,
+    map_view::color_scheme::CategoryColorScheme,
+    partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+use super::{
+    pipeline_step::PipelineStep,
+    resize::interpolate,
+    rivers::{River, RiverPoint},
+};
+
+// Name of the custom layer ExtractRegion writes: where the parent world is sea, land, or
+// close to its coast
+pub const PARENT_COAST_LAYER: &str = "parent_coast";
+const SEA: usize = 0;
+const COAST: usize = 1;
+const LAND: usize = 2;
+
+// Part of a world, in its latitudes and longitudes. Longitudes may cross the antimeridian,
+// e.g. [170, -170].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionWindow {
+    pub latitude: [f32; 2],
+    pub longitude: [f32; 2],
+}
+
+fn wrap_longitude(longitude: f32) -> f32 {
+    return (longitude + 180.0).rem_euclid(360.0) - 180.0;
+}
+
+impl RegionWindow {
+    pub fn validate(&self) -> MapResult<()> {
+        let [lat0, lat1] = self.latitude;
+        if !(-90.0..=90.0).contains(&lat0) || !(-90.0..=90.0).contains(&lat1) || lat0 >= lat1 {
+            return Err(MapError::InvalidData(format!(
+                "invalid latitudes {:?} for a region",
+                self.latitude
+            )));
+        }
+        if self.longitude_span() <= 0.0 {
+            return Err(MapError::InvalidData(format!(
+                "invalid longitudes {:?} for a region",
+                self.longitude
+            )));
+        }
+        return Ok(());
+    }
+
+    fn latitude_span(&self) -> f32 {
+        return self.latitude[1] - self.latitude[0];
+    }
+
+    fn longitude_span(&self) -> f32 {
+        return (self.longitude[1] - self.longitude[0]).rem_euclid(360.0);
+    }
+
+    // Rows of a layer of the parent covered by the window
+    pub fn parent_rows(&self, parent_rows: usize) -> f32 {
+        return self.latitude_span() * parent_rows as f32 / 180.0;
+    }
+
+    // Latitude and longitude in the parent of a point given as fractions of the window
+    fn parent_coords(&self, [fx, fy]: [f32; 2]) -> [f32; 2] {
+        return [
+            self.latitude[0] + fx * self.latitude_span(),
+            wrap_longitude(self.longitude[0] + fy * self.longitude_span()),
+        ];
+    }
+
+    // Fractions of the window of a point of the parent, which are outside [0, 1] for
+    // points outside it
+    fn fractions(&self, [lat, lon]: [f32; 2]) -> [f32; 2] {
+        let fy = (lon - self.longitude[0]).rem_euclid(360.0);
+        // points closer to the start of the window than to its end, across the antimeridian
+        let fy = if fy > (360.0 + self.longitude_span()) / 2.0 {
+            fy - 360.0
+        } else {
+            fy
+        };
+        return [
+            (lat - self.latitude[0]) / self.latitude_span(),
+            fy / self.longitude_span(),
+        ];
+    }
+
+    // Latitude and longitude in the region of a point of the parent
+    fn region_coords(&self, point: [f32; 2]) -> [f32; 2] {
+        let [fx, fy] = self.fractions(point);
+        return [fx * 180.0 - 90.0, fy * 360.0 - 180.0];
+    }
+
+    fn contains(&self, point: [f32; 2]) -> bool {
+        let [fx, fy] = self.fractions(point);
+        return (0.0..1.0).contains(&fx) && (0.0..1.0).contains(&fy);
+    }
+}
+
+// A region of a world, refined by the region recipe into a map of its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionConfig {
+    #[serde(flatten)]
+    pub window: RegionWindow,
+    pub width_pixels: usize,
+    // by default, keeps the proportions of the window on the ground
+    #[serde(default)]
+    pub height_pixels: Option<usize>,
+    // by default, the seed of the world
+    #[serde(default)]
+    pub seed: Option<u32>,
+    // strength of the added noise, relative to the recipe
+    #[serde(default = "default_detail")]
+    pub detail: f32,
+    #[serde(default = "default_erosion_iterations")]
+    pub erosion_iterations: u32,
+    // recipe run after the layers of the parent are extracted, instead of the region recipe
+    #[serde(default)]
+    pub recipe: Option<String>,
+}
+
+fn default_detail() -> f32 {
+    return 1.0;
+}
+
+fn default_erosion_iterations() -> u32 {
+    return 2;
+}
+
+impl RegionConfig {
+    #[allow(dead_code)]
+    pub fn from_file(filename: &str) -> MapResult<Self> {
+        let data = fs::read_to_string(filename)
+            .map_err(|e| MapError::Config(format!("could not read {}: {}", filename, e)))?;
+        let region: RegionConfig = serde_json::from_str(&data)?;
+        region.size()?;
+        return Ok(region);
+    }
+
+    pub fn size(&self) -> MapResult<[usize; 2]> {
+        self.window.validate()?;
+        let height = self.height_pixels.unwrap_or_else(|| {
+            // degrees of longitude get shorter away from the equator
+            let [lat0, lat1] = self.window.latitude;
+            let ground_span =
+                self.window.longitude_span() * ((lat0 + lat1) / 2.0).to_radians().cos();
+            (self.width_pixels as f32 * self.window.latitude_span() / ground_span).round() as usize
+        });
+        if self.width_pixels == 0 || height == 0 {
+            return Err(MapError::InvalidData(format!(
+                "invalid size {}x{} for a region",
+                self.width_pixels, height
+            )));
+        }
+        return Ok([self.width_pixels, height]);
+    }
+}
+
+// Fractions of the window of the center of a pixel
+fn pixel_fractions<S: MapShape, T: Clone>(pmap: &PartialMap<S, T>, x: usize, y: usize) -> [f32; 2] {
+    return [
+        (x as f32 + 0.5) / pmap.values.len() as f32,
+        (y as f32 + 0.5) / pmap.values[x].len() as f32,
+    ];
+}
+
+// A layer of the parent over the window, with as many pixels for each pixel of the height
+// as the parent layer has
+fn extract_layer<P: MapShape, S: MapShape, T: Clone + Default>(
+    parent: &PartialMap<P, T>,
+    parent_height: &PartialMap<P, i32>,
+    window: &RegionWindow,
+    [circunference, height]: [usize; 2],
+) -> PartialMap<S, T> {
+    if parent.values.is_empty() {
+        return PartialMap::new(0, 0);
+    }
+    let factor = parent.height as f32 / parent_height.height as f32;
+    let mut pmap = PartialMap::new(
+        ((circunference as f32 * factor).round() as usize).max(1),
+        ((height as f32 * factor).round() as usize).max(1),
+    );
+    for x in 0..pmap.values.len() {
+        for y in 0..pmap.values[x].len() {
+            let [lat, lon] = window.parent_coords(pixel_fractions(&pmap, x, y));
+            pmap.values[x][y] = parent.get(lat, lon);
+        }
+    }
+    return pmap;
+}
+
+fn extract_months<P: MapShape, S: MapShape, T: Clone + Default>(
+    parent: &[PartialMap<P, T>],
+    parent_height: &PartialMap<P, i32>,
+    window: &RegionWindow,
+    size: [usize; 2],
+) -> Vec<PartialMap<S, T>> {
+    return parent
+        .iter()
+        .map(|month| extract_layer(month, parent_height, window, size))
+        .collect();
+}
+
+// Heights interpolated between the pixels of the parent, and whether the parent is sea or
+// land within one of its pixels of each point
+fn extract_height<P: MapShape, S: MapShape>(
+    parent: &PartialMap<P, i32>,
+    window: &RegionWindow,
+    [circunference, height]: [usize; 2],
+) -> (PartialMap<S, i32>, PartialMap<S, usize>) {
+    let mut heights = PartialMap::new(circunference, height);
+    let mut coast = PartialMap::new(circunference, height);
+    let n_rows = parent.values.len();
+    for x in 0..heights.values.len() {
+        for y in 0..heights.values[x].len() {
+            let [lat, lon] = window.parent_coords(pixel_fractions(&heights, x, y));
+            let [pixel_lat, pixel_lon] = if P::LAT_LON_GRID {
+                let row = ((lat + 90.0) / 180.0 * n_rows as f32 - 0.5).max(0.0);
+                heights.values[x][y] =
+                    interpolate(parent, row, (lon + 180.0) / 360.0).round() as i32;
+                let parent_row = &parent.values[(row as usize).min(n_rows - 1)];
+                [180.0 / n_rows as f32, 360.0 / parent_row.len() as f32]
+            } else {
+                heights.values[x][y] = parent.interpolate_at(lat, lon).round() as i32;
+                [
+                    180.0 / parent.height as f32,
+                    360.0 / parent.circunference as f32,
+                ]
+            };
+            let land = [[-1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0]]
+                .iter()
+                .filter(|[dx, dy]| parent.get(lat + dx * pixel_lat, lon + dy * pixel_lon) > 0)
+                .count();
+            coast.values[x][y] = match land {
+                0 => SEA,
+                4 => LAND,
+                _ => COAST,
+            };
+        }
+    }
+    return (heights, coast);
+}
+
+// Positions in the region of the points of rivers of the parent, split where they leave
+// the window
+fn extract_rivers<P: MapShape, S: MapShape>(
+    rivers: &[River],
+    parent_height: &PartialMap<P, i32>,
+    window: &RegionWindow,
+    height: &PartialMap<S, i32>,
+) -> Vec<River> {
+    let mut region_rivers = vec![];
+    for river in rivers {
+        let mut current: River = vec![];
+        for point in river {
+            let [x, y] = point.position;
+            if x >= parent_height.values.len() || y >= parent_height.values[x].len() {
+                continue;
+            }
+            let parent_point = parent_height.cell_center(x, y);
+            if !window.contains(parent_point) {
+                if current.len() > 1 {
+                    region_rivers.push(current);
+                }
+                current = vec![];
+                continue;
+            }
+            let [fx, fy] = window.fractions(parent_point);
+            let x2 = ((fx * height.values.len() as f32) as usize).min(height.values.len() - 1);
+            let row_len = height.values[x2].len();
+            let y2 = ((fy * row_len as f32) as usize).min(row_len - 1);
+            current.push(RiverPoint {
+                position: [x2, y2],
+                volume: point.volume,
+                direction: point.direction,
+            });
+        }
+        if current.len() > 1 {
+            region_rivers.push(current);
+        }
+    }
+    return region_rivers;
+}
+
+fn extract_points(points: &[[f32; 2]], window: &RegionWindow) -> Vec<[f32; 2]> {
+    return points
+        .iter()
+        .filter(|point| window.contains(**point))
+        .map(|point| window.region_coords(*point))
+        .collect();
+}
+
+// Starts a map of part of a world with the layers of the world over it, at the size of
+// the pipeline. The coordinates of the map are those of the region: latitudes -90 to 90
+// and longitudes -180 to 180 cover the window. Climate layers keep the resolution they
+// have relative to the height in the parent.
+pub struct ExtractRegion<P: MapShape> {
+    pub parent: Arc<CompleteMap<P>>,
+    pub window: RegionWindow,
+}
+
+impl<P: MapShape> fmt::Debug for ExtractRegion<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ExtractRegion({:?})", self.window)
+    }
+}
+
+impl<P: MapShape, S: MapShape> PipelineStep<S> for ExtractRegion<P> {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let parent = self.parent.as_ref();
+        let window = &self.window;
+        let size = [map.height.circunference, map.height.height];
+        let parent_height = &parent.height;
+        if parent_height.values.is_empty() {
+            return Err(MapError::MissingLayer("height".to_string()));
+        }
+
+        let (height, coast) = extract_height(parent_height, window, size);
+        map.rivers = extract_rivers(&parent.rivers, parent_height, window, &height);
+        map.height = height;
+        map.tectonic_plates = extract_layer(&parent.tectonic_plates, parent_height, window, size);
+        map.tectonic_plates_directions = parent.tectonic_plates_directions.clone();
+        map.tectonic_plates_centers = parent
+            .tectonic_plates_centers
+            .iter()
+            .map(|center| window.region_coords(*center))
+            .collect();
+        map.oceanic_plates = parent.oceanic_plates.clone();
+
+        map.temperature = extract_months(&parent.temperature, parent_height, window, size);
+        map.winds = extract_months(&parent.winds, parent_height, window, size);
+        map.precipitation = extract_months(&parent.precipitation, parent_height, window, size);
+        map.annual_precipitation =
+            extract_layer(&parent.annual_precipitation, parent_height, window, size);
+        map.atm_pressure = extract_months(&parent.atm_pressure, parent_height, window, size);
+        map.climate = extract_layer(&parent.climate, parent_height, window, size);
+        map.continentality = extract_layer(&parent.continentality, parent_height, window, size);
+        map.ice_height = extract_months(&parent.ice_height, parent_height, window, size);
+        map.fresh_water = extract_layer(&parent.fresh_water, parent_height, window, size);
+        map.vegetation_density =
+            extract_layer(&parent.vegetation_density, parent_height, window, size);
+
+        map.tectonic_edges = extract_points(&parent.tectonic_edges, window);
+        map.mountain_chains = extract_points(&parent.mountain_chains, window);
+        map.andean_chains = extract_points(&parent.andean_chains, window);
+        map.hymalayan_chains = extract_points(&parent.hymalayan_chains, window);
+        map.trenches = extract_points(&parent.trenches, window);
+        map.hotspots = extract_points(&parent.hotspots, window);
+        map.coastline = None;
+
+        map.custom_pmaps = parent
+            .custom_pmaps
+            .iter()
+            .map(|(name, pmap)| {
+                (
+                    name.clone(),
+                    extract_layer(pmap, parent_height, window, size),
+                )
+            })
+            .collect();
+        map.custom_color_schemes = parent.custom_color_schemes.clone();
+        let color_map: HashMap<usize, Rgba<u8>> = HashMap::from([
+            (SEA, Rgba([40, 80, 200, 255])),
+            (COAST, Rgba([220, 210, 90, 255])),
+            (LAND, Rgba([60, 160, 60, 255])),
+        ]);
+        map.custom_pmaps
+            .insert(PARENT_COAST_LAYER.to_string(), coast);
+        map.custom_color_schemes.insert(
+            PARENT_COAST_LAYER.to_string(),
+            CategoryColorScheme { color_map },
+        );
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return LayerId::ALL.to_vec();
+    }
+}
+
+// Keeps land where the parent of a region is land and sea where it is sea, so that only the
+// coasts close to those of the parent move
+#[derive(Debug)]
+pub struct KeepParentCoast {
+    pub layer: String,
+}
+
+impl<S: MapShape> PipelineStep<S> for KeepParentCoast {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let coast = map
+            .custom_pmaps
+            .get(&self.layer)
+            .ok_or_else(|| MapError::MissingLayer(format!("custom/{}", self.layer)))?;
+        for x in 0..map.height.values.len() {
+            for y in 0..map.height.values[x].len() {
+                let [lat, lon] = map.height.convert_coords(x, y);
+                let height = &mut map.height.values[x][y];
+                match coast.get(lat, lon) {
+                    SEA => *height = (*height).min(0),
+                    LAND => *height = (*height).max(1),
+                    _ => {}
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::CustomLayers];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+}
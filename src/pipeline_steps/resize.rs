@@ -1,19 +1,17 @@
-use std::collections::hash_map::DefaultHasher;
-use std::{
-    fmt,
-    hash::{Hash, Hasher},
-    sync::Arc,
-    vec,
-};
+use crate::seed::mix;
+use std::{fmt, vec};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 use super::rivers::River;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Resize {
     pub factor: f32,
 }
@@ -26,7 +24,33 @@ impl fmt::Debug for Resize {
     }
 }
 
+// Resamples a layer of a shape whose rows are not bands of latitude, with the value of the
+// layer at the center of every new cell
+fn resample_cells<S: MapShape, T: Clone + Default>(
+    pmap: &mut PartialMap<S, T>,
+    circunference: usize,
+    height: usize,
+    value: impl Fn(&PartialMap<S, T>, f32, f32) -> T,
+) {
+    let mut new_pmap = PartialMap::new(circunference, height);
+    for x in 0..new_pmap.values.len() {
+        for y in 0..new_pmap.values[x].len() {
+            let [latitude, longitude] = new_pmap.cell_center(x, y);
+            new_pmap.values[x][y] = value(pmap, latitude, longitude);
+        }
+    }
+    *pmap = new_pmap;
+}
+
 pub fn resize<S: MapShape, T: Clone + Default>(pmap: &mut PartialMap<S, T>, factor: f32) {
+    if !S::LAT_LON_GRID {
+        let [circunference, height] = [
+            (factor * pmap.circunference as f32) as usize,
+            (factor * pmap.height as f32) as usize,
+        ];
+        resample(pmap, circunference, height);
+        return;
+    }
     let mut new_values = S::new_vec(
         (factor * pmap.circunference as f32) as usize,
         (factor * pmap.height as f32) as usize,
@@ -45,6 +69,16 @@ pub fn resize<S: MapShape, T: Clone + Default>(pmap: &mut PartialMap<S, T>, fact
 }
 
 pub fn resize_f32<S: MapShape>(pmap: &mut PartialMap<S, f32>, factor: f32) {
+    if !S::LAT_LON_GRID {
+        let [circunference, height] = [
+            (factor * pmap.circunference as f32) as usize,
+            (factor * pmap.height as f32) as usize,
+        ];
+        resample_cells(pmap, circunference, height, |pmap, latitude, longitude| {
+            pmap.interpolate_at(latitude, longitude)
+        });
+        return;
+    }
     let mut new_values = S::new_vec(
         (factor * pmap.circunference as f32) as usize,
         (factor * pmap.height as f32) as usize,
@@ -69,15 +103,21 @@ pub fn resize_f32<S: MapShape>(pmap: &mut PartialMap<S, f32>, factor: f32) {
         if i % 2 == 0 {
             continue;
         }
+        // the last row is next to the first one when rows wrap around
+        let i_down = if S::WRAPS_ROWS {
+            (i + 1) % new_values.len()
+        } else {
+            i + 1
+        };
         for j in 0..new_values[i].len() {
             let j_up = (j as f32 * new_values[i - 1].len() as f32 / new_values[i].len() as f32)
                 as usize
                 % new_values[i - 1].len();
-            if i < new_values.len() - 1 {
-                let j_down = (j as f32 * new_values[i + 1].len() as f32
+            if i_down < new_values.len() {
+                let j_down = (j as f32 * new_values[i_down].len() as f32
                     / new_values[i].len() as f32) as usize
-                    % new_values[i + 1].len();
-                new_values[i][j] = (new_values[i - 1][j_up] + new_values[i + 1][j_down]) / 2.0;
+                    % new_values[i_down].len();
+                new_values[i][j] = (new_values[i - 1][j_up] + new_values[i_down][j_down]) / 2.0;
             } else {
                 new_values[i][j] = new_values[i - 1][j_up];
             }
@@ -89,6 +129,14 @@ pub fn resize_f32<S: MapShape>(pmap: &mut PartialMap<S, f32>, factor: f32) {
 }
 
 pub fn resize_i32<S: MapShape>(pmap: &mut PartialMap<S, i32>, factor: f32) {
+    if !S::LAT_LON_GRID {
+        let [circunference, height] = [
+            (factor * pmap.circunference as f32) as usize,
+            (factor * pmap.height as f32) as usize,
+        ];
+        resample_i32(pmap, circunference, height);
+        return;
+    }
     let mut new_values = S::new_vec(
         (factor * pmap.circunference as f32) as usize,
         (factor * pmap.height as f32) as usize,
@@ -113,15 +161,21 @@ pub fn resize_i32<S: MapShape>(pmap: &mut PartialMap<S, i32>, factor: f32) {
         if i % 2 == 0 {
             continue;
         }
+        // the last row is next to the first one when rows wrap around
+        let i_down = if S::WRAPS_ROWS {
+            (i + 1) % new_values.len()
+        } else {
+            i + 1
+        };
         for j in 0..new_values[i].len() {
             let j_up = (j as f32 * new_values[i - 1].len() as f32 / new_values[i].len() as f32)
                 as usize
                 % new_values[i - 1].len();
-            if i < new_values.len() - 1 {
-                let j_down = (j as f32 * new_values[i + 1].len() as f32
+            if i_down < new_values.len() {
+                let j_down = (j as f32 * new_values[i_down].len() as f32
                     / new_values[i].len() as f32) as usize
-                    % new_values[i + 1].len();
-                new_values[i][j] = (new_values[i - 1][j_up] + new_values[i + 1][j_down]) / 2;
+                    % new_values[i_down].len();
+                new_values[i][j] = (new_values[i - 1][j_up] + new_values[i_down][j_down]) / 2;
             } else {
                 new_values[i][j] = new_values[i - 1][j_up];
             }
@@ -132,10 +186,88 @@ pub fn resize_i32<S: MapShape>(pmap: &mut PartialMap<S, i32>, factor: f32) {
     pmap.values = new_values;
 }
 
+// Value of a layer between its pixels, interpolated from the four closest ones. `row` is
+// in pixels and `column` is the fraction of the row, since rows may differ in length.
+// Rows wrap around, as in resize_i32, and the last row is next to the first one on shapes
+// wrapping around them.
+pub fn interpolate<S: MapShape>(pmap: &PartialMap<S, i32>, row: f32, column: f32) -> f32 {
+    let row_value = |x: usize| {
+        let values = &pmap.values[x];
+        let y = column * values.len() as f32 - 0.5;
+        let y0 = y.floor();
+        let t = y - y0;
+        let y0 = (y0 as i64).rem_euclid(values.len() as i64) as usize;
+        let y1 = (y0 + 1) % values.len();
+        return values[y0] as f32 * (1.0 - t) + values[y1] as f32 * t;
+    };
+    if S::WRAPS_ROWS {
+        let x0 = (row.floor() as i64).rem_euclid(pmap.values.len() as i64) as usize;
+        let x1 = (x0 + 1) % pmap.values.len();
+        let t = row - row.floor();
+        return row_value(x0) * (1.0 - t) + row_value(x1) * t;
+    }
+    let x0 = (row.floor() as usize).min(pmap.values.len() - 1);
+    let x1 = (x0 + 1).min(pmap.values.len() - 1);
+    let t = row - x0 as f32;
+    return row_value(x0) * (1.0 - t) + row_value(x1) * t;
+}
+
+// Resamples a layer to an exact size, interpolating between pixels
+pub fn resample_i32<S: MapShape>(
+    pmap: &mut PartialMap<S, i32>,
+    circunference: usize,
+    height: usize,
+) {
+    if !S::LAT_LON_GRID {
+        resample_cells(pmap, circunference, height, |pmap, latitude, longitude| {
+            pmap.interpolate_at(latitude, longitude).round() as i32
+        });
+        return;
+    }
+    let mut new_values: Vec<Vec<i32>> = S::new_vec(circunference, height);
+    let n_rows = new_values.len();
+    for (i, new_row) in new_values.iter_mut().enumerate() {
+        let row = (i as f32 + 0.5) * pmap.values.len() as f32 / n_rows as f32 - 0.5;
+        let row = if S::WRAPS_ROWS { row } else { row.max(0.0) };
+        let row_len = new_row.len();
+        for (j, value) in new_row.iter_mut().enumerate() {
+            let column = (j as f32 + 0.5) / row_len as f32;
+            *value = interpolate(pmap, row, column).round() as i32;
+        }
+    }
+    pmap.height = height;
+    pmap.circunference = circunference;
+    pmap.values = new_values;
+}
+
+// Resamples a layer to an exact size, taking the closest pixel
+pub fn resample<S: MapShape, T: Clone + Default>(
+    pmap: &mut PartialMap<S, T>,
+    circunference: usize,
+    height: usize,
+) {
+    if !S::LAT_LON_GRID {
+        resample_cells(pmap, circunference, height, |pmap, latitude, longitude| {
+            pmap.get(latitude, longitude)
+        });
+        return;
+    }
+    let mut new_values = S::new_vec(circunference, height);
+    for i in 0..new_values.len() {
+        let i0 = (i * pmap.values.len() / new_values.len()).min(pmap.values.len() - 1);
+        let row = &pmap.values[i0];
+        let new_len = new_values[i].len();
+        for (j, value) in new_values[i].iter_mut().enumerate() {
+            *value = row[j * row.len() / new_len].clone();
+        }
+    }
+    pmap.height = height;
+    pmap.circunference = circunference;
+    pmap.values = new_values;
+}
+
 fn pseudo_random_float(seed: u32) -> f32 {
-    let mut hasher = DefaultHasher::new();
-    seed.hash(&mut hasher);
-    let hash = hasher.finish();
+    let hash = mix(seed as u64);
     let random_float: f32 = hash as f32 / u64::MAX as f32;
     return random_float;
 }
@@ -180,6 +312,42 @@ pub fn resize_rivers(rivers: &mut Vec<River>, factor: f32) {
     }
 }
 
+// Scales the positions of rivers by a factor for rows and another for columns
+fn scale_rivers(rivers: &mut Vec<River>, [row_factor, column_factor]: [f32; 2]) {
+    for river in rivers {
+        for point in river.iter_mut() {
+            point.position[0] = (point.position[0] as f32 * row_factor) as usize;
+            point.position[1] = (point.position[1] as f32 * column_factor) as usize;
+        }
+    }
+}
+
+// Latitudes and longitudes of the points of rivers, to find their positions once the height
+// is resized when its rows are not bands of latitude
+fn river_coords<S: MapShape>(rivers: &[River], height: &PartialMap<S, i32>) -> Vec<Vec<[f32; 2]>> {
+    return rivers
+        .iter()
+        .map(|river| {
+            river
+                .iter()
+                .map(|point| height.cell_center(point.position[0], point.position[1]))
+                .collect()
+        })
+        .collect();
+}
+
+fn place_rivers<S: MapShape>(
+    rivers: &mut [River],
+    coords: &[Vec<[f32; 2]>],
+    height: &PartialMap<S, i32>,
+) {
+    for (river, river_coords) in rivers.iter_mut().zip(coords) {
+        for (point, [latitude, longitude]) in river.iter_mut().zip(river_coords) {
+            point.position = height.convert_to_vec_coords(*latitude, *longitude);
+        }
+    }
+}
+
 fn smooth_plates<S: MapShape>(pmap: &mut PartialMap<S, usize>) {
     let mut new_values = vec![];
     for (i, row) in pmap.values.iter().enumerate() {
@@ -201,33 +369,121 @@ fn smooth_plates<S: MapShape>(pmap: &mut PartialMap<S, usize>) {
 }
 
 impl<S: MapShape> PipelineStep<S> for Resize {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        while (output_map.height.values.len() as f32)
-            < (input_map.height.values.len() as f32 * self.factor)
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        if !S::LAT_LON_GRID {
+            let coords = river_coords(&map.rivers, &map.height);
+            resize_i32(&mut map.height, self.factor);
+            place_rivers(&mut map.rivers, &coords, &map.height);
+        }
+        let input_rows = map.height.values.len();
+        while S::LAT_LON_GRID
+            && (map.height.values.len() as f32) < (input_rows as f32 * self.factor)
         {
-            let factor = (self.factor * (output_map.height.values.len() as f32)
-                / (input_map.height.values.len() as f32))
-                .min(2.0);
+            let factor =
+                (self.factor * (map.height.values.len() as f32) / (input_rows as f32)).min(2.0);
             if factor == 2.0 {
-                resize_i32(&mut output_map.height, factor);
+                resize_i32(&mut map.height, factor);
             } else {
-                resize(&mut output_map.height, factor);
+                resize(&mut map.height, factor);
             }
         }
 
-        resize(&mut output_map.tectonic_plates, self.factor);
-        smooth_plates(&mut output_map.tectonic_plates);
-        smooth_plates(&mut output_map.tectonic_plates);
-        output_map.mountain_chains = resize_chains(&output_map.mountain_chains);
-        output_map.andean_chains = resize_chains(&output_map.andean_chains);
-        output_map.hymalayan_chains = resize_chains(&output_map.hymalayan_chains);
-        output_map.trenches = resize_chains(&output_map.trenches);
-        // output_map.trenches =
-        resize_rivers(&mut output_map.rivers, self.factor);
-        return output_map;
+        resize(&mut map.tectonic_plates, self.factor);
+        smooth_plates(&mut map.tectonic_plates);
+        smooth_plates(&mut map.tectonic_plates);
+        map.mountain_chains = resize_chains(&map.mountain_chains);
+        map.andean_chains = resize_chains(&map.andean_chains);
+        map.hymalayan_chains = resize_chains(&map.hymalayan_chains);
+        map.trenches = resize_chains(&map.trenches);
+        // map.trenches =
+        if S::LAT_LON_GRID {
+            resize_rivers(&mut map.rivers, self.factor);
+        }
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![
+            LayerId::Height,
+            LayerId::TectonicPlates,
+            LayerId::Features,
+            LayerId::Rivers,
+        ];
+    }
+}
+
+// Resizes the map to an exact size, e.g. the resolution asked for in the configuration.
+// Doubling the size gives the same map as Resize with a factor of 2.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResizeTo {
+    pub circunference: usize,
+    pub height: usize,
+}
+
+impl<S: MapShape> PipelineStep<S> for ResizeTo {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let [circunference, height] = [map.height.circunference, map.height.height];
+        if self.circunference == 2 * circunference && self.height == 2 * height {
+            return Resize { factor: 2.0 }.apply(map);
+        }
+        if [self.circunference, self.height] == [circunference, height] {
+            return Ok(());
+        }
+        if self.circunference == 0 || self.height == 0 {
+            return Err(MapError::InvalidData(format!(
+                "cannot resize to {}x{}",
+                self.circunference, self.height
+            )));
+        }
+        let factors = [
+            self.height as f32 / height as f32,
+            self.circunference as f32 / circunference as f32,
+        ];
+        let coords = river_coords(&map.rivers, &map.height);
+        resample_i32(&mut map.height, self.circunference, self.height);
+
+        let plates = &map.tectonic_plates;
+        if !plates.values.is_empty() {
+            let [plates_circunference, plates_height] = [
+                (plates.circunference as f32 * factors[1]).round() as usize,
+                (plates.height as f32 * factors[0]).round() as usize,
+            ];
+            resample(
+                &mut map.tectonic_plates,
+                plates_circunference.max(1),
+                plates_height.max(1),
+            );
+        }
+        if factors[0] > 1.0 {
+            smooth_plates(&mut map.tectonic_plates);
+            smooth_plates(&mut map.tectonic_plates);
+            map.mountain_chains = resize_chains(&map.mountain_chains);
+            map.andean_chains = resize_chains(&map.andean_chains);
+            map.hymalayan_chains = resize_chains(&map.hymalayan_chains);
+            map.trenches = resize_chains(&map.trenches);
+        }
+        if S::LAT_LON_GRID {
+            scale_rivers(&mut map.rivers, factors);
+        } else {
+            place_rivers(&mut map.rivers, &coords, &map.height);
+        }
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![
+            LayerId::Height,
+            LayerId::TectonicPlates,
+            LayerId::Features,
+            LayerId::Rivers,
+        ];
     }
 }
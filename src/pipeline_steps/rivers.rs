@@ -1,8 +1,9 @@
-use std::sync::Arc;
-
 use serde::{Deserialize, Serialize};
 
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 
@@ -259,49 +260,53 @@ fn get_start_points_from_precipitation<S: MapShape>(input_map: &CompleteMap<S>)
             }
         }
     }
-    dbg!("Number of river starting points: ", points.len());
     points
 }
 
 impl<S: MapShape> PipelineStep<S> for CreateRivers {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.fresh_water =
-            PartialMap::new(output_map.height.circunference, output_map.height.height);
-        if output_map.fresh_water.values[0].len() != input_map.height.values[0].len() {
-            for i in 0..input_map.height.values.len() {
-                output_map.fresh_water.values[i] = vec![0; input_map.height.values[i].len()];
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        map.require_annual_precipitation()?;
+        map.fresh_water = PartialMap::new(map.height.circunference, map.height.height);
+        if map.fresh_water.values[0].len() != map.height.values[0].len() {
+            for i in 0..map.height.values.len() {
+                map.fresh_water.values[i] = vec![0; map.height.values[i].len()];
             }
         }
 
-        let start_points: Vec<[usize; 2]> = get_start_points_from_precipitation(input_map);
+        let start_points: Vec<[usize; 2]> = get_start_points_from_precipitation(map);
         'river_creation_loop: for start_point in start_points {
-            if output_map.height.values[start_point[0]][start_point[1]] <= 0 {
+            if map.height.values[start_point[0]][start_point[1]] <= 0 {
                 continue 'river_creation_loop;
             }
-            for river in &output_map.rivers {
-                let neighbors = output_map
-                    .height
-                    .get_pixel_neighbours_coords(start_point, 5);
+            for river in &map.rivers {
+                let neighbors = map.height.get_pixel_neighbours_coords(start_point, 5);
                 for neighbor in neighbors.iter().flatten() {
                     if contains_point(&river, neighbor.clone()) {
                         continue 'river_creation_loop;
                     }
                 }
             }
-            let river = make_river(&mut output_map, start_point);
+            let river = make_river(map, start_point);
             if river.len() < 3 {
                 continue;
             }
-            let shortcutted_river = shortcut(input_map, &river);
-            update_fresh_water(&mut output_map, &shortcutted_river);
-            erosion(&mut output_map, &shortcutted_river);
-            output_map.rivers.push(shortcutted_river);
+            let shortcutted_river = shortcut(map, &river);
+            update_fresh_water(map, &shortcutted_river);
+            erosion(map, &shortcutted_river);
+            map.rivers.push(shortcutted_river);
         }
-        dbg!("Number of rivers: ", output_map.rivers.len());
-        return output_map;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![
+            LayerId::Height,
+            LayerId::Temperature,
+            LayerId::AnnualPrecipitation,
+        ];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::Rivers, LayerId::FreshWater];
     }
 }
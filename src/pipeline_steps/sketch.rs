@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use image::Rgba;
+
+use crate::{
+    complete_map::CompleteMap,
+    error::{MapError, MapResult},
+    map_file::layers::LayerId,
+    map_view::color_scheme::CategoryColorScheme,
+    partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+
+use super::{layer_step::ElementStep, pipeline_step::PipelineStep, smooth::Smooth};
+
+// Name of the custom layer LoadSketch writes
+pub const SKETCH_LAYER: &str = "sketch";
+
+// What a painted sketch asks for at each pixel, stored as the categories of the sketch layer.
+// Sketches are painted with blue for sea, red or brown for mountains, yellow for lowlands and
+// any other color (green, white, ...) for land.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SketchCategory {
+    Sea = 0,
+    Land = 1,
+    Lowland = 2,
+    Mountains = 3,
+}
+
+impl SketchCategory {
+    const ALL: [SketchCategory; 4] = [
+        SketchCategory::Sea,
+        SketchCategory::Land,
+        SketchCategory::Lowland,
+        SketchCategory::Mountains,
+    ];
+
+    fn from_color(Rgba([r, g, b, _]): Rgba<u8>) -> Self {
+        if b > r && b > g {
+            return SketchCategory::Sea;
+        }
+        if r > g.saturating_add(40) {
+            return SketchCategory::Mountains;
+        }
+        if r > 150 && g > 150 && b < 120 {
+            return SketchCategory::Lowland;
+        }
+        return SketchCategory::Land;
+    }
+
+    fn from_value(value: usize) -> Self {
+        return Self::ALL[value.min(Self::ALL.len() - 1)];
+    }
+
+    fn color(self) -> Rgba<u8> {
+        match self {
+            SketchCategory::Sea => Rgba([40, 80, 200, 255]),
+            SketchCategory::Land => Rgba([60, 160, 60, 255]),
+            SketchCategory::Lowland => Rgba([220, 210, 90, 255]),
+            SketchCategory::Mountains => Rgba([150, 70, 40, 255]),
+        }
+    }
+
+    // Height added, relative to the intensity of SketchHeight. Lowlands are raised as land,
+    // so that they stay above the sea, and flattened later by SketchLowlands.
+    fn bias(self) -> f32 {
+        match self {
+            SketchCategory::Sea => -1.0,
+            SketchCategory::Land | SketchCategory::Lowland => 0.5,
+            SketchCategory::Mountains => 1.5,
+        }
+    }
+}
+
+pub fn load_sketch<S: MapShape>(filename: &str) -> MapResult<PartialMap<S, usize>> {
+    let imgbuffer = image::open(filename)?.to_rgba8();
+    let rows = imgbuffer
+        .rows()
+        .map(|row| {
+            row.map(|color| SketchCategory::from_color(*color) as usize)
+                .collect()
+        })
+        .collect();
+    return Ok(PartialMap::from_rows(rows));
+}
+
+// Percentage of the surface the sketch paints as sea. The rows of a sketch all have the width
+// of the image, so on spheres each pixel counts by the cosine of its latitude.
+pub fn sketch_water_percentage<S: MapShape>(sketch: &PartialMap<S, usize>) -> f32 {
+    let mut sea = 0.0;
+    let mut total = 0.0;
+    for (x, row) in sketch.values.iter().enumerate() {
+        for (y, value) in row.iter().enumerate() {
+            let weight = if S::SPHERE && S::LAT_LON_GRID {
+                let [latitude, _] = sketch.cell_center(x, y);
+                latitude.to_radians().cos()
+            } else {
+                1.0
+            };
+            if SketchCategory::from_value(*value) == SketchCategory::Sea {
+                sea += weight;
+            }
+            total += weight;
+        }
+    }
+    if total == 0.0 {
+        return 0.0;
+    }
+    return 100.0 * sea / total;
+}
+
+// Whether the sketch has sea at a pixel of another layer
+pub fn is_sketched_sea<S: MapShape, T: Clone>(
+    sketch: &PartialMap<S, usize>,
+    pmap: &PartialMap<S, T>,
+    [x, y]: [usize; 2],
+) -> bool {
+    let [lat, lon] = pmap.convert_coords(x, y);
+    return SketchCategory::from_value(sketch.get(lat, lon)) == SketchCategory::Sea;
+}
+
+fn sketch_layer<'a, S: MapShape>(
+    map: &'a CompleteMap<S>,
+    layer: &str,
+) -> MapResult<&'a PartialMap<S, usize>> {
+    return map
+        .custom_pmaps
+        .get(layer)
+        .ok_or_else(|| MapError::MissingLayer(format!("custom/{}", layer)));
+}
+
+// Loads a painted sketch as a custom layer, "sketch" by default
+#[derive(Debug)]
+pub struct LoadSketch {
+    pub layer: String,
+    filename: String,
+}
+
+impl LoadSketch {
+    pub fn new(filename: String) -> Self {
+        Self {
+            layer: SKETCH_LAYER.to_string(),
+            filename,
+        }
+    }
+}
+
+impl<S: MapShape> PipelineStep<S> for LoadSketch {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let color_map: HashMap<usize, Rgba<u8>> = SketchCategory::ALL
+            .iter()
+            .map(|category| (*category as usize, category.color()))
+            .collect();
+        map.custom_pmaps
+            .insert(self.layer.clone(), load_sketch(&self.filename)?);
+        map.custom_color_schemes
+            .insert(self.layer.clone(), CategoryColorScheme { color_map });
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::CustomLayers];
+    }
+}
+
+// Raises the land, mountains and lowlands of a sketch and lowers its sea, blurred over
+// `blur` pixels so that the noise of the other steps draws the coasts.
+#[derive(Debug)]
+pub struct SketchHeight {
+    pub layer: String,
+    pub intensity: f32,
+    pub blur: usize,
+}
+
+impl SketchHeight {
+    pub fn new(intensity: f32) -> Self {
+        Self {
+            layer: SKETCH_LAYER.to_string(),
+            intensity,
+            blur: 2,
+        }
+    }
+}
+
+// A value for every category of a sketch at the resolution of the height, blurred over
+// `blur` pixels
+fn blurred_sketch<S: MapShape>(
+    map: &CompleteMap<S>,
+    layer: &str,
+    blur: usize,
+    value: impl Fn(SketchCategory) -> f32,
+) -> MapResult<Vec<Vec<f32>>> {
+    let sketch = sketch_layer(map, layer)?;
+    let mut values = PartialMap::<S, f32>::new(map.height.circunference, map.height.height);
+    for x in 0..values.values.len() {
+        for y in 0..values.values[x].len() {
+            let [lat, lon] = values.convert_coords(x, y);
+            values.values[x][y] = value(SketchCategory::from_value(sketch.get(lat, lon)));
+        }
+    }
+    let smooth = Smooth {
+        pixel_distance: blur,
+    };
+    return Ok((0..values.values.len())
+        .map(|x| {
+            (0..values.values[x].len())
+                .map(|y| smooth.process_element(x, y, &values, map))
+                .collect()
+        })
+        .collect());
+}
+
+impl<S: MapShape> PipelineStep<S> for SketchHeight {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let blurred = blurred_sketch(map, &self.layer, self.blur, SketchCategory::bias)?;
+        for (x, row) in blurred.iter().enumerate() {
+            for (y, value) in row.iter().enumerate() {
+                map.height.values[x][y] += (value * self.intensity) as i32;
+            }
+        }
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::CustomLayers];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+}
+
+// Scales the land painted as lowlands by `factor`, fading out over `blur` pixels around them
+#[derive(Debug)]
+pub struct SketchLowlands {
+    pub layer: String,
+    pub factor: f32,
+    pub blur: usize,
+}
+
+impl SketchLowlands {
+    pub fn new(factor: f32) -> Self {
+        Self {
+            layer: SKETCH_LAYER.to_string(),
+            factor,
+            blur: 2,
+        }
+    }
+}
+
+impl<S: MapShape> PipelineStep<S> for SketchLowlands {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let weights = blurred_sketch(map, &self.layer, self.blur, |category| {
+            (category == SketchCategory::Lowland) as u8 as f32
+        })?;
+        for (x, row) in weights.iter().enumerate() {
+            for (y, weight) in row.iter().enumerate() {
+                let height = &mut map.height.values[x][y];
+                if *height > 0 {
+                    let factor = 1.0 - weight * (1.0 - self.factor);
+                    *height = ((*height as f32 * factor) as i32).max(1);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::CustomLayers];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::{flat::Flat, globe::Globe};
+
+    // Sea in the southernmost of four rows
+    fn polar_sea<S: MapShape>() -> PartialMap<S, usize> {
+        let mut rows = vec![vec![SketchCategory::Land as usize; 8]; 4];
+        rows[0] = vec![SketchCategory::Sea as usize; 8];
+        return PartialMap::from_rows(rows);
+    }
+
+    #[test]
+    fn water_percentage_counts_the_area_of_the_pixels() {
+        assert_eq!(sketch_water_percentage(&polar_sea::<Flat>()), 25.0);
+        let polar = 67.5_f32.to_radians().cos();
+        let equatorial = 22.5_f32.to_radians().cos();
+        let expected = 100.0 * polar / (2.0 * (polar + equatorial));
+        assert!((sketch_water_percentage(&polar_sea::<Globe>()) - expected).abs() < 1e-3);
+    }
+}
@@ -1,8 +1,6 @@
-use std::sync::Arc;
-
 use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
 
-use super::pipeline_step::PipelineStep;
+use super::layer_step::{height_step, ElementStep, NumericValue};
 
 #[derive(Debug)]
 pub struct Smooth {
@@ -15,21 +13,16 @@ impl Smooth {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for Smooth {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let neighbors = input_map
-            .height
-            .get_pixel_neighbours([x, y], self.pixel_distance);
-        let mut sum = 0;
-        let mut len = 0;
-        for i in 0..neighbors.len() {
-            for j in 0..neighbors[i].len() {
-                sum += neighbors[i][j];
-                len += 1;
-            }
-        }
-        let mean = sum / len;
-        return mean;
+impl<S: MapShape, T: NumericValue> ElementStep<S, T> for Smooth {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, T>,
+        _map: &CompleteMap<S>,
+    ) -> T {
+        let neighbors = layer.get_pixel_neighbours([x, y], self.pixel_distance);
+        return T::mean(&neighbors);
     }
 }
 
@@ -44,27 +37,24 @@ impl SmoothOcean {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for SmoothOcean {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        if input_map.height.values[x][y] > 0 {
-            return input_map.height.values[x][y];
+impl<S: MapShape> ElementStep<S, i32> for SmoothOcean {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, i32>,
+        _map: &CompleteMap<S>,
+    ) -> i32 {
+        if layer.values[x][y] > 0 {
+            return layer.values[x][y];
         }
-        let neighbors = input_map
-            .height
-            .get_pixel_neighbours([x, y], self.pixel_distance);
-        let mut sum = 0;
-        let mut len = 0;
-        for i in 0..neighbors.len() {
-            for j in 0..neighbors[i].len() {
-                sum += neighbors[i][j];
-                len += 1;
-            }
-        }
-        let mean = sum / len;
-        return mean.min(0);
+        let neighbors = layer.get_pixel_neighbours([x, y], self.pixel_distance);
+        return i32::mean(&neighbors).min(0);
     }
 }
 
+height_step!(Smooth, SmoothOcean);
+
 pub fn smooth_pmap<S: MapShape>(
     input_map: &PartialMap<S, i32>,
     pixel_distance: usize,
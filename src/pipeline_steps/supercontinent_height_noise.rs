@@ -1,8 +1,14 @@
-use std::{fmt, sync::Arc};
+use std::fmt;
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    layer_step::{height_step, ElementStep},
+    util::CustomNoise,
+};
 
 pub struct SupercontinentHeightNoise {
     pub noise: CustomNoise,
@@ -26,11 +32,17 @@ impl fmt::Debug for SupercontinentHeightNoise {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for SupercontinentHeightNoise {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let [latitude, longitude] = input_map.height.convert_coords(x, y);
-        let plate_number = input_map.tectonic_plates.get(latitude, longitude);
-        let plate_center = input_map.tectonic_plates_centers[plate_number];
+impl<S: MapShape> ElementStep<S, i32> for SupercontinentHeightNoise {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, i32>,
+        map: &CompleteMap<S>,
+    ) -> i32 {
+        let [latitude, longitude] = layer.convert_coords(x, y);
+        let plate_number = map.tectonic_plates.get(latitude, longitude);
+        let plate_center = map.tectonic_plates_centers[plate_number];
         let mut noise_value = self.noise.get_spheric_f32::<S, i32>(latitude, longitude);
         if plate_center[0].powf(2.0) + plate_center[1].powf(2.0)
             > (45.0 as f32).powf(2.0) + (90.0 as f32).powf(2.0)
@@ -39,6 +51,12 @@ impl<S: MapShape> PipelineStep<S> for SupercontinentHeightNoise {
         } else {
             noise_value = (2.0 * noise_value + noise_value.abs()) / 3.0;
         }
-        return input_map.height.values[x][y] + noise_value as i32;
+        return layer.values[x][y] + noise_value as i32;
+    }
+
+    fn map_reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::TectonicPlates];
     }
 }
+
+height_step!(SupercontinentHeightNoise);
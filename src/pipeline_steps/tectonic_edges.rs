@@ -1,6 +1,7 @@
-use std::sync::Arc;
-
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId,
+    shapes::map_shape::MapShape,
+};
 
 use super::pipeline_step::PipelineStep;
 
@@ -13,6 +14,15 @@ impl DefineTecEdges {
     }
 }
 
+// The lists of points written by the step, taken out of the map while it reads the plates
+struct Chains {
+    tectonic_edges: Vec<[f32; 2]>,
+    mountain_chains: Vec<[f32; 2]>,
+    andean_chains: Vec<[f32; 2]>,
+    hymalayan_chains: Vec<[f32; 2]>,
+    trenches: Vec<[f32; 2]>,
+}
+
 fn scalar_prod(v1: &[f32; 2], v2: &[f32; 2]) -> f32 {
     return v1[0] * v2[0] + v1[1] * v2[1];
 }
@@ -23,7 +33,7 @@ impl DefineTecEdges {
         x: usize,
         y: usize,
         input_map: &CompleteMap<S>,
-        output_map: &mut CompleteMap<S>,
+        chains: &mut Chains,
     ) {
         let plate1 = input_map.tectonic_plates.values[x][y];
         for (i, row) in input_map
@@ -48,7 +58,7 @@ impl DefineTecEdges {
                     let collision = scalar_prod(&direction1, &pixel_direction_normalized)
                         - scalar_prod(&pixel_direction_normalized, &direction2);
                     let [latitude, longitude] = input_map.tectonic_plates.convert_coords(x, y);
-                    output_map.tectonic_edges.push([latitude, longitude]);
+                    chains.tectonic_edges.push([latitude, longitude]);
                     if collision > 0.5 {
                         let height1 = input_map.height.values[x][y];
                         let height2 = input_map.height.values[*x2][*y2];
@@ -63,12 +73,12 @@ impl DefineTecEdges {
                                     longitude - 2.0 * &pixel_direction_normalized[1],
                                 );
                                 if height3 > 0 && height4 > 0 {
-                                    output_map.hymalayan_chains.push([
+                                    chains.hymalayan_chains.push([
                                         latitude - pixel_direction_normalized[0],
                                         longitude - pixel_direction_normalized[1],
                                     ]);
                                 } else {
-                                    output_map.mountain_chains.push([
+                                    chains.mountain_chains.push([
                                         latitude - pixel_direction_normalized[0],
                                         longitude - pixel_direction_normalized[1],
                                     ]);
@@ -84,29 +94,29 @@ impl DefineTecEdges {
                                     longitude - 2.0 * &pixel_direction_normalized[1],
                                 );
                                 if height3 > 0 && height4 > 0 {
-                                    output_map.andean_chains.push([
+                                    chains.andean_chains.push([
                                         latitude - pixel_direction_normalized[0],
                                         longitude - pixel_direction_normalized[1],
                                     ]);
                                 } else {
-                                    output_map.mountain_chains.push([
+                                    chains.mountain_chains.push([
                                         latitude - pixel_direction_normalized[0],
                                         longitude - pixel_direction_normalized[1],
                                     ]);
                                 }
                             } else {
-                                output_map.mountain_chains.push([
+                                chains.mountain_chains.push([
                                     latitude - pixel_direction_normalized[0],
                                     longitude - pixel_direction_normalized[1],
                                 ]);
                             }
                         } else if height1 <= -200 && height2 <= -200 {
-                            output_map.mountain_chains.push([
+                            chains.mountain_chains.push([
                                 latitude - pixel_direction_normalized[0],
                                 longitude - pixel_direction_normalized[1],
                             ]);
                         } else if height1 <= -200 && height2 > -200 {
-                            output_map.trenches.push([
+                            chains.trenches.push([
                                 latitude - pixel_direction_normalized[0],
                                 longitude - pixel_direction_normalized[1],
                             ]);
@@ -119,19 +129,34 @@ impl DefineTecEdges {
 }
 
 impl<S: MapShape> PipelineStep<S> for DefineTecEdges {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let mut chains = Chains {
+            tectonic_edges: std::mem::take(&mut map.tectonic_edges),
+            mountain_chains: std::mem::take(&mut map.mountain_chains),
+            andean_chains: std::mem::take(&mut map.andean_chains),
+            hymalayan_chains: std::mem::take(&mut map.hymalayan_chains),
+            trenches: std::mem::take(&mut map.trenches),
+        };
 
-        for x in 0..output_map.tectonic_plates.values.len() {
-            for y in 0..output_map.tectonic_plates.values[x].len() {
-                self.process_edge_element(x, y, input_map, &mut output_map);
+        for x in 0..map.tectonic_plates.values.len() {
+            for y in 0..map.tectonic_plates.values[x].len() {
+                self.process_edge_element(x, y, map, &mut chains);
             }
         }
 
-        return output_map;
+        map.tectonic_edges = chains.tectonic_edges;
+        map.mountain_chains = chains.mountain_chains;
+        map.andean_chains = chains.andean_chains;
+        map.hymalayan_chains = chains.hymalayan_chains;
+        map.trenches = chains.trenches;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::TectonicPlates];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Features];
     }
 }
@@ -1,12 +1,15 @@
-use std::{f32::consts::PI, sync::Arc};
+use std::f32::consts::PI;
 
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
-
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
 use super::{
+    layer_step::apply_to_layer,
     pipeline_step::PipelineStep,
     resize::{resize, resize_f32},
+    smooth::Smooth,
 };
 
 #[derive(Debug)]
@@ -124,97 +127,72 @@ impl SmoothTemperature {
     }
 }
 
-impl SmoothTemperature {
-    fn process_temperature_element<S: MapShape>(
-        &self,
-        x: usize,
-        y: usize,
-        input_map: Arc<&CompleteMap<S>>,
-        time_of_year: usize,
-    ) -> f32 {
-        let neighbors =
-            input_map.temperature[time_of_year].get_pixel_neighbours([x, y], self.pixel_distance);
-        let mut sum = 0.0;
-        let mut len = 0;
-        for i in 0..neighbors.len() {
-            for j in 0..neighbors[i].len() {
-                sum += neighbors[i][j];
-                len += 1;
-            }
+impl<S: MapShape> PipelineStep<S> for SmoothTemperature {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let smooth = Smooth {
+            pixel_distance: self.pixel_distance,
+        };
+        for i in 0..map.temperature.len() {
+            apply_to_layer::<S, f32, _>(&smooth, &format!("temperature/{}", i), map)?;
         }
-        let mean = sum / len as f32;
-        return mean;
+        return Ok(());
     }
-}
 
-impl<S: MapShape> PipelineStep<S> for SmoothTemperature {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Temperature];
     }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-
-        for i in 0..input_map.temperature.len() {
-            // process elements in parallel with rayon
-            output_map.temperature[i]
-                .values
-                .par_iter_mut()
-                .enumerate()
-                .for_each(|(x, inner_vec)| {
-                    inner_vec.iter_mut().enumerate().for_each(|(y, num)| {
-                        *num = self.process_temperature_element(x, y, Arc::new(input_map), i);
-                    });
-                });
-        }
-        return output_map;
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Temperature];
     }
 }
 
 impl<S: MapShape> PipelineStep<S> for TemperatureFromContinentality {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.temperature = vec![];
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let mut temperature = vec![];
         for t in 0..=self.year_divisions / 2 {
-            output_map.temperature.push(self.define_month_temperature(
+            temperature.push(self.define_month_temperature(
                 t as u32,
                 self.year_divisions as u32,
-                input_map,
+                map,
             ));
         }
+        map.temperature = temperature;
         let mut smooth_step = SmoothTemperature::new();
         smooth_step.pixel_distance = 1;
-        output_map = smooth_step.apply(&output_map);
-        while output_map.temperature[0].values.len() < output_map.height.values.len() {
-            let factor = (output_map.height.values.len() as f32
-                / output_map.temperature[0].values.len() as f32)
-                .min(2.0);
+        smooth_step.apply(map)?;
+        while map.temperature[0].values.len() < map.height.values.len() {
+            let factor =
+                (map.height.values.len() as f32 / map.temperature[0].values.len() as f32).min(2.0);
             if factor == 2.0 {
-                for t in 0..output_map.temperature.len() {
-                    resize_f32(&mut output_map.temperature[t as usize], factor);
+                for t in 0..map.temperature.len() {
+                    resize_f32(&mut map.temperature[t as usize], factor);
                 }
             } else {
-                for t in 0..output_map.temperature.len() {
-                    resize(&mut output_map.temperature[t as usize], factor);
+                for t in 0..map.temperature.len() {
+                    resize(&mut map.temperature[t as usize], factor);
                 }
-                output_map = SmoothTemperature::new().apply(&output_map);
+                SmoothTemperature::new().apply(map)?;
             }
         }
-        for t in 0..output_map.temperature.len() {
-            output_map.temperature[t as usize] =
-                self.decrease_mountain_temperature(t as u32, &output_map);
+        for t in 0..map.temperature.len() {
+            map.temperature[t as usize] = self.decrease_mountain_temperature(t as u32, map);
         }
 
         // Remaining months mirror the previous ones
         for t in self.year_divisions / 2 + 1..self.year_divisions {
-            let temperature_map =
-                output_map.temperature[(self.year_divisions - t) as usize].clone();
-            output_map.temperature.push(temperature_map);
+            let temperature_map = map.temperature[(self.year_divisions - t) as usize].clone();
+            map.temperature.push(temperature_map);
         }
 
-        return output_map;
+        return Ok(());
+    }
+
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![LayerId::Height, LayerId::Continentality];
+    }
+
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::Temperature];
     }
 }
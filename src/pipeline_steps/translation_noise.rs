@@ -1,9 +1,11 @@
 use std::fmt;
-use std::sync::Arc;
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
 
-use super::{pipeline_step::PipelineStep, util::CustomNoise};
+use super::{
+    layer_step::{height_step, ElementStep, LayerValue},
+    util::CustomNoise,
+};
 
 pub struct TranslationNoise {
     noises: [CustomNoise; 8],
@@ -32,9 +34,15 @@ impl fmt::Debug for TranslationNoise {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for TranslationNoise {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        let [latitude, longitude] = input_map.height.convert_coords(x, y);
+impl<S: MapShape, T: LayerValue> ElementStep<S, T> for TranslationNoise {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, T>,
+        _map: &CompleteMap<S>,
+    ) -> T {
+        let [latitude, longitude] = layer.convert_coords(x, y);
 
         let noise_lat = self.noises[0].get_spheric_f32::<S, i32>(latitude, longitude)
             + self.noises[2].get_spheric_f32::<S, i32>(latitude, longitude)
@@ -45,8 +53,8 @@ impl<S: MapShape> PipelineStep<S> for TranslationNoise {
             + self.noises[5].get_spheric_f32::<S, i32>(latitude, longitude)
             + self.noises[7].get_spheric_f32::<S, i32>(latitude, longitude);
 
-        return input_map
-            .height
-            .get(latitude + noise_lat, longitude + noise_lon);
+        return layer.get(latitude + noise_lat, longitude + noise_lon);
     }
 }
+
+height_step!(TranslationNoise);
@@ -12,7 +12,7 @@ pub fn percentile_2d_vector(vector: &Vec<Vec<i32>>, percentile: f32) -> Option<i
     flattened_vector.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
     // Check if the percentile is within the valid range (0.0 to 100.0)
-    if percentile < 0.0 || percentile > 100.0 {
+    if flattened_vector.is_empty() || !(0.0..=100.0).contains(&percentile) {
         return None;
     }
 
@@ -1,7 +1,8 @@
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
-
-use crate::{complete_map::CompleteMap, partial_map::PartialMap, shapes::map_shape::MapShape};
-use std::{fmt, sync::Arc};
+use crate::{
+    complete_map::CompleteMap, error::MapError, map_file::layers::LayerId, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
+use std::fmt;
 
 use super::{
     climate::Climate, pipeline_step::PipelineStep, smooth::smooth_pmap, util::CustomNoise,
@@ -29,8 +30,13 @@ impl Vegetation {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for Vegetation {
-    fn process_element(&self, x: usize, y: usize, complete_map: Arc<&CompleteMap<S>>) -> i32 {
+impl Vegetation {
+    fn process_element<S: MapShape>(
+        &self,
+        x: usize,
+        y: usize,
+        complete_map: &CompleteMap<S>,
+    ) -> i32 {
         let [latitude, longitude] = complete_map.vegetation_density.convert_coords(x, y);
         let noise_value = self.noise.get_spheric::<S, i32>(latitude, longitude)
             + self.noise2.get_spheric::<S, i32>(latitude, longitude)
@@ -91,25 +97,32 @@ impl<S: MapShape> PipelineStep<S> for Vegetation {
 
         return vegetation + noise_value;
     }
+}
+
+impl<S: MapShape> PipelineStep<S> for Vegetation {
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        map.require_climate()?;
+        map.require_annual_precipitation()?;
+        map.vegetation_density = PartialMap::new(map.height.circunference, map.height.height);
+        let mut vegetation_density = map.vegetation_density.clone();
+        vegetation_density
+            .iterate_operator(map, |x, y, arc_map| self.process_element(x, y, &arc_map));
+        map.vegetation_density = smooth_pmap(&vegetation_density, 2);
+
+        return Ok(());
+    }
 
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let mut output_map = input_map.clone();
-        output_map.vegetation_density =
-            PartialMap::new(input_map.height.circunference, input_map.height.height);
-        let in2 = output_map.clone();
-        // process elements in parallel with rayon
-        output_map
-            .vegetation_density
-            .values
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(x, inner_vec)| {
-                inner_vec.iter_mut().enumerate().for_each(|(y, num)| {
-                    *num = self.process_element(x, y, Arc::new(&in2));
-                });
-            });
-        output_map.vegetation_density = smooth_pmap(&output_map.vegetation_density, 2);
+    fn reads(&self) -> Vec<LayerId> {
+        return vec![
+            LayerId::Height,
+            LayerId::Temperature,
+            LayerId::Precipitation,
+            LayerId::AnnualPrecipitation,
+            LayerId::Climate,
+        ];
+    }
 
-        return output_map;
+    fn writes(&self) -> Vec<LayerId> {
+        return vec![LayerId::VegetationDensity];
     }
 }
@@ -1,10 +1,15 @@
-use std::sync::Arc;
-
 use serde::{Deserialize, Serialize};
 
-use crate::{complete_map::CompleteMap, shapes::map_shape::MapShape};
+use crate::{
+    complete_map::CompleteMap, error::MapError, partial_map::PartialMap,
+    shapes::map_shape::MapShape,
+};
 
-use super::{pipeline_step::PipelineStep, util::percentile_2d_vector};
+use super::{
+    layer_step::{height_step, ElementStep, NumericValue},
+    pipeline_step::PipelineStep,
+    util::percentile_2d_vector,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WaterLevel {
@@ -18,13 +23,14 @@ impl WaterLevel {
 }
 
 impl<S: MapShape> PipelineStep<S> for WaterLevel {
-    fn process_element(&self, _x: usize, _y: usize, _input_map: Arc<&CompleteMap<S>>) -> i32 {
-        todo!()
-    }
-    fn apply(&self, input_map: &CompleteMap<S>) -> CompleteMap<S> {
-        let sub_value = percentile_2d_vector(&input_map.height.values, self.percentage).unwrap();
-        let output_map = AddHeight::new(-sub_value).apply(input_map);
-        return output_map;
+    fn apply(&self, map: &mut CompleteMap<S>) -> Result<(), MapError> {
+        let Some(sub_value) = percentile_2d_vector(&map.height.values, self.percentage) else {
+            return Err(MapError::InvalidData(format!(
+                "water percentage {} is not between 0 and 100",
+                self.percentage
+            )));
+        };
+        return AddHeight::new(-sub_value).apply(map);
     }
 }
 
@@ -39,8 +45,16 @@ impl AddHeight {
     }
 }
 
-impl<S: MapShape> PipelineStep<S> for AddHeight {
-    fn process_element(&self, x: usize, y: usize, input_map: Arc<&CompleteMap<S>>) -> i32 {
-        return input_map.height.values[x][y] + self.value;
+impl<S: MapShape, T: NumericValue> ElementStep<S, T> for AddHeight {
+    fn process_element(
+        &self,
+        x: usize,
+        y: usize,
+        layer: &PartialMap<S, T>,
+        _map: &CompleteMap<S>,
+    ) -> T {
+        return layer.values[x][y].offset(self.value as f32);
     }
 }
+
+height_step!(AddHeight);
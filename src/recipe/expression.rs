@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+// Small arithmetic language used for the numeric parameters of recipe files,
+// e.g. "seed + 1000 + k" or "if(supercontinent, width_pixels / 4, 250)".
+// Booleans are represented as 1.0 (true) and 0.0 (false).
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+const OPERATORS: [&str; 15] = [
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "^", "<", ">", "!", "%",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    'outer: while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number '{}' in '{}'", text, expression))?;
+            tokens.push(Token::Number(value));
+            continue;
+        }
+        if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+            continue;
+        }
+        match c {
+            '(' => tokens.push(Token::LeftParen),
+            ')' => tokens.push(Token::RightParen),
+            ',' => tokens.push(Token::Comma),
+            _ => {
+                for op in OPERATORS {
+                    let len = op.len();
+                    if i + len <= chars.len() && chars[i..i + len].iter().copied().eq(op.chars()) {
+                        tokens.push(Token::Operator(op));
+                        i += len;
+                        continue 'outer;
+                    }
+                }
+                return Err(format!("unexpected character '{}' in '{}'", c, expression));
+            }
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    variables: &'a HashMap<String, f64>,
+    expression: &'a str,
+}
+
+fn binding_power(op: &str) -> u8 {
+    match op {
+        "||" => 1,
+        "&&" => 2,
+        "==" | "!=" => 3,
+        "<" | "<=" | ">" | ">=" => 4,
+        "+" | "-" => 5,
+        "*" | "/" | "%" => 6,
+        "^" => 7,
+        _ => 0,
+    }
+}
+
+fn as_bool(value: f64) -> bool {
+    value != 0.0
+}
+
+fn from_bool(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("{} in '{}'", message, self.expression)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(self.error(&format!("expected {:?}", expected))),
+        }
+    }
+
+    fn parse_expression(&mut self, min_power: u8) -> Result<f64, String> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(Token::Operator(op)) = self.peek() {
+            let op = *op;
+            let power = binding_power(op);
+            if power == 0 || power < min_power {
+                break;
+            }
+            self.position += 1;
+            // "^" is right associative, everything else is left associative
+            let next_power = if op == "^" { power } else { power + 1 };
+            let rhs = self.parse_expression(next_power)?;
+            lhs = match op {
+                "+" => lhs + rhs,
+                "-" => lhs - rhs,
+                "*" => lhs * rhs,
+                "/" => lhs / rhs,
+                "%" => lhs.rem_euclid(rhs),
+                "^" => lhs.powf(rhs),
+                "<" => from_bool(lhs < rhs),
+                "<=" => from_bool(lhs <= rhs),
+                ">" => from_bool(lhs > rhs),
+                ">=" => from_bool(lhs >= rhs),
+                "==" => from_bool(lhs == rhs),
+                "!=" => from_bool(lhs != rhs),
+                "&&" => from_bool(as_bool(lhs) && as_bool(rhs)),
+                "||" => from_bool(as_bool(lhs) || as_bool(rhs)),
+                _ => return Err(self.error(&format!("unknown operator '{}'", op))),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some(Token::Operator("-")) => {
+                self.position += 1;
+                Ok(-self.parse_expression(binding_power("*"))?)
+            }
+            Some(Token::Operator("!")) => {
+                self.position += 1;
+                Ok(from_bool(!as_bool(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<f64, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::LeftParen) => {
+                let value = self.parse_expression(0)?;
+                self.expect(Token::RightParen)?;
+                Ok(value)
+            }
+            Some(Token::Identifier(name)) => {
+                if self.peek() == Some(&Token::LeftParen) {
+                    self.position += 1;
+                    let mut args = vec![];
+                    if self.peek() != Some(&Token::RightParen) {
+                        loop {
+                            args.push(self.parse_expression(0)?);
+                            if self.peek() == Some(&Token::Comma) {
+                                self.position += 1;
+                            } else {
+                                break;
+                            }
+                        }
+                    }
+                    self.expect(Token::RightParen)?;
+                    return self.call(&name, &args);
+                }
+                match name.as_str() {
+                    "true" => Ok(1.0),
+                    "false" => Ok(0.0),
+                    _ => self
+                        .variables
+                        .get(&name)
+                        .copied()
+                        .ok_or_else(|| self.error(&format!("unknown variable '{}'", name))),
+                }
+            }
+            _ => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn call(&self, name: &str, args: &[f64]) -> Result<f64, String> {
+        let arity = match name {
+            "if" => 3,
+            "min" | "max" | "pow" => 2,
+            _ => 1,
+        };
+        if args.len() != arity {
+            return Err(self.error(&format!(
+                "function '{}' expects {} arguments, got {}",
+                name,
+                arity,
+                args.len()
+            )));
+        }
+        match name {
+            "if" => Ok(if as_bool(args[0]) { args[1] } else { args[2] }),
+            "min" => Ok(args[0].min(args[1])),
+            "max" => Ok(args[0].max(args[1])),
+            "pow" => Ok(args[0].powf(args[1])),
+            "floor" => Ok(args[0].floor()),
+            "ceil" => Ok(args[0].ceil()),
+            "round" => Ok(args[0].round()),
+            "abs" => Ok(args[0].abs()),
+            "sqrt" => Ok(args[0].sqrt()),
+            "ln" => Ok(args[0].ln()),
+            "log2" => Ok(args[0].log2()),
+            _ => Err(self.error(&format!("unknown function '{}'", name))),
+        }
+    }
+}
+
+pub fn evaluate(expression: &str, variables: &HashMap<String, f64>) -> Result<f64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+        variables,
+        expression,
+    };
+    let value = parser.parse_expression(0)?;
+    if parser.position != parser.tokens.len() {
+        return Err(parser.error("unexpected trailing tokens"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<f64, String> {
+        let variables = HashMap::from([("k".to_string(), 3.0), ("seed".to_string(), 42.0)]);
+        evaluate(expression, &variables)
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3.0));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Ok(512.0));
+        assert_eq!(eval("-2 ^ 2"), Ok(-4.0));
+        assert_eq!(eval("2 * -3"), Ok(-6.0));
+        assert_eq!(eval("(-7) % 3"), Ok(2.0));
+        assert_eq!(eval("1 + 1 == 2 && 3 > 2"), Ok(1.0));
+        assert_eq!(eval("!0 || 0"), Ok(1.0));
+    }
+
+    #[test]
+    fn variables_and_functions() {
+        assert_eq!(eval("seed + 1000 + k"), Ok(1045.0));
+        assert_eq!(eval("250 / (k * k)").map(|v| v * 9.0), Ok(250.0));
+        assert_eq!(eval("if(k > 2, min(k, 10), max(k, 10))"), Ok(3.0));
+        assert_eq!(
+            eval("round(log2(8)) + floor(2.7) + ceil(0.2) + abs(-1)"),
+            Ok(7.0)
+        );
+        assert_eq!(eval("!true"), Ok(0.0));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(eval("unknown + 1")
+            .unwrap_err()
+            .contains("unknown variable 'unknown'"));
+        assert!(eval("min(1)").unwrap_err().contains("expects 2 arguments"));
+        assert!(eval("nope(1)").unwrap_err().contains("unknown function"));
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 2").unwrap_err().contains("trailing"));
+        assert!(eval("1 +").is_err());
+        assert!(eval("1 $ 2").unwrap_err().contains("unexpected character"));
+        assert!(eval("1.2.3").unwrap_err().contains("invalid number"));
+    }
+}
@@ -0,0 +1,418 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    configuration::{Configuration, ShapeEnum},
+    map_pipeline::MapPipeline,
+    pipeline_steps::mask::{MaskSpec, Masked},
+    shapes::map_shape::MapShape,
+};
+
+use super::{expression::evaluate, registry::StepRegistry};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecipeVariable {
+    pub name: String,
+    pub value: Value,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RepeatRange {
+    pub var: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StepSpec {
+    pub step: String,
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecipeEntry {
+    Repeat {
+        repeat: RepeatRange,
+        steps: Vec<RecipeEntry>,
+    },
+    When {
+        when: Value,
+        steps: Vec<RecipeEntry>,
+    },
+    // Every step of the group only changes the map where the mask is set
+    Masked {
+        mask: MaskSpec,
+        steps: Vec<RecipeEntry>,
+    },
+    Step(StepSpec),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecipeFile {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub variables: Vec<RecipeVariable>,
+    pub circunference: Value,
+    pub height: Value,
+    pub steps: Vec<RecipeEntry>,
+}
+
+/// Values that step parameters can refer to by name: the numeric fields of the
+/// generation configuration, the recipe variables and the loop counters.
+#[derive(Clone, Default)]
+pub struct RecipeContext {
+    pub variables: HashMap<String, f64>,
+    pub percentiles: HashMap<String, Vec<(f32, i32)>>,
+    // files given in the configuration, e.g. "sketch"
+    pub files: HashMap<String, String>,
+}
+
+impl RecipeContext {
+    pub fn from_config(config: &Configuration) -> Self {
+        let mut variables = HashMap::new();
+        variables.insert("seed".to_string(), config.seed as f64);
+        variables.insert("height_pixels".to_string(), config.height_pixels as f64);
+        variables.insert("width_pixels".to_string(), config.width_pixels as f64);
+        variables.insert(
+            "number_of_plates".to_string(),
+            config.number_of_plates as f64,
+        );
+        variables.insert(
+            "water_percentage".to_string(),
+            config.water_percentage as f64,
+        );
+        variables.insert(
+            "number_of_rivers".to_string(),
+            config.number_of_rivers as f64,
+        );
+        variables.insert("make_climate".to_string(), config.make_climate as u8 as f64);
+        variables.insert("hotspots".to_string(), config.hotspots as f64);
+        variables.insert(
+            "erosion_iterations".to_string(),
+            config.erosion_iterations as f64,
+        );
+        variables.insert(
+            "supercontinent".to_string(),
+            config.supercontinent as u8 as f64,
+        );
+        variables.insert("islands".to_string(), config.islands as f64);
+        variables.insert(
+            "has_sketch".to_string(),
+            config.sketch.is_some() as u8 as f64,
+        );
+        variables.insert(
+            "disc".to_string(),
+            (config.shape == ShapeEnum::Disc) as u8 as f64,
+        );
+
+        let mut percentiles = HashMap::new();
+        percentiles.insert(
+            "land_height_percentiles".to_string(),
+            config.land_height_percentiles.clone(),
+        );
+        percentiles.insert(
+            "ocean_depth_percentiles".to_string(),
+            config.ocean_depth_percentiles.clone(),
+        );
+        percentiles.insert(
+            "precipitation_percentiles".to_string(),
+            config.precipitation_percentiles.clone(),
+        );
+        let mut files = HashMap::new();
+        if let Some(sketch) = &config.sketch {
+            files.insert("sketch".to_string(), sketch.clone());
+        }
+        Self {
+            variables,
+            percentiles,
+            files,
+        }
+    }
+
+    pub fn number(&self, value: &Value) -> Result<f64, String> {
+        match value {
+            Value::Number(n) => n.as_f64().ok_or_else(|| format!("invalid number {}", n)),
+            Value::Bool(b) => Ok(*b as u8 as f64),
+            Value::String(expression) => evaluate(expression, &self.variables),
+            _ => Err(format!("expected a number or an expression, got {}", value)),
+        }
+    }
+}
+
+/// Parameters of one step of a recipe, evaluated against the recipe context.
+pub struct StepParams<'a> {
+    pub spec: &'a StepSpec,
+    pub context: &'a RecipeContext,
+}
+
+impl<'a> StepParams<'a> {
+    fn get(&self, key: &str) -> Result<&Value, String> {
+        self.spec
+            .params
+            .get(key)
+            .ok_or_else(|| format!("step {} is missing parameter '{}'", self.spec.step, key))
+    }
+
+    fn wrap_error<T>(&self, key: &str, result: Result<T, String>) -> Result<T, String> {
+        result.map_err(|e| format!("step {}, parameter '{}': {}", self.spec.step, key, e))
+    }
+
+    pub fn has(&self, key: &str) -> bool {
+        self.spec.params.contains_key(key)
+    }
+
+    pub fn f32(&self, key: &str) -> Result<f32, String> {
+        let value = self.get(key)?;
+        self.wrap_error(key, self.context.number(value))
+            .map(|v| v as f32)
+    }
+
+    pub fn f32_or(&self, key: &str, default: f32) -> Result<f32, String> {
+        if self.has(key) {
+            self.f32(key)
+        } else {
+            Ok(default)
+        }
+    }
+
+    pub fn u32(&self, key: &str) -> Result<u32, String> {
+        let value = self.get(key)?;
+        // seeds may overflow the u32 range, wrap them the same way for every platform
+        self.wrap_error(key, self.context.number(value))
+            .map(|v| v.max(0.0) as u64 as u32)
+    }
+
+    pub fn usize(&self, key: &str) -> Result<usize, String> {
+        let value = self.get(key)?;
+        self.wrap_error(key, self.context.number(value))
+            .map(|v| v.max(0.0) as usize)
+    }
+
+    pub fn usize_or(&self, key: &str, default: usize) -> Result<usize, String> {
+        if self.has(key) {
+            self.usize(key)
+        } else {
+            Ok(default)
+        }
+    }
+
+    pub fn string(&self, key: &str) -> Result<String, String> {
+        match self.get(key)? {
+            Value::String(s) => Ok(s.clone()),
+            value => Err(format!(
+                "step {}, parameter '{}': expected a string, got {}",
+                self.spec.step, key, value
+            )),
+        }
+    }
+
+    /// A file path, or the name of a file of the configuration.
+    pub fn file(&self, key: &str) -> Result<String, String> {
+        let name = self.string(key)?;
+        Ok(self.context.files.get(&name).cloned().unwrap_or(name))
+    }
+
+    /// The layer a step runs on instead of the height, from the optional parameter "layer".
+    pub fn layer(&self) -> Result<Option<String>, String> {
+        if !self.has("layer") {
+            return Ok(None);
+        }
+        let layer = self.string("layer")?;
+        Ok((layer != "height").then_some(layer))
+    }
+
+    pub fn object<T: DeserializeOwned>(&self, key: &str) -> Result<T, String> {
+        let value = self.get(key)?;
+        self.wrap_error(
+            key,
+            serde_json::from_value(value.clone()).map_err(|e| e.to_string()),
+        )
+    }
+
+    pub fn percentiles(&self, key: &str) -> Result<Vec<(f32, i32)>, String> {
+        let value = self.get(key)?;
+        let result = match value {
+            Value::String(name) => self
+                .context
+                .percentiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("unknown percentile list '{}'", name)),
+            Value::Array(points) => points
+                .iter()
+                .map(|point| match point {
+                    Value::Array(pair) if pair.len() == 2 => Ok((
+                        self.context.number(&pair[0])? as f32,
+                        self.context.number(&pair[1])? as i32,
+                    )),
+                    _ => Err(format!(
+                        "expected a [percentile, value] pair, got {}",
+                        point
+                    )),
+                })
+                .collect(),
+            _ => Err(format!("expected a list of percentiles, got {}", value)),
+        };
+        self.wrap_error(key, result)
+    }
+}
+
+impl RecipeFile {
+    pub fn from_json(data: &str) -> Result<Self, String> {
+        serde_json::from_str(data).map_err(|e| format!("invalid recipe: {}", e))
+    }
+
+    pub fn from_toml(data: &str) -> Result<Self, String> {
+        toml::from_str(data).map_err(|e| format!("invalid recipe: {}", e))
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("could not read recipe {}: {}", path, e))?;
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&data),
+            _ => Self::from_json(&data),
+        }
+    }
+
+    pub fn build<S: MapShape + 'static>(
+        &self,
+        config: &Configuration,
+    ) -> Result<MapPipeline<S>, String> {
+        self.build_with_registry(
+            &RecipeContext::from_config(config),
+            &StepRegistry::default(),
+        )
+    }
+
+    pub fn build_with_registry<S: MapShape + 'static>(
+        &self,
+        context: &RecipeContext,
+        registry: &StepRegistry<S>,
+    ) -> Result<MapPipeline<S>, String> {
+        let mut map_pipeline = MapPipeline::new();
+        self.add_to(&mut map_pipeline, context, registry)?;
+        Ok(map_pipeline)
+    }
+
+    /// Sets the size of a pipeline and adds the steps of the recipe after the ones it has.
+    pub fn add_to<S: MapShape + 'static>(
+        &self,
+        map_pipeline: &mut MapPipeline<S>,
+        context: &RecipeContext,
+        registry: &StepRegistry<S>,
+    ) -> Result<(), String> {
+        let mut context = context.clone();
+        for variable in &self.variables {
+            let value = context
+                .number(&variable.value)
+                .map_err(|e| format!("variable '{}': {}", variable.name, e))?;
+            context.variables.insert(variable.name.clone(), value);
+        }
+
+        map_pipeline.circunference = context.number(&self.circunference)?.max(1.0) as usize;
+        map_pipeline.height = context.number(&self.height)?.max(1.0) as usize;
+        add_entries(map_pipeline, &self.steps, &mut context, registry, None)
+    }
+}
+
+fn add_entries<S: MapShape + 'static>(
+    map_pipeline: &mut MapPipeline<S>,
+    entries: &[RecipeEntry],
+    context: &mut RecipeContext,
+    registry: &StepRegistry<S>,
+    mask: Option<&MaskSpec>,
+) -> Result<(), String> {
+    for entry in entries {
+        match entry {
+            RecipeEntry::Step(spec) => {
+                let step = registry.build(&StepParams { spec, context })?;
+                let key = step_key(spec, context);
+                match mask {
+                    Some(mask) => {
+                        let mask_key = serde_json::to_value(mask).unwrap_or(Value::Null);
+                        map_pipeline.add_keyed_step(
+                            Box::new(Masked::new(step, mask.build().map_err(|e| e.to_string())?)),
+                            format!("{} masked by {}", key, resolve_param(&mask_key, context)),
+                        );
+                    }
+                    None => map_pipeline.add_keyed_step(step, key),
+                }
+            }
+            RecipeEntry::When { when, steps } => {
+                if context.number(when)? != 0.0 {
+                    add_entries(map_pipeline, steps, context, registry, mask)?;
+                }
+            }
+            // an inner mask replaces the outer one
+            RecipeEntry::Masked { mask, steps } => {
+                add_entries(map_pipeline, steps, context, registry, Some(mask))?;
+            }
+            RecipeEntry::Repeat { repeat, steps } => {
+                let from = context.number(&repeat.from)? as i64;
+                let to = context.number(&repeat.to)? as i64;
+                let previous = context.variables.get(&repeat.var).copied();
+                for k in from..to {
+                    context.variables.insert(repeat.var.clone(), k as f64);
+                    add_entries(map_pipeline, steps, context, registry, mask)?;
+                }
+                match previous {
+                    Some(value) => context.variables.insert(repeat.var.clone(), value),
+                    None => context.variables.remove(&repeat.var),
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
+// The step with its parameters evaluated, so that the key only changes when the step
+// does. Files are identified by their path, size and modification time.
+fn step_key(spec: &StepSpec, context: &RecipeContext) -> String {
+    let params: Map<String, Value> = spec
+        .params
+        .iter()
+        .map(|(name, value)| (name.clone(), resolve_param(value, context)))
+        .collect();
+    return format!("{} {}", spec.step, Value::Object(params));
+}
+
+fn resolve_param(value: &Value, context: &RecipeContext) -> Value {
+    match value {
+        Value::String(text) => {
+            if let Ok(number) = context.number(value) {
+                return Value::from(number);
+            }
+            if let Some(percentiles) = context.percentiles.get(text) {
+                return serde_json::to_value(percentiles).unwrap_or(Value::Null);
+            }
+            let text = context.files.get(text).unwrap_or(text);
+            if let Ok(metadata) = fs::metadata(text) {
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or(0, |time| time.as_nanos());
+                return Value::from(format!("{} {} {}", text, metadata.len(), modified));
+            }
+            value.clone()
+        }
+        Value::Array(values) => values
+            .iter()
+            .map(|value| resolve_param(value, context))
+            .collect(),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), resolve_param(value, context)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
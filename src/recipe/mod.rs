@@ -0,0 +1,160 @@
+pub mod expression;
+pub mod format;
+pub mod registry;
+
+use std::sync::Arc;
+
+use crate::{
+    checkpoint::CheckpointCache,
+    complete_map::CompleteMap,
+    configuration::Configuration,
+    error::{MapError, MapResult},
+    map_pipeline::MapPipeline,
+    pipeline_observer::{SnapshotWriter, StatsCollector},
+    pipeline_steps::{
+        define_coastlines::DefineCoastline,
+        load_height::LoadHeight,
+        region::{ExtractRegion, RegionConfig},
+        sketch::{load_sketch, sketch_water_percentage},
+    },
+    shapes::{flat::Flat, map_shape::MapShape},
+};
+
+use self::{
+    format::{RecipeContext, RecipeFile},
+    registry::StepRegistry,
+};
+
+pub const STANDARD_RECIPE: &str = include_str!("../../recipes/standard.json");
+pub const REGION_RECIPE: &str = include_str!("../../recipes/region.json");
+
+// Fails when the configuration gives the recipe invalid values, e.g. a width of 0
+pub fn standard_recipe<T: MapShape + 'static>(config: &Configuration) -> MapResult<MapPipeline<T>> {
+    RecipeFile::from_json(STANDARD_RECIPE)
+        .and_then(|recipe| recipe.build(config))
+        .map_err(MapError::Recipe)
+}
+
+// The standard recipe following a sketch, whose water percentage replaces the one of the
+// configuration
+pub fn sketch_recipe<T: MapShape + 'static>(
+    config: &Configuration,
+    sketch: &str,
+) -> MapResult<MapPipeline<T>> {
+    let config = Configuration {
+        water_percentage: sketch_water_percentage(&load_sketch::<T>(sketch)?),
+        ..config.clone()
+    };
+    RecipeFile::from_json(STANDARD_RECIPE)
+        .and_then(|recipe| recipe.build(&config))
+        .map_err(MapError::Recipe)
+}
+
+pub fn recipe_from_config<T: MapShape + 'static>(
+    config: &Configuration,
+) -> MapResult<MapPipeline<T>> {
+    let mut map_pipeline = match (&config.recipe, &config.sketch) {
+        (Some(path), _) => RecipeFile::from_file(path)
+            .and_then(|recipe| recipe.build(config))
+            .map_err(MapError::Recipe)?,
+        (None, Some(sketch)) => sketch_recipe(config, sketch)?,
+        (None, None) => standard_recipe(config)?,
+    };
+    if let Some(dir) = &config.checkpoint_dir {
+        map_pipeline.set_checkpoints(CheckpointCache::new(dir));
+    }
+    if let Some(dir) = &config.snapshot_dir {
+        map_pipeline.add_observer(SnapshotWriter::new(dir));
+    }
+    if let Some(path) = &config.stats_file {
+        map_pipeline.add_observer(StatsCollector::new(Some(path)));
+    }
+    Ok(map_pipeline)
+}
+
+// Extracts a region of `parent` and adds detail to it with the region recipe, or the recipe
+// of the region configuration. `world_seed` is the seed of the parent, used when the region
+// has none.
+pub fn region_recipe<P: MapShape + 'static>(
+    parent: Arc<CompleteMap<P>>,
+    region: &RegionConfig,
+    world_seed: u32,
+) -> MapResult<MapPipeline<Flat>> {
+    let [width, height] = region.size()?;
+    let recipe = match &region.recipe {
+        Some(path) => RecipeFile::from_file(path),
+        None => RecipeFile::from_json(REGION_RECIPE),
+    }
+    .map_err(MapError::Recipe)?;
+
+    let mut context = RecipeContext::default();
+    for (name, value) in [
+        ("seed", region.seed.unwrap_or(world_seed) as f64),
+        ("width_pixels", width as f64),
+        ("height_pixels", height as f64),
+        ("detail", region.detail as f64),
+        ("erosion_iterations", region.erosion_iterations as f64),
+        ("parent_height_pixels", parent.height.height as f64),
+        (
+            "parent_rows",
+            region.window.parent_rows(parent.height.height) as f64,
+        ),
+    ] {
+        context.variables.insert(name.to_string(), value);
+    }
+
+    let mut map_pipeline = MapPipeline::new();
+    map_pipeline.add_step(ExtractRegion {
+        parent,
+        window: region.window.clone(),
+    });
+    recipe
+        .add_to(&mut map_pipeline, &context, &StepRegistry::default())
+        .map_err(MapError::Recipe)?;
+    Ok(map_pipeline)
+}
+
+pub fn recipe_from_image<T: MapShape + 'static>(load_height: LoadHeight) -> MapPipeline<T> {
+    let mut map_pipeline: MapPipeline<T> = MapPipeline::new();
+    map_pipeline.add_step(load_height);
+
+    map_pipeline.add_step(DefineCoastline {});
+
+    map_pipeline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::globe::Globe;
+
+    fn test_config(seed: u32) -> Configuration {
+        let config = format!(
+            r#"{{"shape": "Globe", "seed": {}, "height_pixels": 50, "width_pixels": 100,
+            "number_of_plates": 12, "water_percentage": 65.0,
+            "land_height_percentiles": [[0.0, 0], [100.0, 5000]],
+            "ocean_depth_percentiles": [[0.0, -5000], [100.0, 0]],
+            "precipitation_percentiles": [], "number_of_rivers": 0, "make_climate": false,
+            "hotspots": 0, "erosion_iterations": 1, "supercontinent": false, "islands": 0}}"#,
+            seed
+        );
+        return serde_json::from_str(&config).unwrap();
+    }
+
+    fn generate(config: &Configuration) -> Vec<u8> {
+        let mut cmap = standard_recipe::<Globe>(config).unwrap().execute().unwrap();
+        // the order of a HashSet changes between runs
+        let mut coastline: Vec<[usize; 2]> = cmap.coastline.take().unwrap().into_iter().collect();
+        coastline.sort();
+        let mut bytes = bincode::serialize(&cmap).unwrap();
+        bytes.extend(bincode::serialize(&coastline).unwrap());
+        return bytes;
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_map() {
+        let map = generate(&test_config(7));
+        assert_eq!(generate(&test_config(7)), map);
+        assert_ne!(generate(&test_config(8)), map);
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use crate::{
+    pipeline_steps::{
+        adjust_percentiles::{
+            AdjustHeightPercentiles, AdjustLandHeightPercentiles, AdjustOceanDepthPercentiles,
+            AdjustPrecipitationPercentiles,
+        },
+        annual_precipitation::CalculateAnnualPrecipitation,
+        brush::Brush,
+        calculate_climate::CalculateClimate,
+        climate::DefineKoppenClimate,
+        continentality::CalculateContinentality,
+        define_coastlines::DefineCoastline,
+        height_in_plates::HeightInPlates,
+        height_noise::HeightNoise,
+        height_noise_mult::HeightNoiseMult,
+        height_noise_poles::HeightNoisePoles,
+        hotspots::Hotspots,
+        hydraulic_erosion::HydraulicErosion,
+        ice::Ice,
+        layer_step::{ElementStep, LayerValue, OnLayer},
+        load_custom_layer::LoadCustomLayer,
+        load_height::LoadHeight,
+        mountains::AddMountains,
+        noisy_voronoi::NoisyVoronoi,
+        noisy_voronoi_supercontinent::NoisyVoronoiSupercontinent,
+        ocean_rim::OceanRim,
+        pipeline_step::PipelineStep,
+        plate_gap::AddPlateGap,
+        precipitation::CalculatePrecipitation,
+        region::{KeepParentCoast, PARENT_COAST_LAYER},
+        resize::{Resize, ResizeTo},
+        rivers::CreateRivers,
+        sketch::{LoadSketch, SketchHeight, SketchLowlands},
+        smooth::{Smooth, SmoothOcean},
+        supercontinent_height_noise::SupercontinentHeightNoise,
+        tectonic_edges::DefineTecEdges,
+        temperature_from_continentality::TemperatureFromContinentality,
+        translation_noise::TranslationNoise,
+        vegetation::Vegetation,
+        water_level::{AddHeight, WaterLevel},
+    },
+    shapes::map_shape::MapShape,
+};
+
+use super::format::StepParams;
+
+pub type StepBuilder<S> = fn(&StepParams) -> Result<Box<dyn PipelineStep<S>>, String>;
+
+/// Maps the step names used in recipe files to functions building the steps.
+pub struct StepRegistry<S: MapShape> {
+    builders: HashMap<String, StepBuilder<S>>,
+}
+
+impl<S: MapShape + 'static> StepRegistry<S> {
+    pub fn empty() -> Self {
+        Self {
+            builders: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, name: &str, builder: StepBuilder<S>) {
+        self.builders.insert(name.to_string(), builder);
+    }
+
+    pub fn build(&self, params: &StepParams) -> Result<Box<dyn PipelineStep<S>>, String> {
+        let Some(builder) = self.builders.get(&params.spec.step) else {
+            return Err(format!("unknown step '{}'", params.spec.step));
+        };
+        builder(params)
+    }
+}
+
+impl<S: MapShape + 'static> Default for StepRegistry<S> {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+
+        // tectonics and base height
+        registry.register("NoisyVoronoi", |p| {
+            let mut step = NoisyVoronoi::new(p.u32("seed")?, p.usize("n_regions")?);
+            if p.has("sketch_layer") {
+                step.sketch = Some(p.string("sketch_layer")?);
+            }
+            Ok(Box::new(step))
+        });
+        registry.register("NoisyVoronoiSupercontinent", |p| {
+            Ok(Box::new(NoisyVoronoiSupercontinent::new(
+                p.u32("seed")?,
+                p.usize("n_regions")?,
+            )))
+        });
+        registry.register("HeightInPlates", |p| {
+            let step =
+                HeightInPlates::new(p.u32("seed")?, p.f32("frequency")?, p.f32("intensity")?);
+            integer_step(p, step)
+        });
+        registry.register("SupercontinentHeightNoise", |p| {
+            let step = SupercontinentHeightNoise::new(
+                p.u32("seed")?,
+                p.f32("frequency")?,
+                p.f32("intensity")?,
+            );
+            integer_step(p, step)
+        });
+        registry.register("AddPlateGap", |p| {
+            let mut step = AddPlateGap::new();
+            step.noise_intensity = p.f32_or("noise_intensity", step.noise_intensity)?;
+            step.noise_frequency = p.f32_or("noise_frequency", step.noise_frequency)?;
+            step.distance = p.usize_or("distance", step.distance)?;
+            step.oceanic_plates = p.usize_or("oceanic_plates", step.oceanic_plates)?;
+            Ok(Box::new(step))
+        });
+        registry.register("DefineTecEdges", |_| Ok(Box::new(DefineTecEdges::new())));
+        registry.register("AddMountains", |p| {
+            let step = AddMountains::new(p.u32("seed")?, p.f32("frequency")?, p.f32("intensity")?);
+            integer_step(p, step)
+        });
+        registry.register("Hotspots", |p| {
+            Ok(Box::new(Hotspots::new(
+                p.u32("seed")?,
+                p.usize("n_regions")?,
+            )))
+        });
+
+        // height adjustments
+        registry.register("WaterLevel", |p| {
+            let mut step = WaterLevel::new();
+            step.percentage = p.f32_or("percentage", step.percentage)?;
+            Ok(Box::new(step))
+        });
+        registry.register("AddHeight", |p| {
+            numeric_step(p, AddHeight::new(p.f32("value")? as i32))
+        });
+        registry.register("AdjustHeightPercentiles", |p| {
+            let mut step = AdjustHeightPercentiles::new(p.percentiles("percentiles")?);
+            step.layer = integer_layer(p)?;
+            Ok(Box::new(step))
+        });
+        registry.register("AdjustLandHeightPercentiles", |p| {
+            let mut step = AdjustLandHeightPercentiles::new(
+                &p.percentiles("percentiles")?,
+                p.f32("water_percentage")?,
+            );
+            step.layer = integer_layer(p)?;
+            Ok(Box::new(step))
+        });
+        registry.register("AdjustOceanDepthPercentiles", |p| {
+            let mut step = AdjustOceanDepthPercentiles::new(
+                &p.percentiles("percentiles")?,
+                p.f32("water_percentage")?,
+            );
+            step.layer = integer_layer(p)?;
+            Ok(Box::new(step))
+        });
+        registry.register("HeightNoise", |p| {
+            let step = HeightNoise::new(p.u32("seed")?, p.f32("frequency")?, p.f32("intensity")?);
+            numeric_step(p, step)
+        });
+        registry.register("HeightNoiseMult", |p| {
+            let step =
+                HeightNoiseMult::new(p.u32("seed")?, p.f32("frequency")?, p.f32("intensity")?);
+            numeric_step(p, step)
+        });
+        registry.register("HeightNoisePoles", |p| {
+            let step =
+                HeightNoisePoles::new(p.u32("seed")?, p.f32("frequency")?, p.f32("intensity")?);
+            numeric_step(p, step)
+        });
+        registry.register("TranslationNoise", |p| {
+            any_layer_step(p, TranslationNoise::new(p.u32("seed")?))
+        });
+        registry.register("HydraulicErosion", |p| {
+            Ok(Box::new(HydraulicErosion::new(p.u32("iterations")?)))
+        });
+        registry.register("Smooth", |p| {
+            let mut step = Smooth::new();
+            step.pixel_distance = p.usize_or("pixel_distance", step.pixel_distance)?;
+            numeric_step(p, step)
+        });
+        registry.register("SmoothOcean", |p| {
+            let mut step = SmoothOcean::new();
+            step.pixel_distance = p.usize_or("pixel_distance", step.pixel_distance)?;
+            integer_step(p, step)
+        });
+        registry.register("OceanRim", |p| {
+            let mut step = OceanRim::new();
+            step.width = p.f32_or("width", step.width)?;
+            step.depth = p.f32_or("depth", step.depth as f32)? as i32;
+            integer_step(p, step)
+        });
+        registry.register("Brush", |p| {
+            Ok(Box::new(Brush {
+                area: p.object("area")?,
+                operation: p.object("operation")?,
+                falloff: p.f32_or("falloff", 0.0)?,
+            }))
+        });
+        registry.register("Resize", |p| {
+            Ok(Box::new(Resize {
+                factor: p.f32("factor")?,
+            }))
+        });
+        registry.register("ResizeTo", |p| {
+            Ok(Box::new(ResizeTo {
+                circunference: p.usize("circunference")?,
+                height: p.usize("height")?,
+            }))
+        });
+        registry.register("DefineCoastline", |_| Ok(Box::new(DefineCoastline {})));
+        registry.register("KeepParentCoast", |p| {
+            let layer = if p.has("layer") {
+                p.string("layer")?
+            } else {
+                PARENT_COAST_LAYER.to_string()
+            };
+            Ok(Box::new(KeepParentCoast { layer }))
+        });
+
+        // climate
+        registry.register("CalculateClimate", |p| {
+            Ok(Box::new(CalculateClimate::new(
+                p.f32("equator_temperature")?,
+                p.f32("pole_temperature")?,
+                p.f32("humidity")?,
+            )))
+        });
+        registry.register("CalculateContinentality", |_| {
+            Ok(Box::new(CalculateContinentality {}))
+        });
+        registry.register("TemperatureFromContinentality", |p| {
+            Ok(Box::new(TemperatureFromContinentality::new(
+                p.f32("equator_temperature")?,
+                p.f32("pole_temperature")?,
+            )))
+        });
+        registry.register("CalculatePrecipitation", |p| {
+            Ok(Box::new(CalculatePrecipitation::new(p.f32("humidity")?)))
+        });
+        registry.register("AdjustPrecipitationPercentiles", |p| {
+            Ok(Box::new(AdjustPrecipitationPercentiles::new(
+                &p.percentiles("percentiles")?,
+            )))
+        });
+        registry.register("CalculateAnnualPrecipitation", |_| {
+            Ok(Box::new(CalculateAnnualPrecipitation {}))
+        });
+        registry.register("DefineKoppenClimate", |_| {
+            Ok(Box::new(DefineKoppenClimate {}))
+        });
+        registry.register("Vegetation", |_| Ok(Box::new(Vegetation::new())));
+        registry.register("CreateRivers", |_| Ok(Box::new(CreateRivers {})));
+        registry.register("Ice", |_| Ok(Box::new(Ice {})));
+
+        // external data
+        registry.register("LoadHeight", |p| {
+            let mut step = LoadHeight::new(p.string("file")?);
+            if p.has("mapping") {
+                step.mapping = Some(p.object("mapping")?);
+            }
+            if p.has("raw_width") {
+                step.raw_width = Some(p.usize("raw_width")?);
+            }
+            Ok(Box::new(step))
+        });
+        registry.register("LoadCustomLayer", |p| {
+            Ok(Box::new(LoadCustomLayer::new(p.string("file")?)))
+        });
+        registry.register("LoadSketch", |p| {
+            let mut step = LoadSketch::new(p.file("file")?);
+            step.layer = sketch_layer(p, step.layer)?;
+            Ok(Box::new(step))
+        });
+        registry.register("SketchLowlands", |p| {
+            let mut step = SketchLowlands::new(p.f32("factor")?);
+            step.layer = sketch_layer(p, step.layer)?;
+            step.blur = p.usize_or("blur", step.blur)?;
+            Ok(Box::new(step))
+        });
+        registry.register("SketchHeight", |p| {
+            let mut step = SketchHeight::new(p.f32("intensity")?);
+            step.layer = sketch_layer(p, step.layer)?;
+            step.blur = p.usize_or("blur", step.blur)?;
+            Ok(Box::new(step))
+        });
+
+        registry
+    }
+}
+
+// The custom layer of a sketch step, from the optional parameter "sketch_layer"
+fn sketch_layer(p: &StepParams, default: String) -> Result<String, String> {
+    if p.has("sketch_layer") {
+        p.string("sketch_layer")
+    } else {
+        Ok(default)
+    }
+}
+
+fn unsupported_layer(p: &StepParams, layer: &str) -> String {
+    format!("step {} cannot change layer '{}'", p.spec.step, layer)
+}
+
+// The layer of a step working on integer values, "height" by default
+fn integer_layer(p: &StepParams) -> Result<String, String> {
+    match p.layer()? {
+        None => Ok("height".to_string()),
+        Some(layer) if i32::is_layer(&layer) => Ok(layer),
+        Some(layer) => Err(unsupported_layer(p, &layer)),
+    }
+}
+
+// Element steps run on the height unless the recipe names another layer of a type they
+// support: integers, numbers or any value.
+fn integer_step<S, E>(p: &StepParams, step: E) -> Result<Box<dyn PipelineStep<S>>, String>
+where
+    S: MapShape + 'static,
+    E: ElementStep<S, i32> + PipelineStep<S> + 'static,
+{
+    match p.layer()? {
+        None => Ok(Box::new(step)),
+        Some(layer) if i32::is_layer(&layer) => Ok(Box::new(OnLayer::<E, i32>::new(step, &layer))),
+        Some(layer) => Err(unsupported_layer(p, &layer)),
+    }
+}
+
+fn numeric_step<S, E>(p: &StepParams, step: E) -> Result<Box<dyn PipelineStep<S>>, String>
+where
+    S: MapShape + 'static,
+    E: ElementStep<S, i32> + ElementStep<S, f32> + PipelineStep<S> + 'static,
+{
+    match p.layer()? {
+        Some(layer) if f32::is_layer(&layer) => Ok(Box::new(OnLayer::<E, f32>::new(step, &layer))),
+        _ => integer_step(p, step),
+    }
+}
+
+fn any_layer_step<S, E>(p: &StepParams, step: E) -> Result<Box<dyn PipelineStep<S>>, String>
+where
+    S: MapShape + 'static,
+    E: ElementStep<S, i32>
+        + ElementStep<S, f32>
+        + ElementStep<S, usize>
+        + PipelineStep<S>
+        + 'static,
+{
+    match p.layer()? {
+        Some(layer) if usize::is_layer(&layer) => {
+            Ok(Box::new(OnLayer::<E, usize>::new(step, &layer)))
+        }
+        _ => numeric_step(p, step),
+    }
+}
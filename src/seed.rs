@@ -0,0 +1,39 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+// Random number generator used for everything that ends in a map. Its output only
+// depends on the seed, on every platform and version of rand, unlike thread_rng or StdRng.
+pub type SeededRng = ChaCha8Rng;
+
+pub fn seeded_rng(seed: u64) -> SeededRng {
+    return ChaCha8Rng::seed_from_u64(seed);
+}
+
+// splitmix64, a fixed function unlike the std hashers, which may change between releases.
+pub fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    return z ^ (z >> 31);
+}
+
+// Seed of an independent stream, e.g. the n-th edit of a world generated from `seed`.
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    return mix(mix(seed) ^ stream);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    // Maps must not change with the platform or the version of a dependency
+    #[test]
+    fn seeds_are_fixed() {
+        assert_eq!(mix(0), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(derive_seed(42, 0), 6332618229526065668);
+        assert_eq!(derive_seed(42, 1), 17532488217563185893);
+        assert_eq!(seeded_rng(7).gen::<u64>(), 2910824217569608635);
+    }
+}
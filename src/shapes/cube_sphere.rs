@@ -0,0 +1,276 @@
+use std::f32::consts::FRAC_PI_4;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::partial_map::PartialMap;
+use crate::seed::SeededRng;
+
+use crate::shapes::globe::Globe;
+use crate::shapes::map_shape::MapShape;
+
+// A sphere divided as the six faces of a cube, each one a grid of n x n cells stored as n
+// rows of the values, one face after the other. Cells are at equal angles from the center
+// of their face, so that their areas differ by less than 30% and there are no poles where
+// they get smaller.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct CubeSphere;
+
+// Center of each face, and the directions of its columns and rows. The four first faces
+// are around the equator with their rows going north, then the north and south faces.
+const FACES: [[[f32; 3]; 3]; 6] = [
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    [[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+    [[-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0]],
+    [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+    [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]],
+    [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+];
+
+// Angles past the edge of a face reached by neighbours of its cells are kept below 90°,
+// where the plane of the face no longer meets the sphere
+const MAX_ANGLE: f32 = 1.5;
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    return a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+}
+
+fn face_size<T>(values: &[Vec<T>]) -> usize {
+    return (values.len() / 6).max(1);
+}
+
+// Point of the sphere at a row and column of a face, in cells, which may be outside it
+fn face_point(face: usize, [row, column]: [f32; 2], n: usize) -> [f32; 3] {
+    let [center, u, v] = FACES[face];
+    let angle =
+        |cells: f32| ((cells / n as f32 * 2.0 - 1.0) * FRAC_PI_4).clamp(-MAX_ANGLE, MAX_ANGLE);
+    let (tan_u, tan_v) = (angle(column).tan(), angle(row).tan());
+    let point = [0, 1, 2].map(|k| center[k] + tan_u * u[k] + tan_v * v[k]);
+    let length = dot(point, point).sqrt();
+    return point.map(|k| k / length);
+}
+
+// Cell of the values containing a point of the sphere
+fn cell_at_point(point: [f32; 3], n: usize) -> [usize; 2] {
+    let mut face = 0;
+    for (f, [center, _, _]) in FACES.iter().enumerate() {
+        if dot(point, *center) > dot(point, FACES[face][0]) {
+            face = f;
+        }
+    }
+    let [center, u, v] = FACES[face];
+    let distance = dot(point, center);
+    let cell = |direction: [f32; 3]| {
+        let fraction = ((dot(point, direction) / distance).atan() / FRAC_PI_4 + 1.0) / 2.0;
+        return ((fraction * n as f32).max(0.0) as usize).min(n - 1);
+    };
+    return [face * n + cell(v), cell(u)];
+}
+
+// Neighbour of a cell, which is on a neighbouring face past the edges of its face
+fn neighbour_coords(n: usize, [x, y]: [usize; 2], [dx, dy]: [i32; 2]) -> [usize; 2] {
+    let face = x / n;
+    let row = (x % n) as i32 + dx;
+    let column = y as i32 + dy;
+    if (0..n as i32).contains(&row) && (0..n as i32).contains(&column) {
+        return [face * n + row as usize, column as usize];
+    }
+    let point = face_point(face, [row as f32 + 0.5, column as f32 + 0.5], n);
+    return cell_at_point(point, n);
+}
+
+impl MapShape for CubeSphere {
+    const LAT_LON_GRID: bool = false;
+    const SPHERE: bool = true;
+
+    fn new() -> Self {
+        Self
+    }
+
+    fn get_distance(&self, p1: &[f32; 2], p2: &[f32; 2]) -> f32 {
+        return Globe.get_distance(p1, p2);
+    }
+
+    fn get_pixel_neighbours<S: MapShape, T: Clone>(
+        &self,
+        p1: [usize; 2],
+        pm: &PartialMap<S, T>,
+        pixel_distance: usize,
+    ) -> Vec<Vec<T>> {
+        return self
+            .get_pixel_neighbours_coords(p1, pm, pixel_distance)
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|[x, y]| pm.values[x][y].clone())
+                    .collect()
+            })
+            .collect();
+    }
+
+    fn get_pixel_neighbours_coords<S: MapShape, T: Clone>(
+        &self,
+        p1: [usize; 2],
+        pm: &PartialMap<S, T>,
+        pixel_distance: usize,
+    ) -> Vec<Vec<[usize; 2]>> {
+        let n = face_size(&pm.values);
+        let distance = pixel_distance as i32;
+        let mut neighbors = vec![];
+        for dx in -distance..=distance {
+            neighbors.push(
+                (-distance..=distance)
+                    .map(|dy| neighbour_coords(n, p1, [dx, dy]))
+                    .collect(),
+            );
+        }
+        return neighbors;
+    }
+
+    fn get_random_point<S: MapShape, T: Clone>(
+        pm: &PartialMap<S, T>,
+        rng: &mut SeededRng,
+    ) -> [usize; 2] {
+        let x = rng.gen_range(0..pm.values.len());
+        let y = rng.gen_range(0..pm.values[x].len());
+        return [x, y];
+    }
+
+    // Cells have nearly the same area, so points are nearly uniform over the sphere
+    fn get_random_points<S: MapShape, T: Clone>(
+        &self,
+        pm: &PartialMap<S, T>,
+        n_points: usize,
+        rng: &mut SeededRng,
+    ) -> Vec<[usize; 2]> {
+        return (0..n_points)
+            .map(|_| Self::get_random_point(pm, rng))
+            .collect();
+    }
+
+    fn new_vec<T: Default + Clone>(circunference: usize, _height: usize) -> Vec<Vec<T>> {
+        // empty layers stay empty
+        let n = circunference.div_ceil(4);
+        return vec![vec![T::default(); n]; 6 * n];
+    }
+
+    fn convert_to_spatial_coords(&self, latitude: f32, longitude: f32) -> [f32; 3] {
+        return Globe.convert_to_spatial_coords(latitude, longitude);
+    }
+
+    fn convert_to_vec_coords<S: MapShape, T: Clone>(
+        &self,
+        latitude: f32,
+        longitude: f32,
+        pmap: &PartialMap<S, T>,
+    ) -> [usize; 2] {
+        return self.cell_at(&pmap.values, latitude, longitude);
+    }
+
+    // The center of the cell
+    fn cell_coords<T>(&self, values: &[Vec<T>], x: usize, y: usize) -> [f32; 2] {
+        let n = face_size(values);
+        let [px, py, pz] = face_point(x / n, [(x % n) as f32 + 0.5, y as f32 + 0.5], n);
+        return [pz.asin().to_degrees(), py.atan2(px).to_degrees()];
+    }
+
+    fn cell_center<T>(&self, values: &[Vec<T>], x: usize, y: usize) -> [f32; 2] {
+        return self.cell_coords(values, x, y);
+    }
+
+    fn interpolation_cells<T>(
+        &self,
+        values: &[Vec<T>],
+        latitude: f32,
+        longitude: f32,
+    ) -> [([usize; 2], f32); 4] {
+        let n = face_size(values);
+        let point = self.convert_to_spatial_coords(latitude, longitude);
+        let [x, y] = cell_at_point(point, n);
+        let [center, u, v] = FACES[x / n];
+        let distance = dot(point, center);
+        // position in the face, in cells from the center of the first one
+        let cells = |direction: [f32; 3]| {
+            let fraction = ((dot(point, direction) / distance).atan() / FRAC_PI_4 + 1.0) / 2.0;
+            return fraction * n as f32 - 0.5;
+        };
+        let [row, column] = [cells(v), cells(u)];
+        let [dx, dy] = [
+            row.floor() as i32 - (x % n) as i32,
+            column.floor() as i32 - y as i32,
+        ];
+        let [tx, ty] = [row - row.floor(), column - column.floor()];
+        return [
+            (
+                neighbour_coords(n, [x, y], [dx, dy]),
+                (1.0 - tx) * (1.0 - ty),
+            ),
+            (neighbour_coords(n, [x, y], [dx, dy + 1]), (1.0 - tx) * ty),
+            (neighbour_coords(n, [x, y], [dx + 1, dy]), tx * (1.0 - ty)),
+            (neighbour_coords(n, [x, y], [dx + 1, dy + 1]), tx * ty),
+        ];
+    }
+
+    fn cell_at<T>(&self, values: &[Vec<T>], latitude: f32, longitude: f32) -> [usize; 2] {
+        let point = self.convert_to_spatial_coords(latitude, longitude);
+        return cell_at_point(point, face_size(values));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: usize = 6;
+
+    fn cells() -> Vec<[usize; 2]> {
+        return (0..6 * N)
+            .flat_map(|x| (0..N).map(move |y| [x, y]))
+            .collect();
+    }
+
+    #[test]
+    fn cells_contain_their_centers() {
+        let values = CubeSphere::new_vec::<u8>(4 * N, 0);
+        assert_eq!(values.len(), 6 * N);
+        for [x, y] in cells() {
+            let [latitude, longitude] = CubeSphere.cell_coords(&values, x, y);
+            assert_eq!(CubeSphere.cell_at(&values, latitude, longitude), [x, y]);
+        }
+    }
+
+    #[test]
+    fn neighbours_cross_face_edges() {
+        let values = CubeSphere::new_vec::<u8>(4 * N, 0);
+        let cell_width = Globe.get_distance(&[0.0, 0.0], &[90.0 / N as f32, 0.0]);
+        for [x, y] in cells() {
+            let center = CubeSphere.cell_coords(&values, x, y);
+            for step in [[-1, 0], [1, 0], [0, -1], [0, 1]] {
+                let [nx, ny] = neighbour_coords(N, [x, y], step);
+                assert_ne!([nx, ny], [x, y]);
+                let neighbour = CubeSphere.cell_coords(&values, nx, ny);
+                assert!(CubeSphere.get_distance(&center, &neighbour) < 1.5 * cell_width);
+                // the cell is a neighbour of its neighbours, whichever face they are on
+                let back: Vec<[usize; 2]> = [[-1, 0], [1, 0], [0, -1], [0, 1]]
+                    .iter()
+                    .map(|&step| neighbour_coords(N, [nx, ny], step))
+                    .collect();
+                assert!(back.contains(&[x, y]));
+            }
+        }
+        // the first row of the first face borders another face
+        assert_ne!(neighbour_coords(N, [0, 2], [-1, 0])[0] / N, 0);
+    }
+
+    #[test]
+    fn interpolation_weights_add_up_to_one() {
+        let values = CubeSphere::new_vec::<u8>(4 * N, 0);
+        for [latitude, longitude] in [[0.0, 0.0], [45.0, 45.0], [-89.0, 10.0], [30.0, 179.0]] {
+            let cells = CubeSphere.interpolation_cells(&values, latitude, longitude);
+            let total: f32 = cells.iter().map(|(_, weight)| weight).sum();
+            assert!((total - 1.0).abs() < 1e-5);
+            let closest = CubeSphere.cell_at(&values, latitude, longitude);
+            assert!(cells.iter().any(|(cell, _)| *cell == closest));
+        }
+    }
+}
@@ -4,6 +4,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::partial_map::PartialMap;
+use crate::seed::SeededRng;
 
 use crate::shapes::map_shape::MapShape;
 
@@ -86,8 +87,10 @@ impl MapShape for Cylinder {
         return neighbors;
     }
 
-    fn get_random_point<S: MapShape, T: Clone>(pm: PartialMap<S, T>) -> [usize; 2] {
-        let mut rng = rand::thread_rng();
+    fn get_random_point<S: MapShape, T: Clone>(
+        pm: &PartialMap<S, T>,
+        rng: &mut SeededRng,
+    ) -> [usize; 2] {
         let x = rng.gen_range(0..pm.values.len());
         let y = rng.gen_range(0..pm.values[x].len());
         return [x, y];
@@ -97,8 +100,8 @@ impl MapShape for Cylinder {
         &self,
         pm: &PartialMap<S, T>,
         n_points: usize,
+        rng: &mut SeededRng,
     ) -> Vec<[usize; 2]> {
-        let mut rng = rand::thread_rng();
         let mut points = Vec::with_capacity(n_points);
         for _ in 0..n_points {
             let x = rng.gen_range(0..pm.values.len());
@@ -0,0 +1,161 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::partial_map::PartialMap;
+use crate::seed::SeededRng;
+
+use crate::shapes::map_shape::MapShape;
+use crate::shapes::util::pseudo_random_usize;
+
+// A flat world in the shape of a disc, the largest one fitting in the map. The pixels
+// outside of it are kept as ocean by the OceanRim step. Maps as wide as high have round
+// discs and square pixels.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Disc;
+
+// Distance of a point from the center of the disc, 1 at its rim
+pub fn disc_radius(latitude: f32, longitude: f32) -> f32 {
+    return ((latitude / 90.0).powi(2) + (longitude / 180.0).powi(2)).sqrt();
+}
+
+impl MapShape for Disc {
+    const WRAPS_COLUMNS: bool = false;
+
+    fn new() -> Self {
+        Self
+    }
+
+    // In degrees of latitude, a degree of longitude being half as long
+    fn get_distance(
+        &self,
+        [latitude1, longitude1]: &[f32; 2],
+        [latitude2, longitude2]: &[f32; 2],
+    ) -> f32 {
+        let dx = latitude1 - latitude2;
+        let dy = (longitude1 - longitude2) / 2.0;
+
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    fn get_pixel_neighbours<S: MapShape, T: Clone>(
+        &self,
+        p1: [usize; 2],
+        pm: &PartialMap<S, T>,
+        pixel_distance: usize,
+    ) -> Vec<Vec<T>> {
+        return self
+            .get_pixel_neighbours_coords(p1, pm, pixel_distance)
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|[x, y]| pm.values[x][y].clone())
+                    .collect()
+            })
+            .collect();
+    }
+
+    // As in flat maps, pixels past the edges of the map have no neighbours there
+    fn get_pixel_neighbours_coords<S: MapShape, T: Clone>(
+        &self,
+        p1: [usize; 2],
+        pm: &PartialMap<S, T>,
+        pixel_distance: usize,
+    ) -> Vec<Vec<[usize; 2]>> {
+        let mut neighbors = vec![];
+        for x in (p1[0] as i32 - pixel_distance as i32)..=(p1[0] as i32 + pixel_distance as i32) {
+            neighbors.push(vec![]);
+            if x >= pm.values.len() as i32 || x < 0 {
+                continue;
+            }
+            for y in (p1[1] as i32 - pixel_distance as i32)..=(p1[1] as i32 + pixel_distance as i32)
+            {
+                if y >= pm.values[x as usize].len() as i32 || y < 0 {
+                    continue;
+                }
+                let n_len: usize = neighbors.len();
+                neighbors[n_len - 1].push([x as usize, y as usize]);
+            }
+        }
+        return neighbors;
+    }
+
+    // A pixel inside the disc, uniformly over its area
+    fn get_random_point<S: MapShape, T: Clone>(
+        pm: &PartialMap<S, T>,
+        rng: &mut SeededRng,
+    ) -> [usize; 2] {
+        let angle = rng.gen_range(0.0..2.0 * PI);
+        let radius = rng.gen_range(0.0f32..1.0).sqrt();
+        return pm.convert_to_vec_coords(90.0 * radius * angle.sin(), 180.0 * radius * angle.cos());
+    }
+
+    fn get_random_points<S: MapShape, T: Clone>(
+        &self,
+        pm: &PartialMap<S, T>,
+        n_points: usize,
+        rng: &mut SeededRng,
+    ) -> Vec<[usize; 2]> {
+        return (0..n_points)
+            .map(|_| Self::get_random_point(pm, rng))
+            .collect();
+    }
+
+    // Inside the disc, uniformly over its area
+    fn get_random_points_from_seed<S: MapShape, T: Clone>(
+        &self,
+        pm: &PartialMap<S, T>,
+        n_points: usize,
+        seed: u32,
+    ) -> Vec<[usize; 2]> {
+        let mut points = Vec::with_capacity(n_points);
+        for i in 0..n_points {
+            let angle = (pseudo_random_usize(seed + i as u32) % 3600) as f32 * 2.0 * PI / 3600.0;
+            let radius =
+                ((pseudo_random_usize(seed + i as u32 + 1000) % 10000) as f32 / 10000.0).sqrt();
+            points.push(
+                pm.convert_to_vec_coords(90.0 * radius * angle.sin(), 180.0 * radius * angle.cos()),
+            );
+        }
+        return points;
+    }
+
+    fn convert_to_spatial_coords(&self, latitude: f32, longitude: f32) -> [f32; 3] {
+        let x = longitude / 180.0;
+        let y = 0.0;
+        let z = latitude / 90.0;
+        [x, y, z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed::seeded_rng;
+
+    #[test]
+    fn random_points_are_inside_the_disc() {
+        let pm = PartialMap::<Disc, i32>::new(64, 32);
+        // a pixel may stick out of the disc by half its diagonal
+        let max_radius = 1.0 + 1.5 / 32.0;
+        let mut rng = seeded_rng(3);
+        let points = Disc.get_random_points(&pm, 500, &mut rng);
+        let seeded_points = Disc.get_random_points_from_seed(&pm, 500, 3);
+        for [x, y] in points.into_iter().chain(seeded_points) {
+            let [latitude, longitude] = pm.cell_center(x, y);
+            assert!(disc_radius(latitude, longitude) < max_radius);
+        }
+        assert!(disc_radius(90.0, 0.0) == 1.0 && disc_radius(0.0, -180.0) == 1.0);
+    }
+
+    #[test]
+    fn neighbours_stop_at_the_edges() {
+        let pm = PartialMap::<Disc, i32>::new(16, 8);
+        let neighbours = Disc.get_pixel_neighbours_coords([0, 0], &pm, 1);
+        assert_eq!(
+            neighbours,
+            vec![vec![], vec![[0, 0], [0, 1]], vec![[1, 0], [1, 1]]]
+        );
+    }
+}
@@ -2,6 +2,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::partial_map::PartialMap;
+use crate::seed::SeededRng;
 
 use crate::shapes::map_shape::MapShape;
 
@@ -9,6 +10,8 @@ use crate::shapes::map_shape::MapShape;
 pub struct Flat;
 
 impl MapShape for Flat {
+    const WRAPS_COLUMNS: bool = false;
+
     fn new() -> Self {
         Self
     }
@@ -74,8 +77,10 @@ impl MapShape for Flat {
         return neighbors;
     }
 
-    fn get_random_point<S: MapShape, T: Clone>(pm: PartialMap<S, T>) -> [usize; 2] {
-        let mut rng = rand::thread_rng();
+    fn get_random_point<S: MapShape, T: Clone>(
+        pm: &PartialMap<S, T>,
+        rng: &mut SeededRng,
+    ) -> [usize; 2] {
         let x = rng.gen_range(0..pm.values.len());
         let y = rng.gen_range(0..pm.values[x].len());
         return [x, y];
@@ -85,8 +90,8 @@ impl MapShape for Flat {
         &self,
         pm: &PartialMap<S, T>,
         n_points: usize,
+        rng: &mut SeededRng,
     ) -> Vec<[usize; 2]> {
-        let mut rng = rand::thread_rng();
         let mut points = Vec::with_capacity(n_points);
         for _ in 0..n_points {
             let x = rng.gen_range(0..pm.values.len());
@@ -5,6 +5,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::partial_map::PartialMap;
+use crate::seed::SeededRng;
 
 use crate::shapes::map_shape::MapShape;
 use crate::shapes::util::pseudo_random_usize;
@@ -13,6 +14,8 @@ use crate::shapes::util::pseudo_random_usize;
 pub struct Globe;
 
 impl MapShape for Globe {
+    const SPHERE: bool = true;
+
     fn new() -> Self {
         Self
     }
@@ -88,8 +91,10 @@ impl MapShape for Globe {
         return neighbors;
     }
 
-    fn get_random_point<S: MapShape, T: Clone>(pm: PartialMap<S, T>) -> [usize; 2] {
-        let mut rng = rand::thread_rng();
+    fn get_random_point<S: MapShape, T: Clone>(
+        pm: &PartialMap<S, T>,
+        rng: &mut SeededRng,
+    ) -> [usize; 2] {
         let x = rng.gen_range(0..pm.values.len());
         let y = rng.gen_range(0..pm.values[x].len());
         return [x, y];
@@ -99,8 +104,8 @@ impl MapShape for Globe {
         &self,
         pm: &PartialMap<S, T>,
         n_points: usize,
+        rng: &mut SeededRng,
     ) -> Vec<[usize; 2]> {
-        let mut rng = rand::thread_rng();
         let mut points = Vec::with_capacity(n_points);
         for _ in 0..n_points {
             let x = rng.gen_range(0..pm.values.len());
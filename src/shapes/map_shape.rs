@@ -1,10 +1,20 @@
 use serde::Serialize;
 
-use crate::partial_map::PartialMap;
+use crate::{partial_map::PartialMap, seed::SeededRng};
 
 use super::util::pseudo_random_usize;
 
 pub trait MapShape: Sync + Send + Clone + Serialize {
+    // Whether the rows of the values are bands of latitude, from -90 to 90
+    const LAT_LON_GRID: bool = true;
+    // Whether the last row of the values is next to the first one
+    const WRAPS_ROWS: bool = false;
+    // Whether longitude 180 is next to longitude -180
+    const WRAPS_COLUMNS: bool = true;
+    // Whether the map is the surface of a sphere, where the cells of rows of the same length
+    // get smaller towards the poles
+    const SPHERE: bool = false;
+
     fn new() -> Self;
     fn get_distance(&self, p1: &[f32; 2], p2: &[f32; 2]) -> f32;
     fn get_pixel_neighbours<S: MapShape, T: Clone>(
@@ -19,11 +29,15 @@ pub trait MapShape: Sync + Send + Clone + Serialize {
         pm: &PartialMap<S, T>,
         pixel_distance: usize,
     ) -> Vec<Vec<[usize; 2]>>;
-    fn get_random_point<S: MapShape, T: Clone>(pm: PartialMap<S, T>) -> [usize; 2];
+    fn get_random_point<S: MapShape, T: Clone>(
+        pm: &PartialMap<S, T>,
+        rng: &mut SeededRng,
+    ) -> [usize; 2];
     fn get_random_points<S: MapShape, T: Clone>(
         &self,
         pm: &PartialMap<S, T>,
         n_points: usize,
+        rng: &mut SeededRng,
     ) -> Vec<[usize; 2]>;
     fn get_random_points_from_seed<S: MapShape, T: Clone>(
         &self,
@@ -45,6 +59,69 @@ pub trait MapShape: Sync + Send + Clone + Serialize {
     }
     fn convert_to_spatial_coords(&self, latitude: f32, longitude: f32) -> [f32; 3];
 
+    // Latitude and longitude of a cell of a map with these values. On latitude/longitude
+    // grids, the corner of the cell towards latitude -90 and longitude -180.
+    fn cell_coords<T>(&self, values: &[Vec<T>], x: usize, y: usize) -> [f32; 2] {
+        let latitude = x as f32 * 180.0 / values.len() as f32 - 90.0;
+        let longitude = y as f32 * 360.0 / values[x].len() as f32 - 180.0;
+        return [latitude, longitude];
+    }
+
+    // Latitude and longitude of the center of a cell
+    fn cell_center<T>(&self, values: &[Vec<T>], x: usize, y: usize) -> [f32; 2] {
+        let latitude = (x as f32 + 0.5) * 180.0 / values.len() as f32 - 90.0;
+        let longitude = (y as f32 + 0.5) * 360.0 / values[x].len() as f32 - 180.0;
+        return [latitude, longitude];
+    }
+
+    // The four cells closest to a point, with their weights to interpolate between their
+    // values. On latitude/longitude grids, rows wrap around.
+    fn interpolation_cells<T>(
+        &self,
+        values: &[Vec<T>],
+        latitude: f32,
+        longitude: f32,
+    ) -> [([usize; 2], f32); 4] {
+        let row = (latitude + 90.0) / 180.0 * values.len() as f32 - 0.5;
+        let (x0, x1, tx) = if Self::WRAPS_ROWS {
+            let x0 = (row.floor() as i64).rem_euclid(values.len() as i64) as usize;
+            (x0, (x0 + 1) % values.len(), row - row.floor())
+        } else {
+            let row = row.max(0.0);
+            let x0 = (row.floor() as usize).min(values.len() - 1);
+            (
+                x0,
+                (x0 + 1).min(values.len() - 1),
+                (row - x0 as f32).min(1.0),
+            )
+        };
+        let columns = |x: usize| {
+            let n_cols = values[x].len();
+            let column = (longitude + 180.0) / 360.0 * n_cols as f32 - 0.5;
+            let y0 = (column.floor() as i64).rem_euclid(n_cols as i64) as usize;
+            return ([y0, (y0 + 1) % n_cols], column - column.floor());
+        };
+        let ([y00, y01], ty0) = columns(x0);
+        let ([y10, y11], ty1) = columns(x1);
+        return [
+            ([x0, y00], (1.0 - tx) * (1.0 - ty0)),
+            ([x0, y01], (1.0 - tx) * ty0),
+            ([x1, y10], tx * (1.0 - ty1)),
+            ([x1, y11], tx * ty1),
+        ];
+    }
+
+    // Cell of a map with these values containing a point
+    fn cell_at<T>(&self, values: &[Vec<T>], latitude: f32, longitude: f32) -> [usize; 2] {
+        let n_rows = values.len() as f32;
+        let x = ((n_rows * (latitude + 90.0) / 180.0) as usize).min(values.len() - 1);
+        let n_cols = values[x].len() as f32;
+        let y = (n_cols * (longitude + 180.0) / 360.0)
+            .rem_euclid(n_cols)
+            .min(n_cols - 1.0) as usize;
+        return [x, y];
+    }
+
     fn convert_to_vec_coords<S: MapShape, T: Clone>(
         &self,
         latitude: f32,
@@ -1,5 +1,8 @@
+pub mod cube_sphere;
 pub mod cylinder;
+pub mod disc;
 pub mod flat;
 pub mod globe;
 pub mod map_shape;
+pub mod torus;
 mod util;
@@ -0,0 +1,163 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::partial_map::PartialMap;
+use crate::seed::SeededRng;
+
+use crate::shapes::map_shape::MapShape;
+
+// A flat map that wraps around in both directions, for maps tiling seamlessly. Latitudes
+// from -90 to 90 go once around the tube of a torus and longitudes around its hole, so
+// that noise in space is continuous across both edges of the map. The outer side of the
+// torus is three times as long as the inner one, which stretches noise along the rows
+// of the map at latitude 0 compared to latitudes -90 and 90.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Torus;
+
+// Radius of the tube, relative to the distance from the center of the hole to the center
+// of the tube, so that maps twice as wide as high have square pixels on average
+const TUBE_RADIUS: f32 = 0.5;
+
+fn wrap(value: i32, length: usize) -> usize {
+    return value.rem_euclid(length as i32) as usize;
+}
+
+impl MapShape for Torus {
+    const WRAPS_ROWS: bool = true;
+
+    fn new() -> Self {
+        Self
+    }
+
+    fn get_distance(
+        &self,
+        [latitude1, longitude1]: &[f32; 2],
+        [latitude2, longitude2]: &[f32; 2],
+    ) -> f32 {
+        let dx = (latitude1 - latitude2).abs() % 180.0;
+        let dy = (longitude1 - longitude2).abs() % 360.0;
+
+        let dx_wrapped = dx.min(180.0 - dx);
+        let dy_wrapped = dy.min(360.0 - dy);
+
+        (dx_wrapped * dx_wrapped + dy_wrapped * dy_wrapped).sqrt()
+    }
+
+    fn get_pixel_neighbours<S: MapShape, T: Clone>(
+        &self,
+        p1: [usize; 2],
+        pm: &PartialMap<S, T>,
+        pixel_distance: usize,
+    ) -> Vec<Vec<T>> {
+        return self
+            .get_pixel_neighbours_coords(p1, pm, pixel_distance)
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|[x, y]| pm.values[x][y].clone())
+                    .collect()
+            })
+            .collect();
+    }
+
+    fn get_pixel_neighbours_coords<S: MapShape, T: Clone>(
+        &self,
+        p1: [usize; 2],
+        pm: &PartialMap<S, T>,
+        pixel_distance: usize,
+    ) -> Vec<Vec<[usize; 2]>> {
+        let distance = pixel_distance as i32;
+        let mut neighbors = vec![];
+        for dx in -distance..=distance {
+            let x = wrap(p1[0] as i32 + dx, pm.values.len());
+            let row_len = pm.values[x].len();
+            neighbors.push(
+                (-distance..=distance)
+                    .map(|dy| [x, wrap(p1[1] as i32 + dy, row_len)])
+                    .collect(),
+            );
+        }
+        return neighbors;
+    }
+
+    fn get_random_point<S: MapShape, T: Clone>(
+        pm: &PartialMap<S, T>,
+        rng: &mut SeededRng,
+    ) -> [usize; 2] {
+        let x = rng.gen_range(0..pm.values.len());
+        let y = rng.gen_range(0..pm.values[x].len());
+        return [x, y];
+    }
+
+    fn get_random_points<S: MapShape, T: Clone>(
+        &self,
+        pm: &PartialMap<S, T>,
+        n_points: usize,
+        rng: &mut SeededRng,
+    ) -> Vec<[usize; 2]> {
+        return (0..n_points)
+            .map(|_| Self::get_random_point(pm, rng))
+            .collect();
+    }
+
+    // Latitudes past -90 or 90 wrap around as longitudes do
+    fn cell_at<T>(&self, values: &[Vec<T>], latitude: f32, longitude: f32) -> [usize; 2] {
+        let n_rows = values.len() as f32;
+        let x = (n_rows * (latitude + 90.0) / 180.0)
+            .rem_euclid(n_rows)
+            .min(n_rows - 1.0) as usize;
+        let n_cols = values[x].len() as f32;
+        let y = (n_cols * (longitude + 180.0) / 360.0)
+            .rem_euclid(n_cols)
+            .min(n_cols - 1.0) as usize;
+        return [x, y];
+    }
+
+    fn convert_to_spatial_coords(&self, latitude: f32, longitude: f32) -> [f32; 3] {
+        let around_hole = longitude * PI / 180.0;
+        let around_tube = latitude * PI / 90.0;
+        let distance_to_axis = 1.0 + TUBE_RADIUS * around_tube.cos();
+        let x = distance_to_axis * around_hole.cos();
+        let y = distance_to_axis * around_hole.sin();
+        let z = TUBE_RADIUS * around_tube.sin();
+        [x, y, z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_wrap_across_both_edges() {
+        assert_eq!(Torus.get_distance(&[-85.0, 0.0], &[85.0, 0.0]), 10.0);
+        assert_eq!(Torus.get_distance(&[0.0, -175.0], &[0.0, 175.0]), 10.0);
+        assert_eq!(Torus.get_distance(&[0.0, 0.0], &[90.0, 0.0]), 90.0);
+    }
+
+    #[test]
+    fn neighbours_wrap_across_both_edges() {
+        let pm = PartialMap::<Torus, i32>::new(16, 8);
+        let neighbours = Torus.get_pixel_neighbours_coords([0, 0], &pm, 1);
+        assert_eq!(
+            neighbours,
+            vec![
+                vec![[7, 15], [7, 0], [7, 1]],
+                vec![[0, 15], [0, 0], [0, 1]],
+                vec![[1, 15], [1, 0], [1, 1]],
+            ]
+        );
+    }
+
+    #[test]
+    fn cells_wrap_past_the_edges() {
+        let values = vec![vec![0; 16]; 8];
+        assert_eq!(Torus.cell_at(&values, 100.0, 190.0), [0, 0]);
+        assert_eq!(Torus.cell_at(&values, -100.0, -190.0), [7, 15]);
+        assert_eq!(Torus.cell_at(&values, 90.0, 180.0), [0, 0]);
+        assert_eq!(wrap(-1, 8), 7);
+        assert_eq!(wrap(8, 8), 0);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// Identifies a rendered tile. Revisions change whenever the world or the layer set
+// change, so that stale tiles are never served.
+pub struct TileKey<'a> {
+    pub world: &'a str,
+    pub world_revision: u64,
+    pub layerset: &'a str,
+    pub layerset_revision: u64,
+    pub zoom: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+// png tiles stored as <dir>/<world>/<world revision>/<layerset>-<layerset revision>/<z>/<x>/<y>.png
+// At most `max_tiles` tiles are kept for a world.
+pub struct TileCache {
+    dir: PathBuf,
+    max_tiles: usize,
+    // revision and number of the tiles cached for each world
+    tiles: Mutex<HashMap<String, (u64, usize)>>,
+}
+
+impl TileCache {
+    pub fn new(dir: &str, max_tiles: usize) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            max_tiles,
+            tiles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn world_dir(&self, key: &TileKey) -> PathBuf {
+        return self
+            .dir
+            .join(key.world)
+            .join(key.world_revision.to_string());
+    }
+
+    fn tile_path(&self, key: &TileKey) -> PathBuf {
+        return self
+            .world_dir(key)
+            .join(format!("{}-{}", key.layerset, key.layerset_revision))
+            .join(key.zoom.to_string())
+            .join(key.x.to_string())
+            .join(format!("{}.png", key.y));
+    }
+
+    pub fn get(&self, key: &TileKey) -> Option<Vec<u8>> {
+        fs::read(self.tile_path(key)).ok()
+    }
+
+    // The first tile of a new revision of a world removes the tiles of the older ones, and
+    // so does a tile that would go over the limit, for the current revision.
+    pub fn put(&self, key: &TileKey, png: &[u8]) -> io::Result<()> {
+        let mut tiles = self
+            .tiles
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (revision, count) = tiles
+            .entry(key.world.to_string())
+            .or_insert((key.world_revision, 0));
+        let world_dir = self.world_dir(key);
+        if !world_dir.exists() || *revision != key.world_revision || *count >= self.max_tiles {
+            remove_dir_if_exists(&self.dir.join(key.world))?;
+            *revision = key.world_revision;
+            *count = 0;
+        }
+        let path = self.tile_path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // written next to the tile and renamed, so that readers never see a partial file
+        let temporary = path.with_extension(format!(
+            "png.{}.{}",
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temporary, png)?;
+        fs::rename(&temporary, &path)?;
+        *count += 1;
+        Ok(())
+    }
+}
+
+fn remove_dir_if_exists(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// World and layer set names are used as directory names.
+pub fn is_valid_name(name: &str) -> bool {
+    return !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == ',' || c == '.');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(world_revision: u64, x: u32) -> TileKey<'static> {
+        return TileKey {
+            world: "world",
+            world_revision,
+            layerset: "height",
+            layerset_revision: 0,
+            zoom: 2,
+            x,
+            y: 0,
+        };
+    }
+
+    #[test]
+    fn cached_tiles_are_bounded() {
+        let dir = std::env::temp_dir().join(format!("tile_cache_{}", std::process::id()));
+        let cache = TileCache::new(dir.to_str().unwrap(), 2);
+        cache.put(&key(1, 0), b"0").unwrap();
+        cache.put(&key(1, 1), b"1").unwrap();
+        assert_eq!(cache.get(&key(1, 0)), Some(b"0".to_vec()));
+
+        // going over the limit starts over
+        cache.put(&key(1, 2), b"2").unwrap();
+        assert_eq!(cache.get(&key(1, 0)), None);
+        assert_eq!(cache.get(&key(1, 2)), Some(b"2".to_vec()));
+
+        // and so does a new revision
+        cache.put(&key(2, 0), b"0").unwrap();
+        assert_eq!(cache.get(&key(1, 2)), None);
+        assert_eq!(cache.get(&key(2, 0)), Some(b"0".to_vec()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}